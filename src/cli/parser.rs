@@ -22,14 +22,17 @@ use std::fs;
 use std::vec::Vec;
 use std::path::PathBuf;
 use std::sync::{Arc,RwLock};
-use crate::util::io::directory_as_string;
+use crate::util::io::{directory_as_string,expand_home_path};
 use crate::util::yaml::blend_variables;
 use crate::inventory::loading::convert_json_vars;
 use crate::util::io::jet_file_open;
 use crate::util::yaml::show_yaml_error_in_context;
 use crate::cli::version::{GIT_VERSION,GIT_BRANCH,BUILD_TIME};
+use crate::cli::roles::{role_cache_path,check_role_cache};
+use crate::connection::ssh::HostKeyChecking;
+use crate::tasks::request::BecomeMethod;
+use crate::util::terminal::read_secret;
 use std::path::Path;
-use std::io;
 
 // the CLI parser struct values hold various values calculated when calling parse() on
 // the struct
@@ -49,7 +52,9 @@ pub struct CliParser {
     pub show_groups: Vec<String>,
     pub batch_size: Option<usize>,
     pub default_user: String,
+    pub default_user_set: bool,
     pub sudo: Option<String>,
+    pub become_method: Option<String>,
     pub default_port: i64,
     pub threads: usize,
     pub verbosity: u32,
@@ -58,6 +63,16 @@ pub struct CliParser {
     pub extra_vars: serde_yaml::Value,
     pub forward_agent: bool,
     pub login_password: Option<String>,
+    pub sudo_password: Option<String>,
+    pub key_passphrase: Option<String>,
+    pub host_key_checking: HostKeyChecking,
+    pub connect_timeout: u64,
+    pub keepalive_interval: u32,
+    pub command_timeout: u64,
+    pub idle_timeout: u64,
+    pub container_runtime: String,
+    pub requirements_path: PathBuf,
+    pub role_cache_path: Option<PathBuf>,
 }
 
 // subcommands are usually required
@@ -71,6 +86,9 @@ pub const CLI_MODE_SSH: u32 = 4;
 pub const CLI_MODE_CHECK_SSH: u32 = 5;
 pub const CLI_MODE_SHOW: u32 = 6;
 pub const CLI_MODE_SIMULATE: u32 = 7;
+pub const CLI_MODE_CONTAINER: u32 = 8;
+pub const CLI_MODE_CHECK_CONTAINER: u32 = 9;
+pub const CLI_MODE_ROLES_INSTALL: u32 = 10;
 
 fn is_cli_mode_valid(value: &String) -> bool {
     match cli_mode_from_string(value) {
@@ -85,12 +103,24 @@ fn cli_mode_from_string(s: &String) -> Result<u32, String> {
         "check-local"     => Ok(CLI_MODE_CHECK_LOCAL),
         "ssh"             => Ok(CLI_MODE_SSH),
         "check-ssh"       => Ok(CLI_MODE_CHECK_SSH),
+        "container"       => Ok(CLI_MODE_CONTAINER),
+        "check-container" => Ok(CLI_MODE_CHECK_CONTAINER),
         "__simulate"      => Ok(CLI_MODE_SIMULATE),
         "show-inventory" => Ok(CLI_MODE_SHOW),
         _ => Err(format!("invalid mode: {}", s))
     }
 }
 
+// the roles mode takes an action, "jetp roles install", the only one so far
+
+fn roles_mode_from_string(s: Option<&String>) -> Result<u32, String> {
+    return match s.map(|x| x.as_str()) {
+        Some("install") => Ok(CLI_MODE_ROLES_INSTALL),
+        Some(x) => Err(format!("invalid roles action: {}, expecting: jetp roles install", x)),
+        None => Err(String::from("expecting: jetp roles install"))
+    }
+}
+
 // all the supported flags
 const ARGUMENT_VERSION: &str  = "--version";
 const ARGUMENT_INVENTORY: & str = "--inventory";
@@ -108,6 +138,7 @@ const ARGUMENT_PORT: &str = "--port";
 const ARGUMENT_USER: &str = "--user";
 const ARGUMENT_USER_SHORT: &str = "-u";
 const ARGUMENT_SUDO: &str = "--sudo";
+const ARGUMENT_BECOME_METHOD: &str = "--become-method";
 const ARGUMENT_TAGS: &str = "--tags";
 const ARGUMENT_ALLOW_LOCALHOST: &str = "--allow-localhost-delegation";
 const ARGUMENT_FORWARD_AGENT: &str = "--forward-agent";
@@ -119,6 +150,15 @@ const ARGUMENT_VERBOSER: &str = "-vv";
 const ARGUMENT_VERBOSEST: &str = "-vvv";
 const ARGUMENT_EXTRA_VARS: &str = "--extra-vars";
 const ARGUMENT_ASK_LOGIN_PASSWORD: &str = "--ask-login-password";
+const ARGUMENT_ASK_KEY_PASSPHRASE: &str = "--ask-key-passphrase";
+const ARGUMENT_ASK_SUDO_PASSWORD: &str = "--ask-sudo-password";
+const ARGUMENT_HOST_KEY_CHECKING: &str = "--host-key-checking";
+const ARGUMENT_CONNECT_TIMEOUT: &str = "--connect-timeout";
+const ARGUMENT_KEEPALIVE: &str = "--keepalive";
+const ARGUMENT_TIMEOUT: &str = "--timeout";
+const ARGUMENT_IDLE_TIMEOUT: &str = "--idle-timeout";
+const ARGUMENT_CONTAINER_RUNTIME: &str = "--container-runtime";
+const ARGUMENT_REQUIREMENTS: &str = "--requirements";
 
 const ARGUMENT_EXTRA_VARS_SHORT: &str = "-e";
 
@@ -164,6 +204,18 @@ fn show_help() {
                       | | check-ssh | looks for configuration differences over SSH\n\
                       | |\n\
                       | | ssh| manages multiple machines over SSH\n\
+                      | |\n\
+                      | | | hosts with jet_connection: winrm are managed over WinRM instead, which only supports Basic authentication over HTTP\n\
+                      | |\n\
+                      | --- | --- | ---\n\
+                      | container management: |\n\
+                      | | check-container | looks for configuration differences in running containers\n\
+                      | |\n\
+                      | | container | manages running containers with docker or podman exec\n\
+                      | |\n\
+                      | --- | --- | ---\n\
+                      | role management: |\n\
+                      | | roles install | fetches the roles listed in --requirements from git into .jet/roles next to it, or $JET_ROLES_CACHE\n\
                       |-|-";
 
     crate::util::terminal::markdown_print(&String::from(mode_table));
@@ -179,14 +231,26 @@ fn show_help() {
                        | |\n\
                        | | -r, --roles path1:path2| adds additional role search paths. Also uses $JET_ROLES_PATH\n\
                        | |\n\
+                       | | --requirements path | (roles install only) the roles to fetch and where they come from (default: requirements.yml)\n\
+                       | |\n\
                        | --- | ---\n\
                        | SSH options:\n\
+                       | | --ask-key-passphrase | prompt for the passphrase of jet_ssh_private_key_file on standard input\n\
+                       | |\n\
                        | | --ask-login-password | prompt for the login password on standard input\n\
                        | |\n\
                        | | --batch-size N| fully configure this many hosts before moving to the next batch\n\
                        | |\n\
+                       | | --connect-timeout N | seconds to wait for an SSH connection before giving up (default: 10)\n\
+                       | |\n\
                        | | --forward-agent | enables SSH agent forwarding but only on specific tasks (ex: git)\n\
                        | |\n\
+                       | | --host-key-checking strict/accept-new/off | how to verify host keys against ~/.ssh/known_hosts (default: accept-new)\n\
+                       | |\n\
+                       | | --idle-timeout N | close connections unused for N seconds, 0 keeps them open for the whole run (default: 0)\n\
+                       | |\n\
+                       | | --keepalive N | send SSH keepalives after N idle seconds, 0 disables (default: 30)\n\
+                       | |\n\
                        | | --limit-groups group1:group2 | further limits scope for playbook runs\n\
                        | |\n\
                        | | --limit-hosts host1 | further limits scope for playbook runs\n\
@@ -201,12 +265,20 @@ fn show_help() {
                        | Misc options:\n\
                        | | --allow-localhost-delegation | signs off on variable sourcing risks and enables localhost actions with delegate_to\n\
                        | |\n\
+                       | | --become-method sudo/su/doas/run0 | tool used to change users for --sudo and sudo: settings, default sudo\n\
+                       | |\n\
+                       | | --container-runtime docker/podman | tool used for container connections instead of $JET_CONTAINER_RUNTIME or docker\n\
+                       | |\n\
                        | | -e, --extra-vars @filename | injects extra variables into the playbook runtime context from a YAML file, or quoted JSON\n\
                        | |\n\
+                       | | --ask-sudo-password | prompt for the password used by --sudo and sudo: settings, instead of jet_sudo_password\n\
+                       | |\n\
                        | | --sudo username | sudo to this user by default for all tasks\n\
                        | |\n\
                        | | --tags tag1:tag2 | only run tasks or roles with one of these tags\n\
                        | |\n\
+                       | | --timeout N | kill any command still running after N seconds unless a task sets its own, 0 disables (default: 0)\n\
+                       | |\n\
                        | | -v -vv -vvv| ever increasing verbosity\n\
                        | |\n\
                        |-|";
@@ -244,7 +316,9 @@ impl CliParser  {
                     Err(_) => String::from("root")
                 }
             },
+            default_user_set: env::var("JET_SSH_USER").is_ok(),
             sudo: None,
+            become_method: None,
             default_port: match env::var("JET_SSH_PORT") {
                 Ok(x) => match x.parse::<i64>() {
                     Ok(i)  => {
@@ -271,7 +345,23 @@ impl CliParser  {
             allow_localhost_delegation: false,
             extra_vars: serde_yaml::Value::Mapping(serde_yaml::Mapping::new()),
             forward_agent: false,
-            login_password: None
+            login_password: None,
+            sudo_password: None,
+            key_passphrase: None,
+            host_key_checking: HostKeyChecking::AcceptNew,
+            connect_timeout: 10,
+            keepalive_interval: 30,
+            command_timeout: 0,
+            idle_timeout: 0,
+            container_runtime: match env::var("JET_CONTAINER_RUNTIME") {
+                Ok(x) => x,
+                Err(_) => String::from("docker")
+            },
+            requirements_path: PathBuf::from("requirements.yml"),
+            role_cache_path: match env::var("JET_ROLES_CACHE") {
+                Ok(x) => Some(PathBuf::from(expand_home_path(&x))),
+                Err(_) => None
+            }
         };
         return p;
     }
@@ -318,6 +408,13 @@ impl CliParser  {
                         return Ok(());
                     }
 
+                    // the roles mode is followed by the action to take, which is skipped over like a flag value
+                    if argument == "roles" {
+                        self.mode = roles_mode_from_string(args.get(2))?;
+                        next_is_value = true;
+                        continue 'each_argument;
+                    }
+
                     // if it's not --help, then the second argument is the
                     // required 'mode' parameter
                     let _result = self.store_mode(argument)?;
@@ -350,6 +447,7 @@ impl CliParser  {
                             ARGUMENT_INVENTORY         => self.append_inventory(&args[arg_count]),
                             ARGUMENT_INVENTORY_SHORT   => self.append_inventory(&args[arg_count]),
                             ARGUMENT_SUDO              => self.store_sudo(&args[arg_count]),
+                            ARGUMENT_BECOME_METHOD     => self.store_become_method(&args[arg_count]),
                             ARGUMENT_TAGS              => self.store_tags(&args[arg_count]),
                             ARGUMENT_USER              => self.store_default_user(&args[arg_count]),
                             ARGUMENT_USER_SHORT        => self.store_default_user(&args[arg_count]),
@@ -369,6 +467,15 @@ impl CliParser  {
                             ARGUMENT_EXTRA_VARS        => self.store_extra_vars(&args[arg_count]),
                             ARGUMENT_EXTRA_VARS_SHORT  => self.store_extra_vars(&args[arg_count]),
                             ARGUMENT_ASK_LOGIN_PASSWORD => self.store_login_password(),
+                            ARGUMENT_ASK_KEY_PASSPHRASE => self.store_key_passphrase(),
+                            ARGUMENT_ASK_SUDO_PASSWORD => self.store_sudo_password(),
+                            ARGUMENT_HOST_KEY_CHECKING => self.store_host_key_checking(&args[arg_count]),
+                            ARGUMENT_CONNECT_TIMEOUT   => self.store_connect_timeout(&args[arg_count]),
+                            ARGUMENT_KEEPALIVE         => self.store_keepalive(&args[arg_count]),
+                            ARGUMENT_TIMEOUT           => self.store_command_timeout(&args[arg_count]),
+                            ARGUMENT_IDLE_TIMEOUT      => self.store_idle_timeout(&args[arg_count]),
+                            ARGUMENT_CONTAINER_RUNTIME => self.store_container_runtime(&args[arg_count]),
+                            ARGUMENT_REQUIREMENTS      => self.store_requirements(&args[arg_count]),
 
                             _                          => Err(format!("invalid flag: {}", argument_str)),
 
@@ -376,7 +483,8 @@ impl CliParser  {
                         if result.is_err() { return result; }
                         if argument_str.eq(ARGUMENT_VERBOSE) || argument_str.eq(ARGUMENT_VERBOSER) || argument_str.eq(ARGUMENT_VERBOSEST)
                              || argument_str.eq(ARGUMENT_ALLOW_LOCALHOST) || argument_str.eq(ARGUMENT_FORWARD_AGENT)
-                             || argument_str.eq(ARGUMENT_ASK_LOGIN_PASSWORD) {
+                             || argument_str.eq(ARGUMENT_ASK_LOGIN_PASSWORD) || argument_str.eq(ARGUMENT_ASK_KEY_PASSPHRASE)
+                             || argument_str.eq(ARGUMENT_ASK_SUDO_PASSWORD) {
                             // these do not take arguments
                         } else {
                             next_is_value = true;
@@ -398,6 +506,7 @@ impl CliParser  {
             CLI_MODE_CHECK_LOCAL => { self.threads = 1 },
             CLI_MODE_SYNTAX      => { self.threads = 1 },
             CLI_MODE_SHOW        => { self.threads = 1 },
+            CLI_MODE_ROLES_INSTALL => { self.threads = 1 },
             CLI_MODE_UNSET       => { self.needs_help = true; },
             _ => {}
         }
@@ -405,6 +514,7 @@ impl CliParser  {
         if self.playbook_set {
             self.add_role_paths_from_environment()?;
             self.add_implicit_role_paths()?;
+            self.add_role_cache_path()?;
         }
         Ok(())
 
@@ -518,8 +628,16 @@ impl CliParser  {
         return Ok(());
     }
 
+    fn store_become_method(&mut self, value: &String) -> Result<(), String> {
+        match BecomeMethod::from_string(value) {
+            Ok(_) => { self.become_method = Some(value.clone()); return Ok(()); },
+            Err(y) => { return Err(format!("{}: {}", ARGUMENT_BECOME_METHOD, y)); }
+        }
+    }
+
     fn store_default_user(&mut self, value: &String) -> Result<(), String> {
         self.default_user = value.clone();
+        self.default_user_set = true;
         return Ok(());
     }
 
@@ -547,6 +665,58 @@ impl CliParser  {
         }
     }
 
+    fn store_connect_timeout(&mut self, value: &String) -> Result<(), String> {
+        match value.parse::<u64>() {
+            Ok(n) =>  { self.connect_timeout = n; return Ok(()); }
+            Err(_e) => { return Err(format!("{}: invalid value", ARGUMENT_CONNECT_TIMEOUT)); }
+        }
+    }
+
+    fn store_keepalive(&mut self, value: &String) -> Result<(), String> {
+        match value.parse::<u32>() {
+            Ok(n) =>  { self.keepalive_interval = n; return Ok(()); }
+            Err(_e) => { return Err(format!("{}: invalid value", ARGUMENT_KEEPALIVE)); }
+        }
+    }
+
+    fn store_command_timeout(&mut self, value: &String) -> Result<(), String> {
+        match value.parse::<u64>() {
+            Ok(n) =>  { self.command_timeout = n; return Ok(()); }
+            Err(_e) => { return Err(format!("{}: invalid value", ARGUMENT_TIMEOUT)); }
+        }
+    }
+
+    fn store_idle_timeout(&mut self, value: &String) -> Result<(), String> {
+        match value.parse::<u64>() {
+            Ok(n) =>  { self.idle_timeout = n; return Ok(()); }
+            Err(_e) => { return Err(format!("{}: invalid value", ARGUMENT_IDLE_TIMEOUT)); }
+        }
+    }
+
+    fn store_container_runtime(&mut self, value: &String) -> Result<(), String> {
+        self.container_runtime = value.clone();
+        return Ok(());
+    }
+
+    fn store_requirements(&mut self, value: &String) -> Result<(), String> {
+        let path = PathBuf::from(value);
+        if ! path.is_file() {
+            return Err(format!("requirements file missing: {:?}", path));
+        }
+        self.requirements_path = path;
+        return Ok(());
+    }
+
+    fn store_host_key_checking(&mut self, value: &String) -> Result<(), String> {
+        self.host_key_checking = match value.as_str() {
+            "strict"     => HostKeyChecking::Strict,
+            "accept-new" => HostKeyChecking::AcceptNew,
+            "off"        => HostKeyChecking::Off,
+            _ => { return Err(format!("{}: expecting strict, accept-new, or off", ARGUMENT_HOST_KEY_CHECKING)); }
+        };
+        return Ok(());
+    }
+
     fn store_allow_localhost_delegation(&mut self) -> Result<(), String> {
         self.allow_localhost_delegation = true;
         Ok(())
@@ -574,6 +744,34 @@ impl CliParser  {
         return Ok(());
     }
 
+    // roles fetched with "jetp roles install" are searched last, so a role in a roles/ directory or given with --roles
+    // can stand in for one from git while it is being worked on.  The cache is looked for next to each playbook, and
+    // when a requirements.lock is there too the checkouts must match it, so a playbook never runs with roles other
+    // than the ones locked for it
+
+    fn add_role_cache_path(&mut self) -> Result<(), String> {
+        let paths = self.playbook_paths.read().unwrap();
+        for pb in paths.iter() {
+            let mut requirements = PathBuf::new();
+            requirements.push(directory_as_string(pb.as_path()));
+            requirements.push("requirements.yml");
+            let cache = role_cache_path(self, &requirements);
+            if ! cache.is_dir() {
+                continue;
+            }
+            let lock_path = requirements.with_extension("lock");
+            if lock_path.is_file() {
+                check_role_cache(&cache, &lock_path)?;
+            }
+            let full = fs::canonicalize(cache.as_path()).unwrap();
+            let mut role_paths = self.role_paths.write().unwrap();
+            if ! role_paths.contains(&full) {
+                role_paths.push(full);
+            }
+        }
+        return Ok(());
+    }
+
     fn add_role_paths_from_environment(&mut self) -> Result<(), String> {
 
         let env_roles_path = env::var("JET_ROLES_PATH");
@@ -634,12 +832,17 @@ impl CliParser  {
      }
 
      fn store_login_password(&mut self) -> Result<(), String>{
-        let mut value = String::new();
-        println!("enter login password:");
-        match io::stdin().read_line(&mut value) {
-            Ok(_) => { self.login_password = Some(String::from(value.trim())); println!("GOT IT!: ({:?})", self.login_password.clone()) }
-            Err(e) =>  return Err(format!("failure reading input: {}", e))
-        }
+        self.login_password = Some(read_secret(&String::from("enter login password:"))?);
+        return Ok(());
+     }
+
+     fn store_key_passphrase(&mut self) -> Result<(), String>{
+        self.key_passphrase = Some(read_secret(&String::from("enter private key passphrase:"))?);
+        return Ok(());
+     }
+
+     fn store_sudo_password(&mut self) -> Result<(), String>{
+        self.sudo_password = Some(read_secret(&String::from("enter sudo password:"))?);
         return Ok(());
      }
 
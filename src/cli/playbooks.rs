@@ -17,8 +17,8 @@
 use crate::cli::parser::CliParser;
 
 use crate::connection::ssh::SshFactory;
-use crate::connection::local::LocalFactory;
 use crate::connection::no::NoFactory;
+use crate::connection::dispatch::{DispatchFactory,ConnectionType};
 use crate::playbooks::traversal::{playbook_traversal,RunState};
 use crate::playbooks::context::PlaybookContext;
 use crate::playbooks::visitor::PlaybookVisitor;
@@ -59,6 +59,7 @@ enum CheckMode {
 enum ConnectionMode {
     Ssh,
     Local,
+    Container,
     Simulate
 }
 
@@ -78,10 +79,22 @@ pub fn playbook_check_local(inventory: &Arc<RwLock<Inventory>>, parser: &CliPars
     return playbook(inventory, parser, CheckMode::Yes, ConnectionMode::Local);
 }
 
+pub fn playbook_container(inventory: &Arc<RwLock<Inventory>>, parser: &CliParser) -> i32 {
+    return playbook(inventory, parser, CheckMode::No, ConnectionMode::Container);
+}
+
+pub fn playbook_check_container(inventory: &Arc<RwLock<Inventory>>, parser: &CliParser) -> i32 {
+    return playbook(inventory, parser, CheckMode::Yes, ConnectionMode::Container);
+}
+
 pub fn playbook_simulate(inventory: &Arc<RwLock<Inventory>>, parser: &CliParser) -> i32 {
     return playbook(inventory, parser, CheckMode::No, ConnectionMode::Simulate);
 }
 
+fn ssh_factory(inventory: &Arc<RwLock<Inventory>>, parser: &CliParser) -> SshFactory {
+    return SshFactory::new(inventory, parser.forward_agent, parser.login_password.clone(), parser.key_passphrase.clone(), parser.host_key_checking);
+}
+
 fn playbook(inventory: &Arc<RwLock<Inventory>>, parser: &CliParser, check_mode: CheckMode, connection_mode: ConnectionMode) -> i32 {
     let run_state = Arc::new(RunState {
         // every object gets an inventory, though with local modes it's empty.
@@ -100,8 +113,10 @@ fn playbook(inventory: &Arc<RwLock<Inventory>>, parser: &CliParser, check_mode:
             CheckMode::No => Arc::new(RwLock::new(LiveVisitor::new())),
         },
         connection_factory: match connection_mode {
-            ConnectionMode::Ssh => Arc::new(RwLock::new(SshFactory::new(inventory, parser.forward_agent, parser.login_password.clone()))),
-            ConnectionMode::Local => Arc::new(RwLock::new(LocalFactory::new(inventory))),
+            // the CLI mode only picks the default connection type, hosts can override it with jet_connection
+            ConnectionMode::Ssh => Arc::new(RwLock::new(DispatchFactory::new(inventory, ConnectionType::Ssh, ssh_factory(inventory, parser)))),
+            ConnectionMode::Local => Arc::new(RwLock::new(DispatchFactory::new(inventory, ConnectionType::Local, ssh_factory(inventory, parser)))),
+            ConnectionMode::Container => Arc::new(RwLock::new(DispatchFactory::new(inventory, ConnectionType::Container, ssh_factory(inventory, parser)))),
             ConnectionMode::Simulate => Arc::new(RwLock::new(NoFactory::new()))
         },
         tags: parser.tags.clone(),
@@ -0,0 +1,204 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+// 
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::cli::parser::CliParser;
+use crate::util::io::{jet_file_open,path_as_string};
+use crate::util::yaml::show_yaml_error_in_context;
+use serde::{Deserialize,Serialize};
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::process::Command;
+
+// "jetp roles install" reads a requirements file listing roles kept in git repositories, clones each one into the role
+// cache (.jet/roles next to the requirements file unless $JET_ROLES_CACHE is set, and automatically searched for roles,
+// see parser.rs) and checks out a fixed commit.  The commits are recorded in a lock file next to the requirements file,
+// so later installs get exactly the same roles until the requirements change.
+
+#[derive(Debug,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleRequirement {
+    pub name: String,
+    pub git: String,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>
+}
+
+#[derive(Debug,Deserialize,Serialize,Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LockedRole {
+    pub name: String,
+    pub git: String,
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    pub commit: String
+}
+
+pub fn roles_install(parser: &CliParser) -> Result<(), String> {
+
+    let requirements_path = parser.requirements_path.as_path();
+    if ! requirements_path.is_file() {
+        return Err(format!("requirements file missing: {}", path_as_string(requirements_path)));
+    }
+    let requirements : Vec<RoleRequirement> = load_yaml(requirements_path)?;
+    let lock_path = requirements_path.with_extension("lock");
+    let locked : Vec<LockedRole> = match lock_path.is_file() {
+        true => load_yaml(&lock_path)?,
+        false => Vec::new()
+    };
+
+    let cache = &role_cache_path(parser, requirements_path);
+    match fs::create_dir_all(cache) {
+        Ok(_) => {},
+        Err(y) => { return Err(format!("could not create the role cache {}: {}", path_as_string(cache), y)); }
+    }
+
+    let mut results : Vec<LockedRole> = Vec::new();
+    for requirement in requirements.iter() {
+        if requirement.name.contains('/') || requirement.name.starts_with('.') {
+            return Err(format!("invalid role name in requirements: {}", requirement.name));
+        }
+        // git would take these for options
+        if requirement.git.starts_with('-') || requirement.git_ref.as_ref().map_or(false, |x| x.starts_with('-')) {
+            return Err(format!("role {}: git and ref may not start with '-'", requirement.name));
+        }
+        if results.iter().any(|x| x.name.eq(&requirement.name)) {
+            return Err(format!("role listed more than once in requirements: {}", requirement.name));
+        }
+        // a lock entry only counts while the requirement it was made from is unchanged
+        let pinned = locked.iter().find(|x| x.name.eq(&requirement.name) && x.git.eq(&requirement.git) && x.git_ref.eq(&requirement.git_ref));
+        if pinned.is_some() && ! pinned.unwrap().commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("role {}: invalid commit in {}: {}", requirement.name, path_as_string(&lock_path), pinned.unwrap().commit));
+        }
+        let mut path = cache.clone();
+        path.push(&requirement.name);
+        let commit = install_role(requirement, &path, pinned.map(|x| &x.commit))?;
+        let source = match (pinned, &requirement.git_ref) {
+            (Some(_), _) => String::from("locked"),
+            (None, Some(git_ref)) => format!("resolved from {}", git_ref),
+            (None, None) => String::from("resolved from the default branch")
+        };
+        println!("{}: {} ({})", requirement.name, commit, source);
+        results.push(LockedRole { name: requirement.name.clone(), git: requirement.git.clone(), git_ref: requirement.git_ref.clone(), commit: commit });
+    }
+
+    let data = match serde_yaml::to_string(&results) {
+        Ok(x) => x,
+        Err(y) => { return Err(format!("could not write {}: {}", path_as_string(&lock_path), y)); }
+    };
+    return match fs::write(&lock_path, data) {
+        Ok(_) => {
+            println!("roles installed in {}, commits saved to {}", path_as_string(cache), path_as_string(&lock_path));
+            Ok(())
+        },
+        Err(y) => Err(format!("could not write {}: {}", path_as_string(&lock_path), y))
+    };
+}
+
+// each project keeps its own cache next to its requirements file, so projects locking the same role to different
+// commits do not overwrite each other's checkouts
+
+pub fn role_cache_path(parser: &CliParser, requirements_path: &Path) -> PathBuf {
+    return match &parser.role_cache_path {
+        Some(x) => x.clone(),
+        None => {
+            let mut path = match requirements_path.parent() {
+                Some(x) => x.to_path_buf(),
+                None => PathBuf::new()
+            };
+            path.push(".jet");
+            path.push("roles");
+            path
+        }
+    };
+}
+
+// used before a playbook run: every locked role must be checked out in the cache at its locked commit
+
+pub fn check_role_cache(cache: &Path, lock_path: &Path) -> Result<(), String> {
+    let locked : Vec<LockedRole> = load_yaml(lock_path)?;
+    for role in locked.iter() {
+        let path = cache.join(&role.name);
+        let dir = path_as_string(&path);
+        if ! path.join(".git").is_dir() {
+            return Err(format!("role {} from {} is not installed in {}, run jetp roles install", role.name, path_as_string(lock_path), path_as_string(cache)));
+        }
+        let head = run_git(&role.name, &["-C", &dir, "rev-parse", "--verify", "--quiet", "HEAD"])?;
+        if ! head.eq(&role.commit) {
+            return Err(format!("role {} in {} is at {} but {} locks it to {}, run jetp roles install", role.name, dir, head, path_as_string(lock_path), role.commit));
+        }
+    }
+    return Ok(());
+}
+
+fn load_yaml<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let file = jet_file_open(path)?;
+    let parsed: Result<T, serde_yaml::Error> = serde_yaml::from_reader(file);
+    return match parsed {
+        Ok(x) => Ok(x),
+        Err(y) => {
+            show_yaml_error_in_context(&y, path);
+            Err(format!("edit the file and try again?"))
+        }
+    };
+}
+
+// clones the role (or fetches into an earlier clone) and checks out the locked commit, or else what the ref points to,
+// returning the commit
+
+fn install_role(requirement: &RoleRequirement, path: &PathBuf, pinned: Option<&String>) -> Result<String, String> {
+    let dir = path_as_string(path);
+    if path.join(".git").is_dir() {
+        run_git(&requirement.name, &["-C", &dir, "remote", "set-url", "origin", &requirement.git])?;
+        run_git(&requirement.name, &["-C", &dir, "fetch", "--quiet", "--tags", "--force", "origin"])?;
+        run_git(&requirement.name, &["-C", &dir, "remote", "set-head", "origin", "--auto"])?;
+    } else {
+        if path.exists() {
+            return Err(format!("role {}: {} exists but is not a git checkout", requirement.name, dir));
+        }
+        run_git(&requirement.name, &["clone", "--quiet", "--", &requirement.git, &dir])?;
+    }
+
+    let target = match pinned {
+        Some(commit) => commit.clone(),
+        None => match &requirement.git_ref {
+            // branches are taken from the remote, as the local branch of an earlier clone may be behind
+            Some(git_ref) => match run_git(&requirement.name, &["-C", &dir, "rev-parse", "--verify", "--quiet", &format!("refs/remotes/origin/{}^{{commit}}", git_ref)]) {
+                Ok(x) => x,
+                Err(_) => git_ref.clone()
+            },
+            None => String::from("refs/remotes/origin/HEAD")
+        }
+    };
+    let commit = match run_git(&requirement.name, &["-C", &dir, "rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", target)]) {
+        Ok(x) => x,
+        Err(_) => { return Err(format!("role {}: {} not found in {}", requirement.name, target, requirement.git)); }
+    };
+    run_git(&requirement.name, &["-C", &dir, "checkout", "--quiet", "--force", "--detach", &commit])?;
+    if ! path.join("role.yml").is_file() {
+        return Err(format!("role {}: no role.yml found at the top of {}", requirement.name, requirement.git));
+    }
+    return Ok(commit);
+}
+
+fn run_git(role: &String, args: &[&str]) -> Result<String, String> {
+    return match Command::new("git").args(args).output() {
+        Ok(x) => match x.status.success() {
+            true => Ok(String::from_utf8_lossy(&x.stdout).trim().to_string()),
+            false => Err(format!("role {}: git {} failed: {}", role, args.join(" "), String::from_utf8_lossy(&x.stderr).trim()))
+        },
+        Err(y) => Err(format!("role {}: failed to run git: {}", role, y))
+    };
+}
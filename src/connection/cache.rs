@@ -20,21 +20,73 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
 use std::collections::HashMap;
+use std::time::{Duration,Instant};
+use ssh2::Session;
+
+// connections to managed hosts are kept for the whole run, so a host that shows up in several plays (or playbooks) is only
+// connected to once.  Each is remembered with when it was made and last used, so that connections left unused for a while
+// can be closed early (see --idle-timeout), and so the traversal code can report how long each was open.
+//
+// each is also remembered with the details it was made with (connection type, user, port, key and so on, as resolved by
+// the factory).  Variables can change between plays, so a factory only reuses a connection made with the same details,
+// and a stale one is replaced.  Replaced connections are kept aside until the traversal code reports them.
+//
+// jump host (bastion) sessions are not connections to managed hosts, so they are kept separately, keyed by
+// the chain of hops used to reach them, and shared by every host that tunnels through the same chain.
 
 pub struct ConnectionCache {
-    connections: HashMap<String, Arc<Mutex<dyn Connection>>>
+    connections: HashMap<String, CachedConnection>,
+    jump_sessions: HashMap<String, Session>,
+    replaced: Vec<(Arc<RwLock<Host>>,Duration)>
+}
+
+struct CachedConnection {
+    host: Arc<RwLock<Host>>,
+    connection: Arc<Mutex<dyn Connection>>,
+    details: String,
+    connected: Instant,
+    last_used: Instant
 }
 
 impl ConnectionCache {
     pub fn new() -> Self {
         Self {
-            connections: HashMap::new()
+            connections: HashMap::new(),
+            jump_sessions: HashMap::new(),
+            replaced: Vec::new()
         }
     }
 
-    pub fn add_connection(&mut self, host:&Arc<RwLock<Host>>, connection: &Arc<Mutex<dyn Connection>>) {
+    pub fn add_connection(&mut self, host:&Arc<RwLock<Host>>, connection: &Arc<Mutex<dyn Connection>>, details: &String) {
         let host2 = host.read().expect("host read");
-        self.connections.insert(host2.name.clone(), Arc::clone(connection));
+        let now = Instant::now();
+        self.connections.insert(host2.name.clone(), CachedConnection {
+            host: Arc::clone(host),
+            connection: Arc::clone(connection),
+            details: details.clone(),
+            connected: now,
+            last_used: now
+        });
+    }
+
+    // returns the connection to the host if there is one made with the same details, see above
+
+    pub fn get_matching_connection(&mut self, host: &Arc<RwLock<Host>>, details: &String) -> Option<Arc<Mutex<dyn Connection>>> {
+        let name = host.read().expect("host read").name.clone();
+        let matches = match self.connections.get(&name) {
+            Some(x) => x.details.eq(details),
+            None => { return None; }
+        };
+        if ! matches {
+            let mut closed = self.remove(vec![name]);
+            self.replaced.append(&mut closed);
+            return None;
+        }
+        return Some(Arc::clone(&self.connections.get(&name).unwrap().connection));
+    }
+
+    pub fn take_replaced(&mut self) -> Vec<(Arc<RwLock<Host>>,Duration)> {
+        return std::mem::take(&mut self.replaced);
     }
 
     pub fn has_connection(&self, host: &Arc<RwLock<Host>>) -> bool {
@@ -44,10 +96,50 @@ impl ConnectionCache {
 
     pub fn get_connection(&self, host: &Arc<RwLock<Host>>) -> Arc<Mutex<dyn Connection>> {
         let host2 = host.read().expect("host read");
-        return Arc::clone(self.connections.get(&host2.name.clone()).unwrap());
+        return Arc::clone(&self.connections.get(&host2.name.clone()).unwrap().connection);
+    }
+
+    pub fn touch(&mut self, host: &Arc<RwLock<Host>>) {
+        let host2 = host.read().expect("host read");
+        if let Some(x) = self.connections.get_mut(&host2.name) {
+            x.last_used = Instant::now();
+        }
+    }
+
+    pub fn add_jump_session(&mut self, chain: &String, session: &Session) {
+        self.jump_sessions.insert(chain.clone(), session.clone());
+    }
+
+    pub fn get_jump_session(&self, chain: &String) -> Option<Session> {
+        return self.jump_sessions.get(chain).cloned();
+    }
+
+    // the methods that close connections return the hosts that were disconnected and how long each connection was open
+
+    pub fn close_idle(&mut self, idle: Duration) -> Vec<(Arc<RwLock<Host>>,Duration)> {
+        let names : Vec<String> = self.connections.iter().filter(|(_,v)| v.last_used.elapsed() >= idle).map(|(k,_)| k.clone()).collect();
+        return self.remove(names);
+    }
+
+    pub fn close_hosts(&mut self, hosts: &Vec<Arc<RwLock<Host>>>) -> Vec<(Arc<RwLock<Host>>,Duration)> {
+        let names : Vec<String> = hosts.iter().map(|h| h.read().expect("host read").name.clone()).collect();
+        return self.remove(names);
     }
 
-    pub fn clear(&mut self) {
-        self.connections.clear();
+    pub fn clear(&mut self) -> Vec<(Arc<RwLock<Host>>,Duration)> {
+        let names : Vec<String> = self.connections.keys().cloned().collect();
+        let closed = self.remove(names);
+        self.jump_sessions.clear();
+        return closed;
     }
-}
\ No newline at end of file
+
+    fn remove(&mut self, names: Vec<String>) -> Vec<(Arc<RwLock<Host>>,Duration)> {
+        let mut closed = Vec::new();
+        for name in names.iter() {
+            if let Some(x) = self.connections.remove(name) {
+                closed.push((x.host, x.connected.elapsed()));
+            }
+        }
+        return closed;
+    }
+}
@@ -0,0 +1,279 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::connection::connection::Connection;
+use crate::connection::factory::ConnectionFactory;
+use crate::connection::command::{Forward,CommandOutput,RC_TIMED_OUT,get_deadline,get_kill_command};
+use crate::connection::local::{LocalFactory,convert_out,wait_with_deadline,get_become_password,get_become_stdin};
+use crate::playbooks::context::PlaybookContext;
+use crate::inventory::hosts::Host;
+use crate::handle::response::Response;
+use crate::tasks::{TaskRequest,TaskResponse};
+use crate::Inventory;
+use crate::util::io::jet_file_open;
+use std::sync::{Arc,Mutex,RwLock};
+use std::process::{Command,Stdio};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::io::Read;
+
+// implementation for both chroot connections and the chroot connection factory.  These act on a root filesystem in a
+// directory on the machine running jetp (such as an image being built) rather than on a running system.  Commands run
+// with chroot or systemd-nspawn, and so do file writes, so that links inside the directory resolve the way they would on
+// the finished system rather than on this machine.  Both need jetp to run as root.
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum ChrootMethod {
+    Chroot,
+    Nspawn
+}
+
+impl ChrootMethod {
+    pub fn from_string(value: &String) -> Result<Self, String> {
+        return match value.as_str() {
+            "chroot" => Ok(ChrootMethod::Chroot),
+            "nspawn" => Ok(ChrootMethod::Nspawn),
+            _ => Err(format!("invalid jet_chroot_method: {} (expecting chroot or nspawn)", value))
+        };
+    }
+}
+
+pub struct ChrootFactory {
+    local_factory: LocalFactory,
+    localhost: Arc<RwLock<Host>>,
+}
+
+impl ChrootFactory {
+    pub fn new(inventory: &Arc<RwLock<Inventory>>) -> Self {
+        Self {
+            localhost : inventory.read().expect("inventory read").get_host(&String::from("localhost")),
+            local_factory: LocalFactory::new(inventory),
+        }
+    }
+}
+
+impl ConnectionFactory for ChrootFactory {
+
+    fn get_local_connection(&self, context: &Arc<RwLock<PlaybookContext>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        return Ok(self.local_factory.get_connection(context, &self.localhost)?);
+    }
+
+    fn get_connection(&self, context: &Arc<RwLock<PlaybookContext>>, host:&Arc<RwLock<Host>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        let ctx = context.read().expect("context read");
+        let hostname = host.read().expect("host read").name.clone();
+        if hostname.eq("localhost") {
+            let conn : Arc<Mutex<dyn Connection>> = self.local_factory.get_connection(context, &self.localhost)?;
+            return Ok(conn);
+        }
+
+        // the directory defaults to the inventory hostname but is usually set with jet_chroot_path
+        let (method, root, user) = ctx.get_chroot_details(host);
+        let details = format!("chroot {} {} {:?}", method, root, user);
+        {
+            let mut cache = ctx.connection_cache.write().unwrap();
+            if let Some(conn) = cache.get_matching_connection(host, &details) {
+                return Ok(conn);
+            }
+        }
+
+        let method = match ChrootMethod::from_string(&method) {
+            Ok(x) => x,
+            Err(y) => { return Err(format!("host {}: {}", hostname, y)); }
+        };
+        let mut conn = ChrootConnection::new(Arc::clone(&host), method, &root, user);
+        return match conn.connect() {
+            Ok(_)  => {
+                let conn2 : Arc<Mutex<dyn Connection>> = Arc::new(Mutex::new(conn));
+                ctx.connection_cache.write().expect("connection cache write").add_connection(
+                    &Arc::clone(&host), &Arc::clone(&conn2), &details);
+                Ok(conn2)
+            },
+            Err(x) => { Err(x) }
+        }
+    }
+}
+
+pub struct ChrootConnection {
+    pub host: Arc<RwLock<Host>>,
+    pub method: ChrootMethod,
+    pub root: String,
+    pub user: Option<String>
+}
+
+impl ChrootConnection {
+    pub fn new(host: Arc<RwLock<Host>>, method: ChrootMethod, root: &String, user: Option<String>) -> Self {
+        Self { host: Arc::clone(&host), method, root: root.clone(), user }
+    }
+
+    fn trim_newlines(&self, s: &mut String) {
+        if s.ends_with('\n') {
+            s.pop();
+            if s.ends_with('\r') {
+                s.pop();
+            }
+        }
+    }
+
+    fn get_tool(&self) -> &str {
+        return match self.method {
+            ChrootMethod::Chroot => "chroot",
+            ChrootMethod::Nspawn => "systemd-nspawn"
+        };
+    }
+
+    // builds "chroot [--userspec=user] dir /bin/sh -c cmd" or the systemd-nspawn equivalent.  nspawn is told to pass
+    // stdin and stdout straight through (--pipe), to leave the machine unregistered since it only lives for one command,
+    // and to keep a stub init as PID 1 so the command handles signals normally.
+
+    fn exec_command(&self, cmd: &String, user: Option<&String>) -> Command {
+        let mut command = Command::new(self.get_tool());
+        match self.method {
+            ChrootMethod::Chroot => {
+                if user.is_some() {
+                    command.arg(format!("--userspec={}", user.unwrap()));
+                }
+                command.arg(&self.root);
+            },
+            ChrootMethod::Nspawn => {
+                command.arg("--quiet").arg("--pipe").arg("--register=no").arg("--as-pid2").arg("-D").arg(&self.root);
+                if user.is_some() {
+                    command.arg(format!("--user={}", user.unwrap()));
+                }
+            }
+        }
+        command.arg("/bin/sh").arg("-c").arg(cmd);
+        return command;
+    }
+
+    fn run_command_low_level(&self, cmd: &String, user: Option<&String>) -> Result<(i32,String),(i32,String)> {
+        let mut command = self.exec_command(cmd, user);
+        return match command.stdin(Stdio::null()).output() {
+            Ok(x) => match x.status.code() {
+                Some(rc) => Ok((rc, convert_out(&x.stdout,&x.stderr))),
+                None => Err((418, String::from("")))
+            },
+            Err(y) => Err((404, format!("failed to run {}: {}", self.get_tool(), y)))
+        };
+    }
+
+    // files are streamed to cat inside the root directory, as the user commands run as, rather than written from outside
+    // where links in the directory would point into this machine.  remote.rs takes care of moving the file into place.
+
+    fn write_through(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, reader: &mut dyn Read, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        let mut command = self.exec_command(&format!("cat > '{}'", remote_path.replace("'", "'\\''")), self.user.as_ref());
+        command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = match command.spawn() {
+            Ok(x) => x,
+            Err(y) => { return Err(response.is_failed(request, &format!("failed to run {}: {}", self.get_tool(), y))); }
+        };
+        {
+            let mut stdin = child.stdin.take().unwrap();
+            match std::io::copy(reader, &mut stdin) {
+                Ok(_) => {},
+                Err(y) => {
+                    drop(stdin);
+                    let _w = child.wait();
+                    return Err(response.is_failed(request, &format!("failed to write {} in {}: {}", remote_path, self.root, y)));
+                }
+            }
+        }
+        return match child.wait_with_output() {
+            Ok(x) if x.status.success() => Ok(()),
+            Ok(x) => Err(response.is_failed(request, &format!("failed to write {} in {}: {}", remote_path, self.root, convert_out(&x.stdout, &x.stderr)))),
+            Err(y) => Err(response.is_failed(request, &format!("failed to write {} in {}: {}", remote_path, self.root, y)))
+        };
+    }
+}
+
+impl Connection for ChrootConnection {
+
+    fn whoami(&self) -> Result<String,String> {
+        if self.user.is_some() {
+            return Ok(self.user.as_ref().unwrap().clone());
+        }
+        return match self.run_command_low_level(&String::from("id -un"), None) {
+            Ok((0,out)) => Ok(out),
+            Ok((rc,out)) => Err(format!("id -un failed in {}: rc={}, out={}", self.root, rc, out)),
+            Err((rc,out)) => Err(format!("id -un failed in {}: rc={}, out={}", self.root, rc, out))
+        };
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        if ! Path::new(&self.root).is_dir() {
+            return Err(format!("chroot directory does not exist: {}", self.root));
+        }
+        // uname reports the kernel of this machine, but running it checks that the directory has a usable shell
+        return match self.run_command_low_level(&String::from("uname -a"), self.user.as_ref()) {
+            Ok((0,out)) => {
+                match self.host.write().unwrap().set_os_info(&out.clone()) {
+                    Ok(_x) => Ok(()),
+                    Err(_y) => Err(format!("failed to set OS info"))
+                }
+            },
+            Ok((rc,out)) => Err(format!("{} into {} failed: rc={}, out={}", self.get_tool(), self.root, rc, out)),
+            Err((rc,out)) => Err(format!("{} into {} failed: rc={}, out={}", self.get_tool(), self.root, rc, out))
+        };
+    }
+
+    fn run_command(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, _forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+        let mut command = self.exec_command(cmd, self.user.as_ref());
+        let deadline = get_deadline(request.get_timeout());
+        // as with local connections, a command with a timeout gets its own process group.  chroot becomes the shell, so
+        // killing the group reaches the command, while systemd-nspawn takes the whole container down when it is killed.
+        if deadline.is_some() {
+            command.process_group(0);
+        }
+        let password = match get_become_password(request) {
+            Ok(x) => x,
+            Err(y) => { return Err(response.is_failed(request, &y)); }
+        };
+        let output = CommandOutput::new(response, request);
+        let mut child = match command.stdin(get_become_stdin(&password)).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(x) => x,
+            Err(y) => {
+                return Err(response.command_failed(request, &output.get_result(cmd, &format!("failed to run {}: {}", self.get_tool(), y), 404)));
+            }
+        };
+        return match wait_with_deadline(&mut child, deadline, &password, &output) {
+            Ok((rc, mut out)) => {
+                // note that non-zero return codes are "ok" to the connection plugin, handle elsewhere!
+                self.trim_newlines(&mut out);
+                Ok(response.command_ok(request, &output.get_result(cmd, &out, rc)))
+            },
+            Err((RC_TIMED_OUT, out)) => {
+                let _k = Command::new("sh").arg("-c").arg(get_kill_command(&format!("{}", child.id()))).output();
+                let _w = child.wait();
+                Err(response.command_timed_out(request, &output.get_result(cmd, &out, RC_TIMED_OUT)))
+            },
+            Err((rc, out)) => {
+                Err(response.command_failed(request, &output.get_result(cmd, &out, rc)))
+            }
+        };
+    }
+
+    fn write_data(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, data: &String, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        let mut reader = data.as_bytes();
+        return self.write_through(response, request, &mut reader, remote_path);
+    }
+
+    fn copy_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
+        let mut src_file = match jet_file_open(src) {
+            Ok(x) => x,
+            Err(y) => return Err(response.is_failed(&request, &y))
+        };
+        return self.write_through(response, request, &mut src_file, remote_path);
+    }
+}
@@ -14,8 +14,11 @@
 // You should have received a copy of the GNU General Public License
 // long with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::sync::{Arc,Mutex};
+use std::time::{Duration,Instant};
 use crate::tasks::response::TaskResponse;
+use crate::tasks::request::{TaskRequest,BECOME_MARKER};
+use crate::handle::response::Response;
 
 // details useful for working with commands
 // not much here, see handle/remote.rs for more
@@ -23,7 +26,10 @@ use crate::tasks::response::TaskResponse;
 #[derive(Clone,Debug)]
 pub struct CommandResult {
     pub cmd: String,
+    // stdout and stderr combined, in the order they arrived
     pub out: String,
+    pub stdout: String,
+    pub stderr: String,
     pub rc: i32
 }
 
@@ -37,4 +43,296 @@ pub fn cmd_info(info: &Arc<TaskResponse>) -> (i32, String) {
     assert!(info.command_result.is_some(), "called cmd_info on a response that is not a command result");
     let result = info.command_result.as_ref().as_ref().unwrap();
     return (result.rc, result.out.clone());
-}
\ No newline at end of file
+}
+// commands that can time out first print the process id of the shell running them, so that the whole process group
+// can be killed later from another channel.  Remote shells started by sshd (or docker exec) lead their own process
+// group, so signalling the group also reaches anything the command started.  The return code matches timeout(1).
+
+pub const RC_TIMED_OUT: i32 = 124;
+const PID_MARKER: &str = "__jet_pid__";
+
+pub fn get_deadline(timeout: u64) -> Option<Instant> {
+    return match timeout {
+        0 => None,
+        _ => Some(Instant::now() + Duration::from_secs(timeout))
+    };
+}
+
+pub fn add_pid_marker(cmd: &String) -> String {
+    return format!("echo {} $$; {}", PID_MARKER, cmd);
+}
+
+// if the output starts with a complete marker line, returns the process id and the rest of the output.
+// Err means the first line is complete but is not a marker, so there is no point in looking further.
+
+fn take_pid_marker(bytes: &Vec<u8>) -> Result<Option<(String,Vec<u8>)>,()> {
+    let newline = match bytes.iter().position(|b| *b == b'\n') {
+        Some(x) => x,
+        None => { return Ok(None); }
+    };
+    let line = String::from_utf8_lossy(&bytes[..newline]);
+    let pid = match line.trim_end_matches('\r').strip_prefix(PID_MARKER) {
+        Some(x) => x.trim(),
+        None => { return Err(()); }
+    };
+    if pid.is_empty() || ! pid.chars().all(|c| c.is_ascii_digit()) {
+        return Err(());
+    }
+    return Ok(Some((String::from(pid), bytes[newline+1..].to_vec())));
+}
+
+// asks nicely first, and falls back to the single process if the group cannot be signalled.  Some shells (dash) do not
+// accept "--" before a negative process id and some kill binaries require it, so both forms are tried.
+
+pub fn get_kill_command(pid: &String) -> String {
+    let mut steps : Vec<String> = Vec::new();
+    for signal in [ "TERM", "KILL" ] {
+        steps.push(format!("{{ kill -{sig} -{pid} || kill -{sig} -- -{pid} || kill -{sig} {pid}; }} 2>/dev/null", sig=signal, pid=pid));
+    }
+    return format!("{}; sleep 1; {}; true", steps[0], steps[1]);
+}
+
+// command output is collected by the connection as it arrives from stdout and stderr, kept per stream and also
+// combined in the order it arrived.  Complete lines are shown as they arrive (see PlaybookVisitor::on_command_output),
+// and anything left over once the command is done.  Lines jet prints for itself (the pid marker, batch delimiters and
+// the like) all start with INTERNAL_PREFIX and are not shown.  Clones share the same output, so the threads draining
+// the pipes of a local process can each hold one.
+
+pub const INTERNAL_PREFIX: &str = "__jet_";
+
+// takes the BECOME_MARKER line out of complete lines of output, returning whether it was there
+
+pub fn remove_become_marker(lines: &mut Vec<u8>) -> bool {
+    let mut start = 0;
+    while start < lines.len() {
+        let end = match lines[start..].iter().position(|b| *b == b'\n') {
+            Some(x) => start + x + 1,
+            None => lines.len()
+        };
+        let line = String::from_utf8_lossy(&lines[start..end]);
+        if line.trim_end_matches(|c| c == '\n' || c == '\r') == BECOME_MARKER {
+            lines.drain(start..end);
+            return true;
+        }
+        start = end;
+    }
+    return false;
+}
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum OutputKind {
+    Stdout,
+    Stderr
+}
+
+#[derive(Clone)]
+pub struct CommandOutput {
+    target: Option<(Arc<Response>, Arc<TaskRequest>)>,
+    state: Arc<Mutex<OutputState>>
+}
+
+#[derive(Default)]
+struct OutputState {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    combined: Vec<u8>,
+    stdout_sent: usize,
+    stderr_sent: usize,
+    // stdout seen while still waiting for the pid marker, see add_pid_marker
+    before_pid: Option<Vec<u8>>,
+    pid: Option<String>
+}
+
+impl CommandOutput {
+
+    pub fn new(response: &Arc<Response>, request: &Arc<TaskRequest>) -> Self {
+        return Self { target: Some((Arc::clone(response), Arc::clone(request))), state: Arc::new(Mutex::new(OutputState::default())) };
+    }
+
+    // for commands the connection runs for itself, such as OS detection
+
+    pub fn none() -> Self {
+        return Self { target: None, state: Arc::new(Mutex::new(OutputState::default())) };
+    }
+
+    // for commands started with add_pid_marker, the marker line is taken out of the output and kept for get_pid
+
+    pub fn expect_pid(&self) {
+        self.state.lock().unwrap().before_pid = Some(Vec::new());
+    }
+
+    pub fn get_pid(&self) -> Option<String> {
+        return self.state.lock().unwrap().pid.clone();
+    }
+
+    pub fn push(&self, kind: OutputKind, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let mut bytes = bytes.to_vec();
+        if kind == OutputKind::Stdout && state.before_pid.is_some() {
+            let mut waiting = state.before_pid.take().unwrap();
+            waiting.extend_from_slice(&bytes);
+            bytes = match take_pid_marker(&waiting) {
+                Ok(Some((pid, rest))) => { state.pid = Some(pid); rest },
+                Ok(None) => { state.before_pid = Some(waiting); return; },
+                Err(_) => waiting
+            };
+        }
+        if bytes.is_empty() {
+            return;
+        }
+        state.combined.extend_from_slice(&bytes);
+        let state = &mut *state;
+        let (buffer, sent) = match kind {
+            OutputKind::Stdout => (&mut state.stdout, &mut state.stdout_sent),
+            OutputKind::Stderr => (&mut state.stderr, &mut state.stderr_sent)
+        };
+        buffer.extend_from_slice(&bytes);
+        if let Some(x) = buffer[*sent..].iter().rposition(|b| *b == b'\n') {
+            let end = *sent + x + 1;
+            self.show(kind, &buffer[*sent..end]);
+            *sent = end;
+        }
+    }
+
+    pub fn finish(&self) {
+        let waiting = self.state.lock().unwrap().before_pid.take();
+        if waiting.is_some() {
+            self.push(OutputKind::Stdout, &waiting.unwrap());
+        }
+        let mut state = self.state.lock().unwrap();
+        self.show(OutputKind::Stdout, &state.stdout[state.stdout_sent..]);
+        self.show(OutputKind::Stderr, &state.stderr[state.stderr_sent..]);
+        state.stdout_sent = state.stdout.len();
+        state.stderr_sent = state.stderr.len();
+    }
+
+    pub fn has_stdout(&self) -> bool {
+        return ! self.state.lock().unwrap().stdout.is_empty();
+    }
+
+    pub fn get_combined(&self) -> String {
+        return String::from_utf8_lossy(&self.state.lock().unwrap().combined).to_string();
+    }
+
+    // the connection decides how the combined output is cleaned up, the separate streams only lose trailing whitespace
+
+    pub fn get_result(&self, cmd: &String, out: &String, rc: i32) -> Arc<Option<CommandResult>> {
+        let state = self.state.lock().unwrap();
+        let clean = |bytes: &Vec<u8>| String::from_utf8_lossy(bytes).replace("\r\n", "\n").trim_end().to_string();
+        return Arc::new(Some(CommandResult {
+            cmd: cmd.clone(),
+            out: out.clone(),
+            stdout: clean(&state.stdout),
+            stderr: clean(&state.stderr),
+            rc: rc
+        }));
+    }
+
+    fn show(&self, kind: OutputKind, bytes: &[u8]) {
+        let (response, request) = match self.target.as_ref() {
+            Some(x) => x,
+            None => { return; }
+        };
+        for line in String::from_utf8_lossy(bytes).lines() {
+            let line = line.trim_end_matches('\r');
+            if ! line.starts_with(INTERNAL_PREFIX) {
+                response.command_output(request, kind, &String::from(line));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdout_of(output: &CommandOutput) -> String {
+        return String::from_utf8_lossy(&output.state.lock().unwrap().stdout).to_string();
+    }
+
+    #[test]
+    fn partial_lines_are_held_until_complete() {
+        let output = CommandOutput::none();
+        output.push(OutputKind::Stdout, b"hel");
+        assert_eq!(output.state.lock().unwrap().stdout_sent, 0);
+        output.push(OutputKind::Stdout, b"lo\nwor");
+        assert_eq!(output.state.lock().unwrap().stdout_sent, 6);
+        output.finish();
+        assert_eq!(output.state.lock().unwrap().stdout_sent, 9);
+        assert_eq!(stdout_of(&output), "hello\nwor");
+    }
+
+    #[test]
+    fn streams_are_kept_apart_and_combined_in_order() {
+        let output = CommandOutput::none();
+        output.push(OutputKind::Stdout, b"one\n");
+        output.push(OutputKind::Stderr, b"two\n");
+        output.push(OutputKind::Stdout, b"three\r\n");
+        output.finish();
+        assert_eq!(output.get_combined(), "one\ntwo\nthree\r\n");
+        assert!(output.has_stdout());
+        let result = output.get_result(&String::from("cmd"), &String::from("out"), 3);
+        let result = result.as_ref().as_ref().unwrap();
+        assert_eq!(result.stdout, "one\nthree");
+        assert_eq!(result.stderr, "two");
+        assert_eq!(result.out, "out");
+        assert_eq!(result.rc, 3);
+    }
+
+    #[test]
+    fn pid_marker_split_across_reads() {
+        let output = CommandOutput::none();
+        output.expect_pid();
+        output.push(OutputKind::Stdout, b"__jet_p");
+        assert!(output.get_pid().is_none());
+        assert!(! output.has_stdout());
+        // stderr is not held back while waiting for the marker
+        output.push(OutputKind::Stderr, b"warning\n");
+        output.push(OutputKind::Stdout, b"id__ 4242\nfirst\n");
+        output.finish();
+        assert_eq!(output.get_pid(), Some(String::from("4242")));
+        assert_eq!(stdout_of(&output), "first\n");
+        assert_eq!(output.get_combined(), "warning\nfirst\n");
+    }
+
+    #[test]
+    fn output_without_a_marker_is_kept() {
+        let output = CommandOutput::none();
+        output.expect_pid();
+        output.push(OutputKind::Stdout, b"no marker here\n");
+        assert!(output.get_pid().is_none());
+        assert_eq!(stdout_of(&output), "no marker here\n");
+
+        // a command that prints nothing at all leaves the partial marker to finish
+        let output = CommandOutput::none();
+        output.expect_pid();
+        output.push(OutputKind::Stdout, b"__jet_pid__ 12");
+        output.finish();
+        assert!(output.get_pid().is_none());
+        assert_eq!(stdout_of(&output), "__jet_pid__ 12");
+    }
+
+    #[test]
+    fn become_marker_lines() {
+        let mut lines = b"__jet_become_ok__\nfirst\n".to_vec();
+        assert!(remove_become_marker(&mut lines));
+        assert_eq!(lines, b"first\n");
+        let mut lines = b"first\r\n__jet_become_ok__\r\nsecond\n".to_vec();
+        assert!(remove_become_marker(&mut lines));
+        assert_eq!(lines, b"first\r\nsecond\n");
+        // only a whole line counts
+        let mut lines = b"echo __jet_become_ok__\n".to_vec();
+        assert!(! remove_become_marker(&mut lines));
+        assert_eq!(lines, b"echo __jet_become_ok__\n");
+    }
+
+    #[test]
+    fn take_pid_marker_cases() {
+        let marker = |s: &str| take_pid_marker(&s.as_bytes().to_vec());
+        assert_eq!(marker("__jet_pid__ 77\r\nrest"), Ok(Some((String::from("77"), b"rest".to_vec()))));
+        assert_eq!(marker("__jet_pid__ 77"), Ok(None));
+        assert_eq!(marker("__jet_pid__ abc\n"), Err(()));
+        assert_eq!(marker("__jet_pid__\n"), Err(()));
+        assert_eq!(marker("hello\n__jet_pid__ 1\n"), Err(()));
+    }
+}
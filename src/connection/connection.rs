@@ -38,4 +38,11 @@ pub trait Connection : Send + Sync {
 
     fn run_command(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>>;
 
+    // runs a batch of independent commands, returning results in the same order. Connections that can run
+    // several commands at once (such as SSH, over multiple channels) override this, the rest run them one by one.
+
+    fn run_commands(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmds: &Vec<(String,Forward)>) -> Vec<Result<Arc<TaskResponse>,Arc<TaskResponse>>> {
+        return cmds.iter().map(|(cmd, forward)| self.run_command(response, request, cmd, *forward)).collect();
+    }
+
 }
\ No newline at end of file
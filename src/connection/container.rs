@@ -0,0 +1,252 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::connection::connection::Connection;
+use crate::connection::factory::ConnectionFactory;
+use crate::connection::command::{Forward,CommandOutput,RC_TIMED_OUT,get_deadline,add_pid_marker,get_kill_command};
+use crate::connection::local::{LocalFactory,convert_out,wait_with_deadline,get_become_password,get_become_stdin};
+use crate::playbooks::context::PlaybookContext;
+use crate::inventory::hosts::Host;
+use crate::handle::response::Response;
+use crate::tasks::{TaskRequest,TaskResponse};
+use crate::Inventory;
+use std::sync::{Arc,Mutex,RwLock};
+use std::process::{Command,Stdio};
+use std::path::Path;
+use std::io::Write;
+
+// implementation for both container connections and the container connection factory.  Containers are managed
+// from the machine running jetp using the docker (or podman) command line, so no SSH server is needed in the container.
+
+pub struct ContainerFactory {
+    local_factory: LocalFactory,
+    localhost: Arc<RwLock<Host>>,
+}
+
+impl ContainerFactory {
+    pub fn new(inventory: &Arc<RwLock<Inventory>>) -> Self {
+        Self {
+            localhost : inventory.read().expect("inventory read").get_host(&String::from("localhost")),
+            local_factory: LocalFactory::new(inventory),
+        }
+    }
+}
+
+impl ConnectionFactory for ContainerFactory {
+
+    fn get_local_connection(&self, context: &Arc<RwLock<PlaybookContext>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        return Ok(self.local_factory.get_connection(context, &self.localhost)?);
+    }
+
+    fn get_connection(&self, context: &Arc<RwLock<PlaybookContext>>, host:&Arc<RwLock<Host>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        let ctx = context.read().expect("context read");
+        let hostname = host.read().expect("host read").name.clone();
+        if hostname.eq("localhost") {
+            let conn : Arc<Mutex<dyn Connection>> = self.local_factory.get_connection(context, &self.localhost)?;
+            return Ok(conn);
+        }
+
+        // the container name defaults to the inventory hostname but may be set with jet_container_name, the runtime
+        // comes from --container-runtime unless jet_container_runtime is set.
+        let (runtime, container, user) = ctx.get_container_details(host);
+        let details = format!("container {} {} {:?}", runtime, container, user);
+        {
+            let mut cache = ctx.connection_cache.write().unwrap();
+            if let Some(conn) = cache.get_matching_connection(host, &details) {
+                return Ok(conn);
+            }
+        }
+
+        let mut conn = ContainerConnection::new(Arc::clone(&host), &runtime, &container, user);
+        return match conn.connect() {
+            Ok(_)  => {
+                let conn2 : Arc<Mutex<dyn Connection>> = Arc::new(Mutex::new(conn));
+                ctx.connection_cache.write().expect("connection cache write").add_connection(
+                    &Arc::clone(&host), &Arc::clone(&conn2), &details);
+                Ok(conn2)
+            },
+            Err(x) => { Err(x) }
+        }
+    }
+}
+
+pub struct ContainerConnection {
+    pub host: Arc<RwLock<Host>>,
+    pub runtime: String,
+    pub container: String,
+    pub user: Option<String>
+}
+
+impl ContainerConnection {
+    pub fn new(host: Arc<RwLock<Host>>, runtime: &String, container: &String, user: Option<String>) -> Self {
+        Self { host: Arc::clone(&host), runtime: runtime.clone(), container: container.clone(), user }
+    }
+
+    fn trim_newlines(&self, s: &mut String) {
+        if s.ends_with('\n') {
+            s.pop();
+            if s.ends_with('\r') {
+                s.pop();
+            }
+        }
+    }
+
+    // builds "docker exec [-i] [-u user] container sh -c cmd"
+
+    fn exec_command(&self, cmd: &String, interactive: bool) -> Command {
+        let mut command = Command::new(&self.runtime);
+        command.arg("exec");
+        if interactive {
+            command.arg("-i");
+        }
+        if self.user.is_some() {
+            command.arg("-u").arg(self.user.as_ref().unwrap());
+        }
+        command.arg(&self.container).arg("sh").arg("-c").arg(cmd);
+        return command;
+    }
+
+    fn run_command_low_level(&self, cmd: &String) -> Result<(i32,String),(i32,String)> {
+        let mut command = self.exec_command(&format!("{} 2>&1", cmd), false);
+        return match command.output() {
+            Ok(x) => match x.status.code() {
+                Some(rc) => {
+                    let mut out = convert_out(&x.stdout,&x.stderr);
+                    self.trim_newlines(&mut out);
+                    Ok((rc, out))
+                },
+                None => Err((418, String::from("")))
+            },
+            Err(y) => Err((404, format!("failed to run {}: {}", self.runtime, y)))
+        };
+    }
+
+    // task commands have their output shown as it arrives, with stdout and stderr kept apart.  With a timeout, killing the
+    // exec client would leave the command running in the container, so the command reports its process id first and a
+    // second exec kills its process group
+
+    fn run_command_streamed(&self, cmd: &String, timeout: u64, password: &Option<String>, output: &CommandOutput) -> Result<(i32,String),(i32,String)> {
+        let actual_cmd = match timeout {
+            0 => cmd.clone(),
+            _ => { output.expect_pid(); add_pid_marker(cmd) }
+        };
+        // the exec client only passes stdin along when interactive, which is how sudo gets the become password
+        let mut command = self.exec_command(&actual_cmd, password.is_some());
+        let mut child = match command.stdin(get_become_stdin(password)).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(x) => x,
+            Err(y) => { return Err((404, format!("failed to run {}: {}", self.runtime, y))); }
+        };
+        let (timed_out, rc, mut out) = match wait_with_deadline(&mut child, get_deadline(timeout), password, output) {
+            Ok((rc, out)) => (false, rc, out),
+            Err((rc, out)) => (rc == RC_TIMED_OUT, rc, out)
+        };
+        self.trim_newlines(&mut out);
+        if ! timed_out {
+            return match rc { 404 | 418 => Err((rc, out)), _ => Ok((rc, out)) };
+        }
+        let pid = output.get_pid();
+        if pid.is_some() {
+            let _k = self.exec_command(&get_kill_command(pid.as_ref().unwrap()), false).output();
+        }
+        let _c = child.kill();
+        let _w = child.wait();
+        return Err((RC_TIMED_OUT, out));
+    }
+}
+
+impl Connection for ContainerConnection {
+
+    fn whoami(&self) -> Result<String,String> {
+        if self.user.is_some() {
+            return Ok(self.user.as_ref().unwrap().clone());
+        }
+        return match self.run_command_low_level(&String::from("id -un")) {
+            Ok((0,out)) => Ok(out),
+            Ok((rc,out)) => Err(format!("id -un failed in container {}: rc={}, out={}", self.container, rc, out)),
+            Err((rc,out)) => Err(format!("id -un failed in container {}: rc={}, out={}", self.container, rc, out))
+        };
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        // there is nothing to connect to, but uname -a tells us both that the container is running and what OS it has
+        return match self.run_command_low_level(&String::from("uname -a")) {
+            Ok((0,out)) => {
+                match self.host.write().unwrap().set_os_info(&out.clone()) {
+                    Ok(_x) => Ok(()),
+                    Err(_y) => Err(format!("failed to set OS info"))
+                }
+            },
+            Ok((rc,out)) => Err(format!("{} exec into container {} failed: rc={}, out={}", self.runtime, self.container, rc, out)),
+            Err((rc,out)) => Err(format!("{} exec into container {} failed: rc={}, out={}", self.runtime, self.container, rc, out))
+        };
+    }
+
+    fn run_command(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, _forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+        let timeout = request.get_timeout();
+        let password = match get_become_password(request) {
+            Ok(x) => x,
+            Err(y) => { return Err(response.is_failed(request, &y)); }
+        };
+        let output = CommandOutput::new(response, request);
+        let result = self.run_command_streamed(cmd, timeout, &password, &output);
+        return match result {
+            Ok((rc,s)) => {
+                // note that non-zero return codes are "ok" to the connection plugin, handle elsewhere!
+                Ok(response.command_ok(request, &output.get_result(cmd, &s, rc)))
+            },
+            Err((RC_TIMED_OUT,s)) => {
+                Err(response.command_timed_out(request, &output.get_result(cmd, &s, RC_TIMED_OUT)))
+            },
+            Err((rc,s)) => {
+                Err(response.command_failed(request, &output.get_result(cmd, &s, rc)))
+            }
+        };
+    }
+
+    fn write_data(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, data: &String, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        // data is piped into the container rather than staged in a local file, remote.rs takes care of moving it into place
+        let mut command = self.exec_command(&format!("cat > '{}'", remote_path.replace("'", "'\\''")), true);
+        command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = match command.spawn() {
+            Ok(x) => x,
+            Err(y) => { return Err(response.is_failed(request, &format!("failed to run {}: {}", self.runtime, y))); }
+        };
+        {
+            let mut stdin = child.stdin.take().unwrap();
+            match stdin.write_all(data.as_bytes()) {
+                Ok(_) => {},
+                Err(y) => { return Err(response.is_failed(request, &format!("failed to write to container: {}", y))); }
+            }
+        }
+        return match child.wait_with_output() {
+            Ok(x) if x.status.success() => Ok(()),
+            Ok(x) => Err(response.is_failed(request, &format!("failed to write {} in container {}: {}", remote_path, self.container,
+                convert_out(&x.stdout, &x.stderr)))),
+            Err(y) => Err(response.is_failed(request, &format!("failed to write {} in container {}: {}", remote_path, self.container, y)))
+        };
+    }
+
+    fn copy_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
+        let mut command = Command::new(&self.runtime);
+        command.arg("cp").arg(src).arg(format!("{}:{}", self.container, remote_path));
+        return match command.output() {
+            Ok(x) if x.status.success() => Ok(()),
+            Ok(x) => Err(response.is_failed(request, &format!("{} cp to {} failed: {}", self.runtime, self.container,
+                convert_out(&x.stdout, &x.stderr)))),
+            Err(y) => Err(response.is_failed(request, &format!("failed to run {}: {}", self.runtime, y)))
+        };
+    }
+}
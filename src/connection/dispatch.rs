@@ -0,0 +1,125 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::connection::connection::Connection;
+use crate::connection::factory::ConnectionFactory;
+use crate::connection::ssh::SshFactory;
+use crate::connection::local::{LocalFactory,LocalConnection};
+use crate::connection::container::ContainerFactory;
+use crate::connection::winrm::WinRmFactory;
+use crate::connection::chroot::ChrootFactory;
+use crate::playbooks::context::PlaybookContext;
+use crate::inventory::hosts::Host;
+use crate::Inventory;
+use std::sync::{Arc,Mutex,RwLock};
+
+// the dispatch factory lets a single play mix SSH hosts, containers, Windows hosts, chroot directories, and the local machine.
+// Each host may pick its backend with the jet_connection variable, hosts that do not set it use the default for the CLI mode.
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum ConnectionType {
+    Ssh,
+    Local,
+    Container,
+    WinRm,
+    Chroot
+}
+
+impl ConnectionType {
+    pub fn from_string(value: &String) -> Result<Self, String> {
+        return match value.as_str() {
+            "ssh"       => Ok(ConnectionType::Ssh),
+            "local"     => Ok(ConnectionType::Local),
+            "container" => Ok(ConnectionType::Container),
+            "winrm"     => Ok(ConnectionType::WinRm),
+            "chroot"    => Ok(ConnectionType::Chroot),
+            _ => Err(format!("invalid jet_connection value: {} (expecting ssh, local, container, winrm, or chroot)", value))
+        };
+    }
+}
+
+pub struct DispatchFactory {
+    default: ConnectionType,
+    ssh_factory: SshFactory,
+    local_factory: LocalFactory,
+    container_factory: ContainerFactory,
+    winrm_factory: WinRmFactory,
+    chroot_factory: ChrootFactory,
+    localhost: Arc<RwLock<Host>>
+}
+
+impl DispatchFactory {
+    pub fn new(inventory: &Arc<RwLock<Inventory>>, default: ConnectionType, ssh_factory: SshFactory) -> Self {
+        Self {
+            default,
+            ssh_factory,
+            local_factory: LocalFactory::new(inventory),
+            container_factory: ContainerFactory::new(inventory),
+            winrm_factory: WinRmFactory::new(inventory),
+            chroot_factory: ChrootFactory::new(inventory),
+            localhost: inventory.read().expect("inventory read").get_host(&String::from("localhost"))
+        }
+    }
+}
+
+impl ConnectionFactory for DispatchFactory {
+
+    fn get_local_connection(&self, context: &Arc<RwLock<PlaybookContext>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        return self.local_factory.get_connection(context, &self.localhost);
+    }
+
+    fn get_connection(&self, context: &Arc<RwLock<PlaybookContext>>, host:&Arc<RwLock<Host>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        let connection_type = match context.read().expect("context read").get_connection_type(host) {
+            Some(x) => match ConnectionType::from_string(&x) {
+                Ok(y) => y,
+                Err(y) => { return Err(format!("host {}: {}", host.read().expect("host read").name, y)); }
+            },
+            None => self.default
+        };
+        return match connection_type {
+            ConnectionType::Ssh       => self.ssh_factory.get_connection(context, host),
+            ConnectionType::Local     => self.get_local_connection_for_host(context, host),
+            ConnectionType::Container => self.container_factory.get_connection(context, host),
+            ConnectionType::WinRm     => self.winrm_factory.get_connection(context, host),
+            ConnectionType::Chroot    => self.chroot_factory.get_connection(context, host)
+        };
+    }
+}
+
+impl DispatchFactory {
+
+    // an inventory host other than localhost with jet_connection: local still needs its own OS detection (and facts),
+    // so it gets its own local connection rather than sharing the one for localhost
+
+    fn get_local_connection_for_host(&self, context: &Arc<RwLock<PlaybookContext>>, host:&Arc<RwLock<Host>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        if host.read().expect("host read").name.eq("localhost") {
+            return self.local_factory.get_connection(context, host);
+        }
+        let ctx = context.read().expect("context read");
+        let details = String::from("local");
+        {
+            let mut cache = ctx.connection_cache.write().unwrap();
+            if let Some(conn) = cache.get_matching_connection(host, &details) {
+                return Ok(conn);
+            }
+        }
+        let mut conn = LocalConnection::new(host);
+        conn.connect()?;
+        let conn2 : Arc<Mutex<dyn Connection>> = Arc::new(Mutex::new(conn));
+        ctx.connection_cache.write().expect("connection cache write").add_connection(&Arc::clone(&host), &Arc::clone(&conn2), &details);
+        return Ok(conn2);
+    }
+}
@@ -22,7 +22,8 @@ use std::sync::Mutex;
 use std::sync::RwLock;
 use std::marker::{Send,Sync};
 
-// the factory trait that serves as the base for SshFactory, LocalFactory, and NoFactory
+// the factory trait that serves as the base for SshFactory, LocalFactory, ContainerFactory, WinRmFactory, ChrootFactory,
+// DispatchFactory, and NoFactory
 
 pub trait ConnectionFactory : Send + Sync {
 
@@ -18,16 +18,21 @@ use crate::connection::connection::Connection;
 use crate::connection::command::CommandResult;
 use crate::playbooks::context::PlaybookContext;
 use crate::connection::factory::ConnectionFactory;
-use crate::connection::command::Forward;
+use crate::connection::command::{Forward,CommandOutput,OutputKind,RC_TIMED_OUT,get_deadline,get_kill_command,remove_become_marker};
 
 use crate::inventory::hosts::Host;
 use crate::handle::response::Response;
 use crate::tasks::{TaskRequest,TaskResponse};
+use crate::tasks::request::SUDO_PROMPT;
 
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
-use std::process::Command;
+use std::process::{Child,ChildStdin,Command,Stdio};
+use std::os::unix::process::CommandExt;
+use std::io::Read;
+use std::thread;
+use std::time::{Duration,Instant};
 use crate::Inventory;
 use crate::util::io::jet_file_open;
 use std::fs::File;
@@ -115,64 +120,82 @@ impl Connection for LocalConnection {
     fn run_command(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, _forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
         let mut base = Command::new("sh");
         let command = base.arg("-c").arg(cmd).arg("2>&1");
-        match command.output() {
-            Ok(x) => {
-                match x.status.code() {
-                    Some(rc) => {
-                        let mut out = convert_out(&x.stdout,&x.stderr);
-                        self.trim_newlines(&mut out);
-                        return Ok(response.command_ok(request,&Arc::new(Some(CommandResult { cmd: cmd.clone(), out: out.clone(), rc: rc }))));
-                    },
-                    None => {
-                        return Err(response.command_failed(request, &Arc::new(Some(CommandResult { cmd: cmd.clone(), out: String::from(""), rc: 418 }))));
-                    }
-                }
-            },
+        let deadline = get_deadline(request.get_timeout());
+        // a command with a timeout gets its own process group, so that when it runs too long the whole group can be killed.
+        // This is not done otherwise because it also keeps Ctrl-C on the terminal from reaching the command.
+        if deadline.is_some() {
+            command.process_group(0);
+        }
+        let password = match get_become_password(request) {
+            Ok(x) => x,
+            Err(y) => { return Err(response.is_failed(request, &y)); }
+        };
+        let spawned = command.stdin(get_become_stdin(&password)).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+        let mut child = match spawned {
+            Ok(x) => x,
             Err(_x) => {
-                return Err(response.command_failed(request, &Arc::new(Some(CommandResult { cmd: cmd.clone(), out: String::from(""), rc: 404 }))));
+                return Err(response.command_failed(request, &Arc::new(Some(CommandResult { cmd: cmd.clone(), out: String::from(""), stdout: String::from(""), stderr: String::from(""), rc: 404 }))));
+            }
+        };
+        let output = CommandOutput::new(response, request);
+        return match wait_with_deadline(&mut child, deadline, &password, &output) {
+            Ok((rc, mut out)) => {
+                self.trim_newlines(&mut out);
+                Ok(response.command_ok(request, &output.get_result(cmd, &out, rc)))
+            },
+            Err((RC_TIMED_OUT, out)) => {
+                let _k = Command::new("sh").arg("-c").arg(get_kill_command(&format!("{}", child.id()))).output();
+                let _w = child.wait();
+                Err(response.command_timed_out(request, &output.get_result(cmd, &out, RC_TIMED_OUT)))
+            },
+            Err((rc, out)) => {
+                Err(response.command_failed(request, &output.get_result(cmd, &out, rc)))
             }
         };
     }
 
     fn copy_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
-        // FIXME: this (temporary) implementation currently loads the file contents into memory which we do not want
-        // copy the files with system calls instead.
-        let remote_path2 = Path::new(remote_path);
-        let result = std::fs::copy(src, &remote_path2);
-        return match result {
-            Ok(_x) => Ok(()),
+        // streams the file and flushes it to disk, remote.rs then renames it into place
+        let mut src_file = match jet_file_open(src) {
+            Ok(x) => x,
+            Err(y) => return Err(response.is_failed(&request, &y))
+        };
+        let mut file = match File::create(Path::new(remote_path)) {
+            Ok(x) => x,
+            Err(y) => return Err(response.is_failed(&request, &format!("failed to create: {}: {:?}", remote_path, y)))
+        };
+        match std::io::copy(&mut src_file, &mut file) {
+            Ok(_) => {},
             Err(e) => { return Err(response.is_failed(&request, &format!("copy failed: {:?}", e))) }
-        }
+        };
+        return self.sync_file(response, request, &file, remote_path);
     }
 
     fn write_data(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, data: &String, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
-        let path = Path::new(&remote_path);
-        if path.exists() {
-            let mut file = match jet_file_open(path) {
-                Ok(x) => x,
-                Err(y) => return Err(response.is_failed(&request, &format!("failed to open: {}: {:?}", remote_path, y)))
-            };
-            let write_result = write!(file, "{}", data);
-            match write_result {
-                Ok(_) => {},
-                Err(y) => return Err(response.is_failed(&request, &format!("failed to write: {}: {:?}", remote_path, y)))
-            };
-        } else {
-            let mut file = match File::create(&path) {
-                Ok(x) => x,
-                Err(y) => return Err(response.is_failed(&request, &format!("failed to create: {}: {:?}", remote_path, y)))
-            };
-            let write_result = write!(file, "{}", data);
-            match write_result {
-                Ok(_) => {},
-                Err(y) => return Err(response.is_failed(&request, &format!("failed to write: {}: {:?}", remote_path, y)))
-            };
-        }
-        return Ok(());
+        let mut file = match File::create(Path::new(remote_path)) {
+            Ok(x) => x,
+            Err(y) => return Err(response.is_failed(&request, &format!("failed to create: {}: {:?}", remote_path, y)))
+        };
+        let write_result = write!(file, "{}", data);
+        match write_result {
+            Ok(_) => {},
+            Err(y) => return Err(response.is_failed(&request, &format!("failed to write: {}: {:?}", remote_path, y)))
+        };
+        return self.sync_file(response, request, &file, remote_path);
     }
 
 }
 
+impl LocalConnection {
+
+    fn sync_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, file: &File, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        return match file.sync_all() {
+            Ok(_) => Ok(()),
+            Err(y) => Err(response.is_failed(&request, &format!("failed to sync: {}: {:?}", remote_path, y)))
+        };
+    }
+}
+
 pub fn convert_out(output: &Vec<u8>, err: &Vec<u8>) -> String {
     // output from the Rust command class can contain junk bytes, here we mostly don't try to solve this yet
     // and will basically fail if output contains junk. This may be dealt with later.
@@ -189,6 +212,124 @@ pub fn convert_out(output: &Vec<u8>, err: &Vec<u8>) -> String {
     return base.trim().to_string();
 }
 
+// waits for a child started with piped stdout and stderr, which are drained by threads so a chatty command cannot block
+// on a full pipe, and so output can be shown as it arrives.  The combined output is returned, and the streams are kept
+// apart in the CommandOutput.  Past the deadline (if any) this gives up with RC_TIMED_OUT and whatever output there was
+// so far, leaving the child for the caller to kill.
+
+// sudo -S reads the become password from stdin after printing SUDO_PROMPT, which wait_with_deadline answers.  su, doas
+// and run0 insist on a terminal, which only SSH connections set up, so a password for those is an error here.
+
+pub fn get_become_password(request: &Arc<TaskRequest>) -> Result<Option<String>,String> {
+    let password = request.get_become_password();
+    if password.is_some() && request.become_needs_pty() {
+        return Err(String::from("a become password is only supported with become_method sudo on this connection type"));
+    }
+    return Ok(password);
+}
+
+pub fn get_become_stdin(password: &Option<String>) -> Stdio {
+    return match password.is_some() {
+        true  => Stdio::piped(),
+        false => Stdio::null()
+    };
+}
+
+// with a become password the child must have been spawned with get_become_stdin.  The password is written once the
+// prompt shows up on stderr and stdin is closed right after, so a wrong password fails sudo's next read instead of
+// waiting.  Should sudo not ask (cached credentials or NOPASSWD), stdin is closed when BECOME_MARKER shows up, so the
+// command gets end of input rather than waiting on a password that will never be sent.  A custom sudo_template may
+// leave the marker out, in which case stdin is closed as soon as the command prints anything.
+
+struct BecomeState {
+    stdin: Option<ChildStdin>,
+    // the marker has been seen, output no longer needs holding back
+    settled: bool
+}
+
+pub fn wait_with_deadline(child: &mut Child, deadline: Option<Instant>, password: &Option<String>, output: &CommandOutput) -> Result<(i32,String),(i32,String)> {
+    let sudo_state = Arc::new(Mutex::new(BecomeState {
+        stdin: match password.is_some() {
+            true  => child.stdin.take(),
+            false => None
+        },
+        settled: password.is_none()
+    }));
+    let readers = vec![ 
+        drain_pipe(child.stdout.take(), OutputKind::Stdout, output.clone(), &sudo_state, password),
+        drain_pipe(child.stderr.take(), OutputKind::Stderr, output.clone(), &sudo_state, password)
+    ];
+    let mut wait = Duration::from_millis(5);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => { break status; },
+            Ok(None) => {},
+            Err(y) => { return Err((404, y.to_string())); }
+        }
+        if deadline.is_some() && Instant::now() >= deadline.unwrap() {
+            output.finish();
+            return Err((RC_TIMED_OUT, output.get_combined().trim().to_string()));
+        }
+        thread::sleep(wait);
+        wait = std::cmp::min(wait * 2, Duration::from_millis(100));
+    };
+    for reader in readers {
+        let _j = reader.join();
+    }
+    output.finish();
+    return match status.code() {
+        Some(rc) => Ok((rc, output.get_combined().trim().to_string())),
+        None => Err((418, String::from("")))
+    };
+}
+
+fn drain_pipe<R: Read + Send + 'static>(pipe: Option<R>, kind: OutputKind, output: CommandOutput, sudo_state: &Arc<Mutex<BecomeState>>,
+    password: &Option<String>) -> thread::JoinHandle<()> {
+    let sudo_state = Arc::clone(sudo_state);
+    let password = password.clone();
+    return thread::spawn(move || {
+        if pipe.is_none() {
+            return;
+        }
+        let mut pipe = pipe.unwrap();
+        let mut buf = [0u8; 8192];
+        // until sudo is done, output is held back a line at a time so the prompt and the marker can be left out of it.
+        // The marker usually arrives on stderr, but a redirect in the command can send it to stdout instead.
+        let mut held : Vec<u8> = Vec::new();
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => { break; },
+                Ok(n) => n
+            };
+            let mut state = sudo_state.lock().unwrap();
+            if state.settled {
+                output.push(kind, &held);
+                held.clear();
+                output.push(kind, &buf[..n]);
+                continue;
+            }
+            held.extend_from_slice(&buf[..n]);
+            if kind == OutputKind::Stderr && state.stdin.is_some() && held.ends_with(SUDO_PROMPT.as_bytes()) {
+                held.truncate(held.len() - SUDO_PROMPT.len());
+                let _w = state.stdin.take().unwrap().write_all(format!("{}\n", password.as_ref().unwrap()).as_bytes());
+            }
+            if let Some(x) = held.iter().rposition(|b| *b == b'\n') {
+                let mut lines : Vec<u8> = held.drain(..x+1).collect();
+                if remove_become_marker(&mut lines) {
+                    state.stdin = None;
+                    state.settled = true;
+                }
+                output.push(kind, &lines);
+            }
+            if state.stdin.is_some() && output.has_stdout() {
+                // output from the command itself (not just the pid marker) means sudo is done, or never asked
+                state.stdin = None;
+            }
+        }
+        output.push(kind, &held);
+    });
+}
+
 fn detect_os(host: &Arc<RwLock<Host>>) -> Result<(),(i32, String)> {
     // upon connection we run uname -a on connect to check the OS type.
     
@@ -212,3 +353,64 @@ fn detect_os(host: &Arc<RwLock<Host>>) -> Result<(),(i32, String)> {
         Err(_x) => Err((418, String::from("uname -a failed without status code")))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::request::{BecomeMethod,BECOME_MARKER};
+
+    fn run(script: &String, password: &Option<String>) -> (i32, String, CommandOutput) {
+        let mut child = Command::new("sh").arg("-c").arg(script)
+            .stdin(get_become_stdin(password)).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        let output = CommandOutput::none();
+        let deadline = get_deadline(10);
+        return match wait_with_deadline(&mut child, deadline, password, &output) {
+            Ok((rc, out)) => (rc, out, output),
+            Err((rc, out)) => {
+                let _k = child.kill();
+                (rc, out, output)
+            }
+        };
+    }
+
+    // the default template without the sudo in front of it, as sudo runs it when it does not ask for a password
+
+    fn without_sudo(cmd: &str) -> String {
+        let template = BecomeMethod::Sudo.get_default_template(true);
+        let start = template.find(" sh -c ").unwrap();
+        return template[start..].replace("{{jet_command}}", cmd);
+    }
+
+    #[test]
+    fn stdin_is_closed_when_sudo_does_not_prompt() {
+        let password = Some(String::from("secret"));
+        let (rc, out, output) = run(&without_sudo("sh -c 'cat; echo done'"), &password);
+        assert_eq!(rc, 0);
+        assert_eq!(out, "done");
+        assert!(! output.get_combined().contains(BECOME_MARKER));
+
+        // a command that reads before printing anything, and only writes to stderr
+        let (rc, out, _output) = run(&without_sudo("sh -c 'read x; echo \"got [$x]\" >&2'"), &password);
+        assert_eq!(rc, 0);
+        assert_eq!(out, "got []");
+    }
+
+    #[test]
+    fn prompt_is_answered_and_left_out() {
+        let password = Some(String::from("secret"));
+        let script = format!("printf '%s' '{}' >&2; read p; echo {} >&2; echo \"pw=$p\"; cat", SUDO_PROMPT, BECOME_MARKER);
+        let (rc, out, output) = run(&script, &password);
+        assert_eq!(rc, 0);
+        assert_eq!(out, "pw=secret");
+        assert!(! output.get_combined().contains(SUDO_PROMPT));
+    }
+
+    #[test]
+    fn output_without_a_password_is_untouched() {
+        let (rc, out, output) = run(&format!("echo one; echo {} >&2", BECOME_MARKER), &None);
+        assert_eq!(rc, 0);
+        assert!(out.contains("one") && out.contains(BECOME_MARKER));
+        let result = output.get_result(&String::from("cmd"), &out, rc);
+        assert_eq!(result.as_ref().as_ref().unwrap().stderr, BECOME_MARKER);
+    }
+}
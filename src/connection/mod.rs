@@ -17,7 +17,12 @@
 pub mod connection;
 pub mod factory;
 pub mod ssh;
+pub mod ssh_config;
 pub mod local;
+pub mod container;
+pub mod winrm;
+pub mod chroot;
+pub mod dispatch;
 pub mod no;
 pub mod command;
 pub mod cache;
\ No newline at end of file
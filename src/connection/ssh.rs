@@ -15,45 +15,128 @@
 // long with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::connection::connection::Connection;
-use crate::connection::command::CommandResult;
 use crate::connection::factory::ConnectionFactory;
 use crate::playbooks::context::PlaybookContext;
 use crate::connection::local::LocalFactory;
 use crate::tasks::*;
+use crate::tasks::request::SUDO_PROMPT;
 use crate::inventory::hosts::Host;
 use crate::Inventory;
 use crate::handle::response::Response;
-use crate::connection::command::Forward;
-use crate::connection::local::convert_out;
-use std::process::Command;
+use crate::connection::command::{Forward,CommandOutput,OutputKind,RC_TIMED_OUT,get_deadline,add_pid_marker,get_kill_command,remove_become_marker};
+use crate::connection::local::wait_with_deadline;
+use crate::util::encoding::base64_encode;
+use std::process::{Command,Child,Stdio};
 use std::sync::{Arc,Mutex,RwLock};
-use ssh2::Session;
-use std::io::{Read,Write};
+use ssh2::{Session,Channel,CheckResult,KnownHostFileKind,HostKeyType,ErrorCode,OpenFlags,OpenType};
+use std::io::{Read,Write,ErrorKind};
 use std::net::TcpStream;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration,Instant};
 use std::net::ToSocketAddrs;
-use std::fs::File;
+use std::fs::{File,OpenOptions};
+use std::env;
+use std::fs;
+use once_cell::sync::Lazy;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::sync::atomic::{AtomicBool,Ordering};
 
 // implementation for both Ssh Connections and the Ssh Connection factory
 
+// how the key presented by a server is checked against ~/.ssh/known_hosts.  AcceptNew records keys for
+// hosts we have never seen before, Strict requires them to already be there.  A changed key is always an error
+// unless checking is Off.
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum HostKeyChecking {
+    Strict,
+    AcceptNew,
+    Off
+}
+
+// many hosts connect in parallel, so reads and writes of known_hosts are serialized
+
+static KNOWN_HOSTS_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// when a session drops mid-play, operations that had not started on the remote side yet are retried on a new session.
+// commands that were already running when the connection was lost are not repeated, but the next command reconnects.
+
+const RECONNECT_ATTEMPTS: u64 = 3;
+const RC_CONNECTION_LOST: i32 = 503;
+
+// returned when sudo/su/doas asks for the password a second time
+
+const RC_BECOME_FAILED: i32 = 401;
+
+// batches of independent commands each get their own channel on the host's session, so they run in parallel.
+// this stays below the default MaxSessions (10) of OpenSSH servers.
+
+const MAX_PARALLEL_CHANNELS: usize = 8;
+
+// a command from a parallel batch that has been started, but whose output has not been read yet
+
+enum Started {
+    Channel(Channel),
+    Child(Child),
+    NotStarted
+}
+
 pub struct SshFactory {
     local_factory: LocalFactory,
     localhost: Arc<RwLock<Host>>,
     forward_agent: bool,
-    login_password: Option<String>
+    login_password: Option<String>,
+    key_passphrase: Option<String>,
+    host_key_checking: HostKeyChecking
 }
 
 impl SshFactory { 
-    pub fn new(inventory: &Arc<RwLock<Inventory>>, forward_agent: bool, login_password: Option<String>) -> Self { 
+    pub fn new(inventory: &Arc<RwLock<Inventory>>, forward_agent: bool, login_password: Option<String>, key_passphrase: Option<String>,
+        host_key_checking: HostKeyChecking) -> Self { 
         // we create a local connection factory for localhost rather than establishing local connections with SSH
         Self {
             localhost : inventory.read().expect("inventory read").get_host(&String::from("localhost")),
             local_factory: LocalFactory::new(inventory),
             forward_agent,
-            login_password
+            login_password,
+            key_passphrase,
+            host_key_checking
         } 
     }
+
+    // returns an authenticated session to the last hop in a jump host chain, each hop tunnels through the previous one.
+    // sessions are cached per chain so all hosts behind the same bastion share one connection to it.
+
+    fn get_jump_session(&self, ctx: &PlaybookContext, host: &Arc<RwLock<Host>>, hops: &Vec<(String,String,i64,Option<String>)>, 
+        key_file: &Option<String>, connect_timeout: u64, keepalive_interval: u32) -> Result<Session, String> {
+
+        let mut previous : Option<Session> = None;
+        let mut chain : Vec<String> = Vec::new();
+        for (hostname, user, port, hop_key_file) in hops.iter() {
+            chain.push(format!("{}@{}:{}", user, hostname, port));
+            let chain_key = chain.join(",");
+            let cached = ctx.connection_cache.read().expect("connection cache read").get_jump_session(&chain_key);
+            let session = match cached {
+                Some(x) => x,
+                None => {
+                    let hop = SshConnection::new(Arc::clone(host), hostname, user, *port, false, self.login_password.clone(), 
+                        hop_key_file.clone().or(key_file.clone()), self.key_passphrase.clone(), self.host_key_checking, previous.clone(), None,
+                        connect_timeout, keepalive_interval);
+                    let session = match hop.open_session() {
+                        Ok(x) => x,
+                        Err(y) => { return Err(format!("jump host {}: {}", chain_key, y)); }
+                    };
+                    // tunnels to several hosts share this session from their own threads, see open_tunnel
+                    session.set_blocking(false);
+                    ctx.connection_cache.write().expect("connection cache write").add_jump_session(&chain_key, &session);
+                    session
+                }
+            };
+            previous = Some(session);
+        }
+        return Ok(previous.unwrap());
+    }
 }
 
 impl ConnectionFactory for SshFactory {
@@ -72,18 +155,8 @@ impl ConnectionFactory for SshFactory {
             return Ok(conn);
         } 
 
-        {
-            // SSH connections are kept open between tasks generally but cleared at many strategic points during playbook traversal
-            // between plays, in between batches, etc.
-            let cache = ctx.connection_cache.read().unwrap();
-            if cache.has_connection(host) {
-                let conn = cache.get_connection(host);
-                return Ok(conn);
-            }
-        }
-
-        // how we connect to a host depends on some settings of the play (ssh_port, ssh_user), the CLI (--user) and
-        // possibly magic variables on the host.  The context contains all of this logic.
+        // how we connect to a host depends on some settings of the play (ssh_port, ssh_user), the CLI (--user),
+        // ~/.ssh/config, and possibly magic variables on the host.  The context contains all of this logic.
         let (hostname2, user, port) = ctx.get_ssh_connection_details(host);      
         if hostname2.eq("localhost") { 
             // jet_ssh_hostname was set to localhost, which doesn't make a lot of sense but could happen in testing
@@ -92,13 +165,41 @@ impl ConnectionFactory for SshFactory {
             return Ok(conn); 
         }
 
+        // a private key file is optional and is only used if agent authentication does not succeed
+        let key_file = ctx.get_ssh_private_key_file(host);
+
+        // hosts behind a bastion (jet_ssh_jump_host or ProxyJump) are reached through a tunnel over a jump host session
+        let jump_hosts = ctx.get_ssh_jump_hosts(host);
+        let jump_spec = match jump_hosts.is_empty() {
+            true => None,
+            false => Some(jump_hosts.iter().map(|(h,u,p,_)| format!("{}@{}:{}", u, h, p)).collect::<Vec<String>>().join(","))
+        };
+
+        // SSH connections are kept open between tasks generally but cleared at many strategic points during playbook traversal
+        // between plays, in between batches, etc.  One made as another user, port or key is not reused.
+        let details = format!("ssh {}@{}:{} key={:?} jump={:?}", user, hostname2, port, key_file, jump_spec);
+        {
+            let mut cache = ctx.connection_cache.write().unwrap();
+            if let Some(conn) = cache.get_matching_connection(host, &details) {
+                return Ok(conn);
+            }
+        }
+
+        let (connect_timeout, keepalive_interval) = ctx.get_ssh_timeouts(host);
+
+        let jump_session = match jump_hosts.is_empty() {
+            true => None,
+            false => Some(self.get_jump_session(&ctx, host, &jump_hosts, &key_file, connect_timeout, keepalive_interval)?)
+        };
+
         // actually connect here
-        let mut conn = SshConnection::new(Arc::clone(&host), &user, port, self.forward_agent, self.login_password.clone());
+        let mut conn = SshConnection::new(Arc::clone(&host), &hostname2, &user, port, self.forward_agent, self.login_password.clone(), key_file, 
+            self.key_passphrase.clone(), self.host_key_checking, jump_session, jump_spec, connect_timeout, keepalive_interval);
         return match conn.connect() {
             Ok(_)  => { 
                 let conn2 : Arc<Mutex<dyn Connection>> = Arc::new(Mutex::new(conn));
                 ctx.connection_cache.write().expect("connection cache write").add_connection(
-                    &Arc::clone(&host), &Arc::clone(&conn2));
+                    &Arc::clone(&host), &Arc::clone(&conn2), &details);
                 Ok(conn2)
             },
             Err(x) => { Err(x) } 
@@ -108,16 +209,37 @@ impl ConnectionFactory for SshFactory {
  
 pub struct SshConnection {
     pub host: Arc<RwLock<Host>>,
+    pub hostname: String,
     pub username: String,
     pub port: i64,
-    pub session: Option<Session>,
+    pub session: Arc<RwLock<Option<Session>>>,
     pub forward_agent: bool,
-    pub login_password: Option<String>
+    pub login_password: Option<String>,
+    pub key_file: Option<String>,
+    pub key_passphrase: Option<String>,
+    pub host_key_checking: HostKeyChecking,
+    pub jump_session: Option<Session>,
+    pub jump_spec: Option<String>,
+    pub connect_timeout: u64,
+    pub keepalive_interval: u32,
+    lost: Arc<AtomicBool>,
+    keepalive_stop: Arc<AtomicBool>
 }
 
 impl SshConnection {
-    pub fn new(host: Arc<RwLock<Host>>, username: &String, port: i64, forward_agent: bool, login_password: Option<String>) -> Self {
-        Self { host: Arc::clone(&host), username: username.clone(), port, session: None, forward_agent, login_password }
+    pub fn new(host: Arc<RwLock<Host>>, hostname: &String, username: &String, port: i64, forward_agent: bool, login_password: Option<String>, 
+        key_file: Option<String>, key_passphrase: Option<String>, host_key_checking: HostKeyChecking, jump_session: Option<Session>,
+        jump_spec: Option<String>, connect_timeout: u64, keepalive_interval: u32) -> Self {
+        Self { host: Arc::clone(&host), hostname: hostname.clone(), username: username.clone(), port, session: Arc::new(RwLock::new(None)), 
+            forward_agent, login_password, key_file, key_passphrase, host_key_checking, jump_session, jump_spec, connect_timeout, 
+            keepalive_interval, lost: Arc::new(AtomicBool::new(false)), keepalive_stop: Arc::new(AtomicBool::new(false)) }
+    }
+}
+
+impl Drop for SshConnection {
+    fn drop(&mut self) {
+        // lets the keepalive thread exit, which releases its reference to the session
+        self.keepalive_stop.store(true, Ordering::Relaxed);
     }
 }
 
@@ -131,78 +253,21 @@ impl Connection for SshConnection {
 
     fn connect(&mut self) -> Result<(), String> {
 
-        if self.session.is_some() {
+        if self.session.read().unwrap().is_some() {
             // don't re-connect if we are already connected (the code might not try this anyway?)
             return Ok(());
         }
 
-        // derived from docs at https://docs.rs/ssh2/latest/ssh2/
-        let session = match Session::new() { Ok(x) => x, Err(_y) => { return Err(String::from("failed to attach to session")); } };
-        let mut agent = match session.agent() { Ok(x) => x, Err(_y) => { return Err(String::from("failed to acquire SSH-agent")); } };
-        
-
-        // Connect the agent
-        match agent.connect() { Ok(_x) => {}, Err(_y)  => { return Err(String::from("failed to connect to SSH-agent")) }}
-       
-        // currently we don't do anything with listing the identities in SSH agent.  It might be helpful to provide a nice error
-        // if none were detected
-
-        //agent.list_identities().unwrap();
-        //for identity in agent.identities().unwrap() {
-        //    println!("{}", identity.comment());
-        //    let _pubkey = identity.blob();
-        //}
-
-        // Connect to the local SSH server - need to get socketaddrs first in order to use Duration for timeout
-        let seconds = Duration::from_secs(10);
         assert!(!self.host.read().expect("host read").name.eq("localhost"));
-        let connect_str = format!("{host}:{port}", host=self.host.read().expect("host read").name, port=self.port.to_string());
-        // connect with timeout requires SocketAddr objects instead of just connection strings
-        let addrs_iter = connect_str.as_str().to_socket_addrs();
-        
-        // check for errors
-        let mut addrs_iter2 = match addrs_iter { Err(_x) => { return Err(String::from("unable to resolve")); }, Ok(y) => y };
-        let addr = addrs_iter2.next();
-        if ! addr.is_some() { return Err(String::from("unable to resolve(2)"));  }
-        
-        // actually connect (finally) here
-        let tcp = match TcpStream::connect_timeout(&addr.unwrap(), seconds) { Ok(x) => x, _ => { 
-            return Err(format!("SSH connection attempt failed for {}:{}", self.host.read().expect("host read").name, self.port)); } };
-        
-        // new session & handshake
-        let mut sess = match Session::new() { Ok(x) => x, _ => { return Err(String::from("SSH session failed")); } };
-        sess.set_tcp_stream(tcp);
-        match sess.handshake() { Ok(_) => {}, _ => { return Err(String::from("SSH handshake failed")); } } ;
-        
-        //let identities = agent.identities();
-        
-        if self.login_password.is_some() {
-            match sess.userauth_password(&self.username.clone(), self.login_password.clone().unwrap().as_str()) {
-                Ok(_) => {},
-                Err(x) => {
-                    return Err(format!("SSH password authentication failed for user {}: {}", self.username, x));
-                }
-            }
-        } else {
-            // try to authenticate with the identities in the agent
-            match sess.userauth_agent(&self.username) { 
-                Ok(_) => {}, 
-                Err(x) => { 
-                    return Err(format!("SSH agent authentication failed for user {}: {}", self.username, x));
-                }
-            };
-        }
-
-
+        let sess = self.open_session()?;
 
-        if !(sess.authenticated()) { return Err("failed to authenticate".to_string()); };
-      
         // OS detection -- always run uname -a on first connect so we know the OS type, which will allow the command library and facts
         // module to work correctly.
 
-        self.session = Some(sess);
+        *self.session.write().unwrap() = Some(sess);
+        self.start_keepalive();
 
-        let uname_result = self.run_command_low_level(&String::from("uname -a"));
+        let uname_result = self.run_command_low_level(&String::from("uname -a"), &None, false, None, &CommandOutput::none());
         match uname_result {
             Ok((_rc,out)) => {
                 {
@@ -221,54 +286,86 @@ impl Connection for SshConnection {
     }
 
     fn run_command(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+        let output = CommandOutput::new(response, request);
         let result = match forward {   
             Forward::Yes => match self.forward_agent {
-                false => self.run_command_low_level(cmd),
-                true  => self.run_command_with_ssh_a(cmd)
+                false => self.run_command_with_reconnect(response, request, cmd, &output)?,
+                true  => self.run_command_with_ssh_a(cmd, get_deadline(request.get_timeout()), &output)
             },
-            Forward::No => self.run_command_low_level(cmd)
+            Forward::No => self.run_command_with_reconnect(response, request, cmd, &output)?
         };
+        return self.command_response(response, request, cmd, result, &output);
+    }
 
-        match result {
-            Ok((rc,s)) => {
-                // note that non-zero return codes are "ok" to the connection plugin, handle elsewhere!
-                return Ok(response.command_ok(request, &Arc::new(Some(CommandResult { cmd: cmd.clone(), out: s.clone(), rc: rc }))));
-            }, 
-            Err((rc,s)) => {
-                return Err(response.command_failed(request, &Arc::new(Some(CommandResult { cmd: cmd.clone(), out: s.clone(), rc: rc }))));
+    fn run_commands(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmds: &Vec<(String,Forward)>) -> Vec<Result<Arc<TaskResponse>,Arc<TaskResponse>>> {
+        let mut results = Vec::new();
+        for batch in cmds.chunks(MAX_PARALLEL_CHANNELS) {
+            let mut attempt = 0;
+            match self.reconnect_if_lost(response, request, &mut attempt) {
+                Ok(_) => {},
+                Err(x) => {
+                    for _ in batch.iter() { results.push(Err(Arc::clone(&x))); }
+                    continue;
+                }
+            }
+            // every command in the batch is started before any output is read, so they all run at the same time on the remote
+            let password = request.get_become_password();
+            let pty = password.is_some() && request.become_needs_pty();
+            let deadline = get_deadline(request.get_timeout());
+            let started : Vec<Started> = batch.iter().map(|(cmd, forward)| self.start_command(cmd, *forward, pty, deadline.is_some())).collect();
+            for ((cmd, forward), start) in batch.iter().zip(started.into_iter()) {
+                let output = CommandOutput::new(response, request);
+                let result = match start {
+                    Started::Channel(channel) => self.finish_channel(channel, &password, pty, deadline, &output),
+                    Started::Child(child) => self.finish_ssh_a(child, deadline, &output),
+                    // the command never started, so the sequential path (which knows how to reconnect) can safely try again
+                    Started::NotStarted => { results.push(self.run_command(response, request, cmd, *forward)); continue; }
+                };
+                results.push(self.command_response(response, request, cmd, result, &output));
             }
         }
+        return results;
     }
 
     fn write_data(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, data: &String, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        // remote.rs always writes to a temp path, so a transfer interrupted by a dropped connection can just be started over
+        let mut attempt = 0;
+        loop {
+            self.reconnect_if_lost(response, request, &mut attempt)?;
+            match self.write_data_low_level(response, request, data, remote_path) {
+                Err(_) if self.lost.load(Ordering::Relaxed) && attempt < RECONNECT_ATTEMPTS => { continue; },
+                x => { return x; }
+            }
+        }
+    }
+
+    fn copy_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
+        let mut attempt = 0;
+        loop {
+            self.reconnect_if_lost(response, request, &mut attempt)?;
+            match self.copy_file_low_level(response, request, src, remote_path) {
+                Err(_) if self.lost.load(Ordering::Relaxed) && attempt < RECONNECT_ATTEMPTS => { continue; },
+                x => { return x; }
+            }
+        }
+    }
+}
+
+impl SshConnection {
+
+    fn write_data_low_level(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, data: &String, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
 
         // SFTP writing does not allow root to overwrite files root does not own, and does not support sudo. 
-        // as such this is a pretty low level write (as is copy_file) and logic around tempfiles and permissions is handled in remote.rs
+        // as such this is a pretty low level write (as is copy_file) and logic around tempfiles, permissions, and the
+        // final (atomic) rename into place is handled in remote.rs
 
         // write_data writes a string and is really meant for small files like the template module. Large files should use copy_file instead.
 
-        let session = self.session.as_ref().expect("session not established");
-        let sftp_result = session.sftp();
-        let sftp = match sftp_result {
-            Ok(x) => x,
-            Err(y) => { return Err(response.is_failed(request, &format!("sftp connection failed: {y}"))); }
-        };
-        let sftp_path = Path::new(&remote_path);
-        let fh_result = sftp.create(sftp_path);
-        let mut fh = match fh_result {
-            Ok(x) => x,
-            Err(y) => { return Err(response.is_failed(request, &format!("sftp open failed: {y}"))) }
-        };
         let bytes = data.as_bytes();
-        match fh.write_all(bytes) {
-            Ok(_x) => {},
-            Err(y) => { return Err(response.is_failed(request, &format!("sftp write failed: {y}"))); }
-        }
-
-        return Ok(());
+        return self.sftp_stream(response, request, &mut &bytes[..], bytes.len() as u64, remote_path);
     }
 
-    fn copy_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
+    fn copy_file_low_level(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
 
         // this is a streaming copy that should be fine with large files.
 
@@ -277,43 +374,244 @@ impl Connection for SshConnection {
             Ok(x) => x,
             Err(y) => { return Err(response.is_failed(request, &format!("failed to open source file: {y}"))); }
         };
+        let total = match src.metadata() {
+            Ok(x) => x.len(),
+            Err(y) => { return Err(response.is_failed(request, &format!("failed to stat source file: {y}"))); }
+        };
+        return self.sftp_stream(response, request, &mut src, total, remote_path);
+    }
 
-        let session = self.session.as_ref().expect("session not established");
+    // streams data into a new remote file, which is only readable by the connecting user until remote.rs applies the
+    // requested attributes, and is flushed to disk before returning so that a rename afterwards never exposes a partial file.
+
+    fn sftp_stream<R: Read>(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &mut R, total: u64, remote_path: &String) 
+        -> Result<(), Arc<TaskResponse>> {
+
+        let session = self.get_session();
         let sftp_result = session.sftp();
         let sftp = match sftp_result {
             Ok(x) => x,
-            Err(y) => { return Err(response.is_failed(request, &format!("sftp connection failed: {y}"))); }
+            Err(y) => { self.check_lost(&y); return Err(response.is_failed(request, &format!("sftp connection failed: {y}"))); }
         };
         let sftp_path = Path::new(&remote_path);
-        let fh_result = sftp.create(sftp_path);
+        let fh_result = sftp.open_mode(sftp_path, OpenFlags::WRITE | OpenFlags::TRUNCATE, 0o600, OpenType::File);
         let mut fh = match fh_result {
             Ok(x) => x,
-            Err(y) => { return Err(response.is_failed(request, &format!("sftp write failed (1): {y}"))) }
+            Err(y) => { self.check_lost(&y); return Err(response.is_failed(request, &format!("sftp open failed: {y}"))) }
         };
 
-        let chunk_size = 64536;
+        let chunk_size = 65536;
+        let mut chunk = vec![0u8; chunk_size];
+        let mut transferred : u64 = 0;
+        let mut last_reported : u64 = 0;
+        let visitor = response.get_visitor();
+        let context = response.get_context();
 
         loop {
-            let mut chunk = Vec::with_capacity(chunk_size);
-            let mut taken = std::io::Read::by_ref(&mut src).take(chunk_size as u64);
-            let take_result = taken.read_to_end(&mut chunk);
-            let n = match take_result {
+            let n = match src.read(&mut chunk) {
                 Ok(x) => x,
                 Err(y) => { return Err(response.is_failed(request, &format!("failed during file transfer: {y}"))); }
             };
             if n == 0 { break; }
-            match fh.write(&chunk) {
+            match fh.write_all(&chunk[..n]) {
                 Err(y) => { return Err(response.is_failed(request, &format!("sftp write failed: {y}"))); }
                 _ => {},
-
+            }
+            transferred = transferred + n as u64;
+            // report progress in steps of 10% so that large transfers do not flood the output
+            if total > 0 && (transferred - last_reported) * 10 >= total {
+                visitor.read().expect("read visitor").on_transfer_progress(&context, &self.host, remote_path, transferred, total);
+                last_reported = transferred;
             }
         }
+
+        match fh.fsync() {
+            Ok(_) => {},
+            // not all SFTP servers support the fsync extension, in which case closing the file is the best we can do
+            Err(y) if y.code() == ErrorCode::SFTP(8) => {},
+            Err(y) => { self.check_lost(&y); return Err(response.is_failed(request, &format!("sftp fsync failed: {y}"))); }
+        }
+        match fh.close() {
+            Ok(_) => {},
+            Err(y) => { self.check_lost(&y); return Err(response.is_failed(request, &format!("sftp close failed: {y}"))); }
+        }
         return Ok(());
     }
 }
 
 impl SshConnection {
 
+    // opens and authenticates a session to self.hostname, this is used both for managed hosts and for jump hosts
+
+    fn open_session(&self) -> Result<Session, String> {
+
+        // new session, carried over a tunnel through the jump host if there is one, otherwise a direct TCP connection
+        let mut sess = match Session::new() { Ok(x) => x, _ => { return Err(String::from("SSH session failed")); } };
+        match &self.jump_session {
+            Some(jump) => { sess.set_tcp_stream(open_tunnel(jump, &self.hostname, self.port)?); },
+            None => { sess.set_tcp_stream(self.connect_tcp()?); }
+        }
+        // the connect timeout also bounds the handshake and authentication, but not commands that run afterwards
+        sess.set_timeout((self.connect_timeout * 1000) as u32);
+        match sess.handshake() { Ok(_) => {}, _ => { return Err(String::from("SSH handshake failed")); } } ;
+
+        // the host key is verified before any credentials are offered to the server
+        self.check_host_key(&sess)?;
+
+        // authentication methods are tried in a fixed order: SSH agent, then a private key file (if jet_ssh_private_key_file is
+        // set), then a password (if --ask-login-password was used). We record what was tried so failures are easier to debug.
+
+        let mut tried : Vec<String> = Vec::new();
+        self.authenticate_with_agent(&sess, &mut tried);
+        if !sess.authenticated() {
+            self.authenticate_with_key_file(&sess, &mut tried);
+        }
+        if !sess.authenticated() {
+            self.authenticate_with_password(&sess, &mut tried);
+        }
+        if !(sess.authenticated()) {
+            return Err(format!("SSH authentication failed for user {}, methods tried: {}", self.username, tried.join(", ")));
+        }
+        sess.set_timeout(0);
+        sess.set_keepalive(false, self.keepalive_interval);
+        return Ok(sess);
+    }
+
+    fn connect_tcp(&self) -> Result<TcpStream, String> {
+
+        // Connect to the SSH server - need to get socketaddrs first in order to use Duration for timeout
+        let seconds = Duration::from_secs(self.connect_timeout);
+        let connect_str = format!("{host}:{port}", host=self.hostname, port=self.port.to_string());
+        // connect with timeout requires SocketAddr objects instead of just connection strings
+        let addrs_iter = connect_str.as_str().to_socket_addrs();
+
+        // check for errors
+        let mut addrs_iter2 = match addrs_iter { Err(_x) => { return Err(String::from("unable to resolve")); }, Ok(y) => y };
+        let addr = addrs_iter2.next();
+        if ! addr.is_some() { return Err(String::from("unable to resolve(2)"));  }
+
+        // actually connect (finally) here
+        return match TcpStream::connect_timeout(&addr.unwrap(), seconds) {
+            Ok(x) => Ok(x),
+            _ => Err(format!("SSH connection attempt failed for {}:{}", self.hostname, self.port))
+        };
+    }
+
+    fn check_host_key(&self, sess: &Session) -> Result<(), String> {
+        if self.host_key_checking == HostKeyChecking::Off {
+            return Ok(());
+        }
+        let (key, key_type) = match sess.host_key() {
+            Some(x) => x,
+            None => { return Err(String::from("SSH server did not present a host key")); }
+        };
+        let home = match env::var("HOME") {
+            Ok(x) => x,
+            Err(_) => { return Err(String::from("cannot check host keys, $HOME is not set")); }
+        };
+        let ssh_dir = Path::new(&home).join(".ssh");
+        let known_hosts_path = ssh_dir.join("known_hosts");
+
+        let _guard = KNOWN_HOSTS_LOCK.lock().unwrap();
+        let mut known_hosts = match sess.known_hosts() {
+            Ok(x) => x,
+            Err(y) => { return Err(format!("failed to initialize known hosts: {}", y)); }
+        };
+        if known_hosts_path.is_file() {
+            match known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH) {
+                Ok(_) => {},
+                Err(y) => { return Err(format!("failed to read {}: {}", known_hosts_path.display(), y)); }
+            }
+        }
+
+        // known_hosts entries for non-standard ports are written as [host]:port
+        let entry_name = match self.port {
+            22 => self.hostname.clone(),
+            _  => format!("[{}]:{}", self.hostname, self.port)
+        };
+
+        return match known_hosts.check_port(&self.hostname, self.port as u16, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(format!("host key for {} does not match {}, refusing to connect (possible man-in-the-middle attack)", 
+                entry_name, known_hosts_path.display())),
+            CheckResult::Failure => Err(format!("failed to check host key for {}", entry_name)),
+            CheckResult::NotFound => match self.host_key_checking {
+                HostKeyChecking::Strict => Err(format!("host key for {} is not in {} and --host-key-checking is strict", 
+                    entry_name, known_hosts_path.display())),
+                _ => {
+                    if ! ssh_dir.is_dir() {
+                        match fs::create_dir_all(&ssh_dir) {
+                            Ok(_) => {},
+                            Err(y) => { return Err(format!("failed to create {}: {}", ssh_dir.display(), y)); }
+                        }
+                    }
+                    // the new key is appended rather than having libssh2 write the file back out, as that would lose
+                    // the comments and @cert-authority/@revoked lines it does not understand
+                    let key_name = match key_type {
+                        HostKeyType::Rsa       => "ssh-rsa",
+                        HostKeyType::Dss       => "ssh-dss",
+                        HostKeyType::Ecdsa256  => "ecdsa-sha2-nistp256",
+                        HostKeyType::Ecdsa384  => "ecdsa-sha2-nistp384",
+                        HostKeyType::Ecdsa521  => "ecdsa-sha2-nistp521",
+                        HostKeyType::Ed255219  => "ssh-ed25519",
+                        HostKeyType::Unknown   => { return Err(format!("host key for {} is of an unknown type", entry_name)); }
+                    };
+                    let needs_newline = match fs::read(&known_hosts_path) {
+                        Ok(x) => ! x.is_empty() && ! x.ends_with(b"\n"),
+                        Err(_) => false
+                    };
+                    let line = format!("{}{} {} {}\n", match needs_newline { true => "\n", false => "" }, entry_name, key_name, base64_encode(key));
+                    let result = OpenOptions::new().create(true).append(true).open(&known_hosts_path).and_then(|mut f| f.write_all(line.as_bytes()));
+                    match result {
+                        Ok(_) => Ok(()),
+                        Err(y) => Err(format!("failed to write {}: {}", known_hosts_path.display(), y))
+                    }
+                }
+            }
+        };
+    }
+
+    fn authenticate_with_agent(&self, sess: &Session, tried: &mut Vec<String>) {
+        // without a running agent there is nothing to ask, which is common on CI runners
+        if env::var("SSH_AUTH_SOCK").is_err() {
+            tried.push(String::from("agent (SSH_AUTH_SOCK not set)"));
+            return;
+        }
+        match sess.userauth_agent(&self.username) {
+            Ok(_) => {},
+            Err(x) => { tried.push(format!("agent ({})", x)); }
+        };
+    }
+
+    fn authenticate_with_key_file(&self, sess: &Session, tried: &mut Vec<String>) {
+        if self.key_file.is_none() {
+            tried.push(String::from("private key (jet_ssh_private_key_file not set)"));
+            return;
+        }
+        let key_file = self.key_file.as_ref().unwrap();
+        let key_path = Path::new(key_file);
+        if ! key_path.is_file() {
+            tried.push(format!("private key ({}: no such file)", key_file));
+            return;
+        }
+        let passphrase = self.key_passphrase.as_ref().map(|x| x.as_str());
+        match sess.userauth_pubkey_file(&self.username, None, key_path, passphrase) {
+            Ok(_) => {},
+            Err(x) => { tried.push(format!("private key ({}: {})", key_file, x)); }
+        };
+    }
+
+    fn authenticate_with_password(&self, sess: &Session, tried: &mut Vec<String>) {
+        if self.login_password.is_none() {
+            tried.push(String::from("password (--ask-login-password not given)"));
+            return;
+        }
+        match sess.userauth_password(&self.username, self.login_password.as_ref().unwrap().as_str()) {
+            Ok(_) => {},
+            Err(x) => { tried.push(format!("password ({})", x)); }
+        };
+    }
+
     fn trim_newlines(&self, s: &mut String) {
         if s.ends_with('\n') {
             s.pop();
@@ -323,51 +621,497 @@ impl SshConnection {
         }
     }
 
-    fn run_command_low_level(&self, cmd: &String) -> Result<(i32,String),(i32,String)> {
-        // FIXME: catch the rare possibility this unwrap fails and return a nice error?
-        let session = self.session.as_ref().unwrap();
+    fn get_session(&self) -> Session {
+        return self.session.read().unwrap().as_ref().expect("session not established").clone();
+    }
+
+    // errors at the socket level mean the session is gone for good, the next operation will need a new one
+
+    fn check_lost(&self, err: &ssh2::Error) {
+        match err.code() {
+            ErrorCode::Session(-7) | ErrorCode::Session(-13) | ErrorCode::Session(-30) | ErrorCode::Session(-43) => {
+                self.lost.store(true, Ordering::Relaxed);
+            },
+            _ => {}
+        }
+    }
+
+    fn reconnect_if_lost(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, attempt: &mut u64) -> Result<(), Arc<TaskResponse>> {
+        while self.lost.load(Ordering::Relaxed) {
+            if *attempt >= RECONNECT_ATTEMPTS {
+                return Err(response.is_failed(request, &format!("connection to {} lost, gave up after {} reconnect attempts", self.hostname, attempt)));
+            }
+            *attempt = *attempt + 1;
+            response.get_visitor().read().expect("read visitor").on_host_reconnect(&response.get_context(), &self.host, *attempt, 
+                &format!("{}:{}", self.hostname, self.port));
+            match self.open_session() {
+                Ok(sess) => {
+                    *self.session.write().unwrap() = Some(sess);
+                    self.lost.store(false, Ordering::Relaxed);
+                },
+                Err(y) => {
+                    if *attempt >= RECONNECT_ATTEMPTS {
+                        return Err(response.is_failed(request, &format!("reconnect to {} failed: {}", self.hostname, y)));
+                    }
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn run_command_with_reconnect(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, output: &CommandOutput) 
+        -> Result<Result<(i32,String),(i32,String)>, Arc<TaskResponse>> {
+        let mut attempt = 0;
+        loop {
+            self.reconnect_if_lost(response, request, &mut attempt)?;
+            let password = request.get_become_password();
+            let deadline = get_deadline(request.get_timeout());
+            match self.run_command_low_level(cmd, &password, password.is_some() && request.become_needs_pty(), deadline, output) {
+                // only a lost session is worth retrying (reconnect_if_lost counts the attempt), a channel the server
+                // refuses, such as when over its MaxSessions limit, would be refused again
+                Err((RC_CONNECTION_LOST,_)) if self.lost.load(Ordering::Relaxed) && attempt < RECONNECT_ATTEMPTS => { continue; },
+                x => { return Ok(x); }
+            }
+        }
+    }
+
+    // keepalives are only sent by libssh2 when asked, so a background thread asks for as long as the connection is alive.
+    // this is also how a dead session is noticed between tasks.
+
+    fn start_keepalive(&self) {
+        if self.keepalive_interval == 0 {
+            return;
+        }
+        let session = Arc::clone(&self.session);
+        let lost = Arc::clone(&self.lost);
+        let stop = Arc::clone(&self.keepalive_stop);
+        thread::spawn(move || {
+            let mut wait : u32 = 1;
+            loop {
+                for _ in 0..wait {
+                    thread::sleep(Duration::from_secs(1));
+                    if stop.load(Ordering::Relaxed) { return; }
+                }
+                let current = session.read().unwrap().clone();
+                wait = match current {
+                    Some(sess) => match sess.keepalive_send() {
+                        Ok(n) => std::cmp::max(n, 1),
+                        // commands are read with the session in non-blocking mode, see read_channel
+                        Err(y) if y.code() == ErrorCode::Session(-37) => 1,
+                        Err(_) => { lost.store(true, Ordering::Relaxed); 1 }
+                    },
+                    None => 1
+                };
+            }
+        });
+    }
+
+    fn command_response(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, result: Result<(i32,String),(i32,String)>,
+        output: &CommandOutput) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+        return match result {
+            Ok((rc,s)) => {
+                // note that non-zero return codes are "ok" to the connection plugin, handle elsewhere!
+                Ok(response.command_ok(request, &output.get_result(cmd, &s, rc)))
+            }, 
+            Err((RC_TIMED_OUT,s)) => {
+                Err(response.command_timed_out(request, &output.get_result(cmd, &s, RC_TIMED_OUT)))
+            },
+            Err((rc,s)) => {
+                Err(response.command_failed(request, &output.get_result(cmd, &s, rc)))
+            }
+        };
+    }
+
+    fn run_command_low_level(&self, cmd: &String, password: &Option<String>, pty: bool, deadline: Option<Instant>, output: &CommandOutput) 
+        -> Result<(i32,String),(i32,String)> {
+        let channel = self.start_channel(cmd, pty, deadline.is_some())?;
+        return self.finish_channel(channel, password, pty, deadline, output);
+    }
+
+    // commands that may time out report their process id first, see add_pid_marker
+
+    fn start_channel(&self, cmd: &String, pty: bool, timed: bool) -> Result<Channel,(i32,String)> {
+        let session = self.get_session();
+        // failures before the command starts are safe to retry on a new session, so they get their own return code
         let mut channel = match session.channel_session() {
             Ok(x) => x,
-            Err(y) => { return Err((500, format!("channel session failed: {:?}", y))); }
+            Err(y) => { 
+                self.check_lost(&y);
+                return Err((RC_CONNECTION_LOST, format!("channel session failed: {:?}", y))); 
+            }
         };
-        let actual_cmd = format!("{} 2>&1", cmd);
-        match channel.exec(&actual_cmd) { Ok(_x) => {}, Err(y) => { return Err((500,y.to_string())) } };
-        let mut s = String::new();
-        match channel.read_to_string(&mut s) { Ok(_x) => {}, Err(y) => { return Err((500,y.to_string())) } };
-        // BOOKMARK: add sudo password prompt (configurable) support here (and below)
+        // su and doas only read passwords from a terminal, so commands that may be asked for one get a PTY
+        if pty {
+            match channel.request_pty("dumb", None, None) { 
+                Ok(_x) => {}, 
+                Err(y) => { self.check_lost(&y); return Err((RC_CONNECTION_LOST, format!("pty request failed: {:?}", y))) } 
+            };
+        }
+        let actual_cmd = match timed {
+            true  => add_pid_marker(cmd),
+            false => cmd.clone()
+        };
+        match channel.exec(&actual_cmd) { Ok(_x) => {}, Err(y) => { self.check_lost(&y); return Err((RC_CONNECTION_LOST,y.to_string())) } };
+        return Ok(channel);
+    }
+
+    fn finish_channel(&self, mut channel: Channel, password: &Option<String>, pty: bool, deadline: Option<Instant>, output: &CommandOutput) 
+        -> Result<(i32,String),(i32,String)> {
+        if deadline.is_some() {
+            output.expect_pid();
+        }
+        self.read_channel(&mut channel, password, pty, deadline, output)?;
+        let mut s = output.get_combined();
+        if pty {
+            // terminals translate newlines
+            s = s.replace("\r\n", "\n");
+        }
         let _w = channel.wait_close();
         let exit_status = match channel.exit_status() { Ok(x) => x, Err(y) => { return Err((500,y.to_string())) } };
         self.trim_newlines(&mut s);
         return Ok((exit_status, s.clone()));
     }
 
-    fn run_command_with_ssh_a(&self, cmd: &String) -> Result<(i32,String),(i32,String)> {
+    // reads all output from a channel, stdout and stderr as they arrive, and answers the first password prompt from the
+    // become tool (on the terminal for su/doas/run0, or on stderr for sudo -S).  While there is a password, output is
+    // held back a line at a time so the prompt (and the BECOME_MARKER line) never becomes part of it.  A second prompt means the password was not
+    // accepted, and the command is abandoned rather than left waiting for input.  Neither stream may be left unread
+    // while waiting on the other, so the session is non-blocking while reading.  This also makes the deadline easy to
+    // check; a command that is still running at the deadline is killed using the process id it printed first.
+
+    fn read_channel(&self, channel: &mut Channel, password: &Option<String>, pty: bool, deadline: Option<Instant>, output: &CommandOutput) 
+        -> Result<(),(i32,String)> {
+        let mut answered = false;
+        let mut echoed_newline = false;
+        let mut buf = [0u8; 8192];
+        let mut held : [Vec<u8>; 2] = [Vec::new(), Vec::new()];
+        let kinds = [OutputKind::Stdout, OutputKind::Stderr];
+        let mut idle = Duration::from_millis(1);
+        let session = self.get_session();
+        session.set_blocking(false);
+        loop {
+            let mut progress = false;
+            for (index, kind) in kinds.iter().enumerate() {
+                let read = match kind {
+                    OutputKind::Stdout => channel.read(&mut buf),
+                    OutputKind::Stderr => channel.stderr().read(&mut buf)
+                };
+                let n = match read {
+                    Ok(n) => n,
+                    Err(y) if y.kind() == ErrorKind::WouldBlock => 0,
+                    Err(y) => { session.set_blocking(true); self.lost.store(true, Ordering::Relaxed); return Err((500,y.to_string())) }
+                };
+                if n == 0 {
+                    continue;
+                }
+                progress = true;
+                if password.is_none() {
+                    output.push(*kind, &buf[..n]);
+                    continue;
+                }
+                let bytes = &mut held[index];
+                bytes.extend_from_slice(&buf[..n]);
+                // the terminal echoes the newline that ended the password
+                if echoed_newline && *kind == OutputKind::Stdout {
+                    if bytes.starts_with(b"\r\n") || bytes.starts_with(b"\n") {
+                        bytes.drain(..bytes.iter().position(|b| *b == b'\n').unwrap() + 1);
+                        echoed_newline = false;
+                    } else if bytes.len() > 1 || bytes[0] != b'\r' {
+                        echoed_newline = false;
+                    }
+                }
+                if is_password_prompt(bytes, pty) {
+                    if answered {
+                        session.set_blocking(true);
+                        let _c = channel.close();
+                        return Err((RC_BECOME_FAILED, String::from("the become password was not accepted")));
+                    }
+                    match write_all_nonblocking(channel, format!("{}\n", password.as_ref().unwrap()).as_bytes()) {
+                        Ok(_x) => {},
+                        Err(y) => { session.set_blocking(true); return Err((500, format!("failed to answer password prompt: {}", y))) }
+                    }
+                    answered = true;
+                    echoed_newline = pty;
+                    bytes.clear();
+                    continue;
+                }
+                if let Some(x) = bytes.iter().rposition(|b| *b == b'\n') {
+                    let mut lines : Vec<u8> = bytes.drain(..x+1).collect();
+                    remove_become_marker(&mut lines);
+                    output.push(*kind, &lines);
+                }
+            }
+            if progress {
+                idle = Duration::from_millis(1);
+                continue;
+            }
+            if channel.eof() {
+                break;
+            }
+            if deadline.is_some() && Instant::now() >= deadline.unwrap() {
+                session.set_blocking(true);
+                let pid = output.get_pid();
+                if pid.is_some() {
+                    self.kill_remote(pid.as_ref().unwrap());
+                }
+                let _c = channel.close();
+                output.push(OutputKind::Stdout, &held[0]);
+                output.push(OutputKind::Stderr, &held[1]);
+                output.finish();
+                return Err((RC_TIMED_OUT, output.get_combined().trim_end().to_string()));
+            }
+            thread::sleep(idle);
+            idle = std::cmp::min(idle * 2, Duration::from_millis(10));
+        }
+        session.set_blocking(true);
+        output.push(OutputKind::Stdout, &held[0]);
+        output.push(OutputKind::Stderr, &held[1]);
+        output.finish();
+        return Ok(());
+    }
+
+    // the channel of a command that timed out is still busy, so the kill goes over a new one
+
+    fn kill_remote(&self, pid: &String) {
+        let session = self.get_session();
+        session.set_timeout((self.connect_timeout * 1000) as u32);
+        match session.channel_session() {
+            Ok(mut channel) => {
+                if channel.exec(&get_kill_command(pid)).is_ok() {
+                    let mut s = String::new();
+                    let _r = channel.read_to_string(&mut s);
+                    let _w = channel.wait_close();
+                }
+            },
+            Err(y) => { self.check_lost(&y); }
+        }
+        session.set_timeout(0);
+    }
+
+    fn start_command(&self, cmd: &String, forward: Forward, pty: bool, timed: bool) -> Started {
+        if forward == Forward::Yes && self.forward_agent {
+            return match self.start_ssh_a(cmd, timed) {
+                Ok(child) => Started::Child(child),
+                Err(_) => Started::NotStarted
+            };
+        }
+        return match self.start_channel(cmd, pty, timed) {
+            Ok(channel) => Started::Channel(channel),
+            Err(_) => Started::NotStarted
+        };
+    }
+
+    fn run_command_with_ssh_a(&self, cmd: &String, deadline: Option<Instant>, output: &CommandOutput) -> Result<(i32,String),(i32,String)> {
+        let child = self.start_ssh_a(cmd, deadline.is_some())?;
+        return self.finish_ssh_a(child, deadline, output);
+    }
+
+    fn start_ssh_a(&self, cmd: &String, timed: bool) -> Result<Child,(i32,String)> {
         // this is annoying but libssh2 agent support is not really working, so if we need to SSH -A we need to invoke
         // SSHd directly, which we need to for example with git clones. we will likely use this again
         // for fanout support.
 
         let mut base = Command::new("ssh");
-        let hostname = &self.host.read().unwrap().name;
+        let hostname = &self.hostname;
         let port = format!("{}", self.port);
-        let cmd2 = format!("{} 2>&1", cmd);
+        let cmd2 = match timed {
+            true  => add_pid_marker(cmd),
+            false => cmd.clone()
+        };
+        if self.jump_spec.is_some() {
+            base.arg("-J").arg(self.jump_spec.as_ref().unwrap());
+        }
         let command = base.arg(hostname).arg("-p").arg(port).arg("-l").arg(self.username.clone()).arg("-A").arg(cmd2);
-        match command.output() {
-            Ok(x) => {
-                match x.status.code() {
-                    Some(rc) => {
-                        let mut out = convert_out(&x.stdout,&x.stderr);
-                        self.trim_newlines(&mut out);
-                        return Ok((rc, out.clone()))
-                    },
-                    None => {
-                        return Ok((418, String::from("")))
-                    }
+        return match command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(x) => Ok(x),
+            Err(_x) => Err((404, String::from("")))
+        };
+    }
+
+    // output is shown as it arrives.  With a deadline, killing the local ssh client is not enough to stop the remote
+    // command, so the remote process group is killed too
+
+    fn finish_ssh_a(&self, mut child: Child, deadline: Option<Instant>, output: &CommandOutput) -> Result<(i32,String),(i32,String)> {
+        if deadline.is_some() {
+            output.expect_pid();
+        }
+        let (timed_out, rc, mut out) = match wait_with_deadline(&mut child, deadline, &None, output) {
+            Ok((rc, out)) => (false, rc, out),
+            Err((rc, out)) => (rc == RC_TIMED_OUT, rc, out)
+        };
+        self.trim_newlines(&mut out);
+        if ! timed_out {
+            return match rc { 404 => Err((rc, out)), _ => Ok((rc, out)) };
+        }
+        let pid = output.get_pid();
+        if pid.is_some() {
+            self.kill_remote(pid.as_ref().unwrap());
+        }
+        let _c = child.kill();
+        let _w = child.wait();
+        return Err((RC_TIMED_OUT, out));
+    }
+
+}
+
+// become tools prompt with something like "[sudo] password for alice:" or "Password:", only the end of the output
+// needs to be checked since the prompt is the last thing printed before the tool waits for input.  Without a terminal
+// the password would go to stdin, so only the exact prompt given to sudo -p is answered.
+
+fn is_password_prompt(bytes: &Vec<u8>, pty: bool) -> bool {
+    let start = match bytes.len() > 256 {
+        true => bytes.len() - 256,
+        false => 0
+    };
+    let text = String::from_utf8_lossy(&bytes[start..]);
+    if ! pty {
+        return text.ends_with(SUDO_PROMPT);
+    }
+    let last_line = text.trim_end().rsplit('\n').next().unwrap_or("").to_lowercase();
+    return last_line.ends_with(':') && last_line.contains("password");
+}
+
+// libssh2 sessions need a real socket, so a tunnel through a jump host is a direct-tcpip channel on the jump host
+// session, pumped to one end of a unix socket pair by a background thread.  The other end is handed to the new session.
+// The jump host session is non-blocking so that many tunnels (and both directions of each) can share it.
+
+fn open_tunnel(jump: &Session, hostname: &String, port: i64) -> Result<UnixStream, String> {
+    let jump2 = jump.clone();
+    let mut channel = loop {
+        match jump.channel_direct_tcpip(hostname, port as u16, None) {
+            Ok(x) => break x,
+            Err(y) => {
+                let err = std::io::Error::from(y);
+                if err.kind() != ErrorKind::WouldBlock {
+                    return Err(format!("failed to open tunnel to {}:{} through jump host: {}", hostname, port, err));
                 }
-            },
-            Err(_x) => {
-                return Err((404, String::from("")))
+                thread::sleep(Duration::from_millis(5));
             }
-        };
+        }
+    };
+    let (local, mut remote) = match UnixStream::pair() {
+        Ok(x) => x,
+        Err(y) => { return Err(format!("failed to create tunnel socket: {}", y)); }
+    };
+    match remote.set_nonblocking(true) {
+        Ok(_) => {},
+        Err(y) => { return Err(format!("failed to configure tunnel socket: {}", y)); }
+    }
+
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 32768];
+        loop {
+            let mut idle = true;
+            match remote.read(&mut buf) {
+                Ok(0) => { break; },
+                Ok(n) => { idle = false; if write_all_nonblocking(&mut channel, &buf[..n]).is_err() { break; } },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(_) => { break; }
+            }
+            match channel.read(&mut buf) {
+                Ok(0) => { if channel.eof() { break; } },
+                Ok(n) => { idle = false; if write_all_nonblocking(&mut remote, &buf[..n]).is_err() { break; } },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(_) => { break; }
+            }
+            if idle {
+                // keepalives for the jump host are sent from here, libssh2 only sends them once the configured interval has passed
+                let _ = jump2.keepalive_send();
+                thread::sleep(Duration::from_millis(2));
+            }
+        }
+        let _ = channel.close();
+    });
+
+    return Ok(local);
+}
+
+fn write_all_nonblocking<W: Write>(writer: &mut W, data: &[u8]) -> std::io::Result<()> {
+    let mut offset = 0;
+    while offset < data.len() {
+        match writer.write(&data[offset..]) {
+            Ok(n) => { offset += n; },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => { thread::sleep(Duration::from_millis(1)); },
+            Err(e) => { return Err(e); }
+        }
+    }
+    return Ok(());
+}
+
+// compares running a batch of commands one at a time against run_commands, which spreads them over several
+// channels of the same session. This needs a reachable sshd that accepts agent or default key authentication,
+// so it is ignored by default:
+//
+//   JET_BENCH_HOST=127.0.0.1 JET_BENCH_COMMANDS=64 cargo test --release ssh_bench -- --ignored --nocapture
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::parser::CliParser;
+    use crate::connection::no::NoFactory;
+    use crate::playbooks::traversal::RunState;
+    use crate::playbooks::visitor::PlaybookVisitor;
+    use crate::tasks::request::TaskRequest;
+    use std::path::PathBuf;
+
+    struct BenchVisitor {}
+    impl PlaybookVisitor for BenchVisitor {
+        fn is_check_mode(&self) -> bool { return false; }
     }
 
+    #[test]
+    #[ignore]
+    fn ssh_bench_sequential_vs_pooled() {
+        let hostname = env::var("JET_BENCH_HOST").unwrap_or(String::from("127.0.0.1"));
+        let username = env::var("JET_BENCH_USER").unwrap_or(env::var("USER").expect("USER must be set"));
+        let port = env::var("JET_BENCH_PORT").ok().and_then(|x| x.parse::<i64>().ok()).unwrap_or(22);
+        let count = env::var("JET_BENCH_COMMANDS").ok().and_then(|x| x.parse::<usize>().ok()).unwrap_or(32);
+
+        let parser = CliParser::new();
+        let run_state = Arc::new(RunState {
+            inventory: Arc::new(RwLock::new(Inventory::new())),
+            playbook_paths: Arc::new(RwLock::new(Vec::<PathBuf>::new())),
+            role_paths: Arc::new(RwLock::new(Vec::<PathBuf>::new())),
+            limit_hosts: Vec::new(),
+            limit_groups: Vec::new(),
+            batch_size: None,
+            context: Arc::new(RwLock::new(PlaybookContext::new(&parser))),
+            visitor: Arc::new(RwLock::new(BenchVisitor {})),
+            connection_factory: Arc::new(RwLock::new(NoFactory::new())),
+            tags: None,
+            allow_localhost_delegation: false
+        });
+        // the connection refuses hosts named localhost, the name only matters for output
+        let host = Arc::new(RwLock::new(Host::new(&String::from("ssh-bench"))));
+        let response = Arc::new(Response::new(Arc::clone(&run_state), Arc::clone(&host)));
+        let request = TaskRequest::validate();
+
+        let mut connection = SshConnection::new(Arc::clone(&host), &hostname, &username, port, false, None, None, None,
+            HostKeyChecking::Off, None, None, 10, 0);
+        connection.connect().expect("connect to the benchmark host");
+
+        let cmds : Vec<(String,Forward)> = (0..count).map(|i| (format!("sleep 0.05; echo {}", i), Forward::No)).collect();
+
+        let start = Instant::now();
+        for (i, (cmd, forward)) in cmds.iter().enumerate() {
+            let result = connection.run_command(&response, &request, cmd, *forward).expect("sequential command");
+            let command_result = result.command_result.as_ref().as_ref().unwrap();
+            assert_eq!(command_result.out.trim(), i.to_string());
+        }
+        let sequential = start.elapsed();
+
+        let start = Instant::now();
+        let results = connection.run_commands(&response, &request, &cmds);
+        let pooled = start.elapsed();
+        assert_eq!(results.len(), count);
+        for (i, result) in results.into_iter().enumerate() {
+            let result = result.expect("pooled command");
+            let command_result = result.command_result.as_ref().as_ref().unwrap();
+            assert_eq!(command_result.out.trim(), i.to_string());
+        }
+
+        println!("{} commands: sequential {:?}, pooled {:?}", count, sequential, pooled);
+        assert!(pooled <= sequential, "running commands over parallel channels was slower than running them one by one");
+    }
 }
@@ -0,0 +1,266 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::util::io::expand_home_path;
+use std::path::Path;
+use std::fs;
+use std::env;
+
+// a minimal reader for the OpenSSH client configuration file (~/.ssh/config). We only care about
+// the handful of keywords that change where and how we connect, and like OpenSSH, the first value
+// obtained for each keyword wins.  Match blocks are not supported and are skipped.
+
+pub struct SshConfig {
+    blocks: Vec<SshConfigBlock>
+}
+
+struct SshConfigBlock {
+    patterns: Vec<String>,
+    hostname: Option<String>,
+    user: Option<String>,
+    port: Option<i64>,
+    identity_file: Option<String>,
+    proxy_jump: Option<String>
+}
+
+// the result of looking up a host alias, any value not found in the config is None
+
+pub struct SshConfigEntry {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<i64>,
+    pub identity_file: Option<String>,
+    pub proxy_jump: Option<String>
+}
+
+impl SshConfigBlock {
+    fn new(patterns: Vec<String>) -> Self {
+        Self { patterns, hostname: None, user: None, port: None, identity_file: None, proxy_jump: None }
+    }
+}
+
+impl SshConfig {
+
+    pub fn new() -> Self {
+        Self { blocks: Vec::new() }
+    }
+
+    // loads ~/.ssh/config if it exists. A missing or unreadable file just means there is nothing to apply.
+
+    pub fn load() -> Self {
+        let home = match env::var("HOME") {
+            Ok(x) => x,
+            Err(_) => { return Self::new(); }
+        };
+        let path = Path::new(&home).join(".ssh").join("config");
+        return match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(_) => Self::new()
+        };
+    }
+
+    pub fn parse(contents: &String) -> Self {
+        let mut blocks : Vec<SshConfigBlock> = Vec::new();
+        // lines before the first Host keyword apply to every host
+        blocks.push(SshConfigBlock::new(vec![String::from("*")]));
+        let mut skipping = false;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, value) = match Self::split_line(line) {
+                Some(x) => x,
+                None => { continue; }
+            };
+            match keyword.as_str() {
+                "host" => {
+                    skipping = false;
+                    let patterns = value.split_whitespace().map(|x| String::from(x.trim_matches('"'))).collect();
+                    blocks.push(SshConfigBlock::new(patterns));
+                    continue;
+                },
+                "match" => {
+                    skipping = true;
+                    continue;
+                },
+                _ => {}
+            }
+            if skipping {
+                continue;
+            }
+            let block = blocks.last_mut().unwrap();
+            match keyword.as_str() {
+                "hostname"     => { if block.hostname.is_none() { block.hostname = Some(value); } },
+                "user"         => { if block.user.is_none() { block.user = Some(value); } },
+                "port"         => { if block.port.is_none() { block.port = value.parse::<i64>().ok(); } },
+                "identityfile" => { if block.identity_file.is_none() { block.identity_file = Some(value); } },
+                "proxyjump"    => { if block.proxy_jump.is_none() { block.proxy_jump = Some(value); } },
+                _ => {}
+            }
+        }
+        return Self { blocks };
+    }
+
+    // keywords are case insensitive and may be separated from their values by whitespace or an '='
+
+    fn split_line(line: &str) -> Option<(String,String)> {
+        let idx = line.find(|c: char| c.is_whitespace() || c == '=')?;
+        let keyword = line[..idx].to_lowercase();
+        let rest = line[idx..].trim_start();
+        let rest = rest.strip_prefix('=').unwrap_or(rest).trim();
+        let value = rest.trim_matches('"');
+        if value.is_empty() {
+            return None;
+        }
+        return Some((keyword, String::from(value)));
+    }
+
+    pub fn lookup(&self, alias: &String) -> SshConfigEntry {
+        let mut entry = SshConfigEntry { hostname: None, user: None, port: None, identity_file: None, proxy_jump: None };
+        for block in self.blocks.iter() {
+            if ! Self::block_matches(block, alias) {
+                continue;
+            }
+            if entry.hostname.is_none()      { entry.hostname = block.hostname.clone(); }
+            if entry.user.is_none()          { entry.user = block.user.clone(); }
+            if entry.port.is_none()          { entry.port = block.port; }
+            if entry.identity_file.is_none() { entry.identity_file = block.identity_file.clone(); }
+            if entry.proxy_jump.is_none()    { entry.proxy_jump = block.proxy_jump.clone(); }
+        }
+        // HostName may refer to the original alias with %h, IdentityFile is usually relative to ~
+        entry.hostname = entry.hostname.map(|x| x.replace("%h", alias));
+        entry.identity_file = entry.identity_file.map(|x| expand_home_path(&x.replace("%h", alias)));
+        // "ProxyJump none" is how a more specific block turns off a jump host set by a wildcard block
+        entry.proxy_jump = entry.proxy_jump.filter(|x| ! x.eq_ignore_ascii_case("none"));
+        return entry;
+    }
+
+    // a block applies if any of its patterns match and none of its negated (!) patterns do
+
+    fn block_matches(block: &SshConfigBlock, alias: &String) -> bool {
+        let mut matched = false;
+        for pattern in block.patterns.iter() {
+            match pattern.strip_prefix('!') {
+                Some(negated) => { if glob_match(negated, alias) { return false; } },
+                None => { if glob_match(pattern, alias) { matched = true; } }
+            }
+        }
+        return matched;
+    }
+}
+
+// OpenSSH host patterns only support '*' and '?'
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p : Vec<char> = pattern.chars().collect();
+    let t : Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0usize, 0usize);
+    let mut star : Option<(usize,usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((spi, sti)) = star {
+            pi = spi + 1;
+            ti = sti + 1;
+            star = Some((spi, sti + 1));
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    return pi == p.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("web*", "web01"));
+        assert!(glob_match("web?", "web1"));
+        assert!(! glob_match("web?", "web10"));
+        assert!(glob_match("*.example.com", "db.example.com"));
+        assert!(! glob_match("*.example.com", "example.com"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(! glob_match("a*b*c", "aXXbYY"));
+        assert!(glob_match("", ""));
+        assert!(! glob_match("", "x"));
+    }
+
+    #[test]
+    fn first_value_wins_and_wildcards_apply_last() {
+        let config = SshConfig::parse(&String::from("\
+            # comment\n\
+            Host web*\n\
+            \tHostName %h.internal\n\
+            \tUser deploy\n\
+            Host *\n\
+            \tUser nobody\n\
+            \tPort=2222\n\
+            \tIdentityFile \"/keys/id_%h\"\n"));
+        let web = config.lookup(&String::from("web1"));
+        assert_eq!(web.hostname, Some(String::from("web1.internal")));
+        assert_eq!(web.user, Some(String::from("deploy")));
+        assert_eq!(web.port, Some(2222));
+        assert_eq!(web.identity_file, Some(String::from("/keys/id_web1")));
+        let db = config.lookup(&String::from("db1"));
+        assert_eq!(db.hostname, None);
+        assert_eq!(db.user, Some(String::from("nobody")));
+    }
+
+    #[test]
+    fn negated_patterns() {
+        let config = SshConfig::parse(&String::from("\
+            Host *.example.com !bastion.example.com\n\
+            ProxyJump bastion.example.com\n"));
+        assert_eq!(config.lookup(&String::from("app.example.com")).proxy_jump, Some(String::from("bastion.example.com")));
+        assert_eq!(config.lookup(&String::from("bastion.example.com")).proxy_jump, None);
+        assert_eq!(config.lookup(&String::from("other.org")).proxy_jump, None);
+    }
+
+    #[test]
+    fn global_lines_match_and_blocks_are_skipped() {
+        let config = SshConfig::parse(&String::from("\
+            User everyone\n\
+            Include ~/.ssh/config.d/*\n\
+            Match host foo\n\
+            \tUser matched\n\
+            Host foo\n\
+            \tProxyJump none\n\
+            Host *\n\
+            \tProxyJump jump\n"));
+        let foo = config.lookup(&String::from("foo"));
+        assert_eq!(foo.user, Some(String::from("everyone")));
+        assert_eq!(foo.proxy_jump, None);
+        assert_eq!(config.lookup(&String::from("bar")).proxy_jump, Some(String::from("jump")));
+    }
+
+    #[test]
+    fn empty_config() {
+        let entry = SshConfig::parse(&String::new()).lookup(&String::from("host"));
+        assert!(entry.hostname.is_none() && entry.user.is_none() && entry.port.is_none());
+    }
+}
@@ -0,0 +1,700 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::connection::connection::Connection;
+use crate::connection::factory::ConnectionFactory;
+use crate::connection::command::{Forward,CommandOutput,OutputKind,RC_TIMED_OUT,get_deadline};
+use crate::connection::local::LocalFactory;
+use crate::playbooks::context::PlaybookContext;
+use crate::inventory::hosts::Host;
+use crate::handle::response::Response;
+use crate::tasks::{TaskRequest,TaskResponse};
+use crate::util::io::jet_file_open;
+use crate::util::encoding::{base64_encode,base64_decode};
+use crate::Inventory;
+use std::sync::{Arc,Mutex,RwLock};
+use std::path::Path;
+use std::io::{Read,Write};
+use std::net::{TcpStream,ToSocketAddrs};
+use std::time::{Duration,Instant};
+use guid_create::GUID;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash,Hasher};
+
+// implementation for both WinRM connections and the WinRM connection factory.  Windows hosts are managed over
+// WS-Management (SOAP over HTTP), with one remote shell opened on connect and kept for the life of the connection.
+// Every command runs as a PowerShell script in that shell.  Only plain HTTP with basic authentication is supported,
+// so the WinRM service needs AllowUnencrypted and Basic turned on, which is best kept to trusted networks.
+
+const RESOURCE_URI: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/cmd";
+const ACTION_CREATE: &str = "http://schemas.xmlsoap.org/ws/2004/09/transfer/Create";
+const ACTION_DELETE: &str = "http://schemas.xmlsoap.org/ws/2004/09/transfer/Delete";
+const ACTION_COMMAND: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Command";
+const ACTION_SEND: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Send";
+const ACTION_RECEIVE: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Receive";
+const ACTION_SIGNAL: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/Signal";
+const SIGNAL_TERMINATE: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/signal/terminate";
+const STATE_DONE: &str = "http://schemas.microsoft.com/wbem/wsman/1/windows/shell/CommandState/Done";
+
+// the server holds a Receive open for up to this long waiting for output, and answers with this fault code when
+// there was none, which just means asking again
+const RECEIVE_WAIT: u64 = 20;
+const FAULT_OPERATION_TIMEOUT: &str = "2150858793";
+
+// file contents go over stdin as lines of base64 (decoded by the remote script), each line is sized so that its
+// envelope stays well under the default MaxEnvelopeSize of 500 KB
+const TRANSFER_CHUNK: usize = 48 * 1024;
+
+pub struct WinRmFactory {
+    local_factory: LocalFactory,
+    localhost: Arc<RwLock<Host>>,
+}
+
+impl WinRmFactory {
+    pub fn new(inventory: &Arc<RwLock<Inventory>>) -> Self {
+        Self {
+            localhost : inventory.read().expect("inventory read").get_host(&String::from("localhost")),
+            local_factory: LocalFactory::new(inventory),
+        }
+    }
+}
+
+impl ConnectionFactory for WinRmFactory {
+
+    fn get_local_connection(&self, context: &Arc<RwLock<PlaybookContext>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        return Ok(self.local_factory.get_connection(context, &self.localhost)?);
+    }
+
+    fn get_connection(&self, context: &Arc<RwLock<PlaybookContext>>, host:&Arc<RwLock<Host>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+        let ctx = context.read().expect("context read");
+        let hostname = host.read().expect("host read").name.clone();
+        if hostname.eq("localhost") {
+            let conn : Arc<Mutex<dyn Connection>> = self.local_factory.get_connection(context, &self.localhost)?;
+            return Ok(conn);
+        }
+
+        let (remote_hostname, port, user, password) = ctx.get_winrm_details(host);
+        let password = match password {
+            Some(x) => x,
+            None => { return Err(format!("host {}: jet_winrm_password is required for WinRM connections", hostname)); }
+        };
+        // the password is part of the details, but only as a hash, as they are kept for the whole run
+        let mut hasher = DefaultHasher::new();
+        password.hash(&mut hasher);
+        let details = format!("winrm {}@{}:{} {:x}", user, remote_hostname, port, hasher.finish());
+        {
+            let mut cache = ctx.connection_cache.write().unwrap();
+            if let Some(conn) = cache.get_matching_connection(host, &details) {
+                return Ok(conn);
+            }
+        }
+
+        let mut conn = WinRmConnection::new(Arc::clone(&host), &remote_hostname, port, &user, &password, ctx.ssh_connect_timeout);
+        return match conn.connect() {
+            Ok(_)  => {
+                let conn2 : Arc<Mutex<dyn Connection>> = Arc::new(Mutex::new(conn));
+                ctx.connection_cache.write().expect("connection cache write").add_connection(
+                    &Arc::clone(&host), &Arc::clone(&conn2), &details);
+                Ok(conn2)
+            },
+            Err(x) => { Err(x) }
+        }
+    }
+}
+
+pub struct WinRmConnection {
+    pub host: Arc<RwLock<Host>>,
+    hostname: String,
+    port: i64,
+    user: String,
+    password: String,
+    connect_timeout: u64,
+    shell_id: Option<String>
+}
+
+impl WinRmConnection {
+    pub fn new(host: Arc<RwLock<Host>>, hostname: &String, port: i64, user: &String, password: &String, connect_timeout: u64) -> Self {
+        Self {
+            host: Arc::clone(&host),
+            hostname: hostname.clone(),
+            port,
+            user: user.clone(),
+            password: password.clone(),
+            connect_timeout,
+            shell_id: None
+        }
+    }
+}
+
+impl Drop for WinRmConnection {
+    fn drop(&mut self) {
+        // servers only allow a few shells per user, so they are not left for the idle timeout to clean up
+        if self.shell_id.is_some() {
+            let _d = self.soap(ACTION_DELETE, true, &String::new(), &String::new(), RECEIVE_WAIT);
+        }
+    }
+}
+
+impl Connection for WinRmConnection {
+
+    fn whoami(&self) -> Result<String,String> {
+        // the account may be given as DOMAIN\user or user@domain, jet only wants the name for paths under C:/Users
+        let user = self.user.rsplit('\\').next().unwrap_or("");
+        return Ok(String::from(user.split('@').next().unwrap_or("")));
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        if self.shell_id.is_some() {
+            return Ok(());
+        }
+        let options = String::from("<w:OptionSet><w:Option Name=\"WINRS_NOPROFILE\">TRUE</w:Option><w:Option Name=\"WINRS_CODEPAGE\">65001</w:Option></w:OptionSet>");
+        let body = String::from("<rsp:Shell><rsp:InputStreams>stdin</rsp:InputStreams><rsp:OutputStreams>stdout stderr</rsp:OutputStreams></rsp:Shell>");
+        let reply = self.soap(ACTION_CREATE, false, &options, &body, RECEIVE_WAIT)?;
+        let shell_id = match xml_elements(&reply, "ShellId").into_iter().next() {
+            Some((_, x)) => x,
+            None => match xml_elements(&reply, "Selector").into_iter().find(|(attrs, _)| xml_attribute(attrs, "Name") == Some(String::from("ShellId"))) {
+                Some((_, x)) => x,
+                None => { return Err(format!("WinRM connection to {} did not return a shell id", self.hostname)); }
+            }
+        };
+        self.shell_id = Some(shell_id);
+
+        // like uname -a for the other connections, this both checks that commands work and tells us the OS type
+        let output = CommandOutput::none();
+        return match self.run_powershell(&String::from("[Environment]::OSVersion.VersionString"), None, None, &output) {
+            Ok((0, out)) => self.host.write().unwrap().set_os_info(&out),
+            Ok((rc, out)) | Err((rc, out)) => Err(format!("WinRM command on {} failed: rc={}, out={}", self.hostname, rc, out))
+        };
+    }
+
+    fn run_command(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, cmd: &String, _forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+        let output = CommandOutput::new(response, request);
+        let deadline = get_deadline(request.get_timeout());
+        return match self.run_powershell(cmd, None, deadline, &output) {
+            Ok((rc,s)) => {
+                // note that non-zero return codes are "ok" to the connection plugin, handle elsewhere!
+                Ok(response.command_ok(request, &output.get_result(cmd, &s, rc)))
+            },
+            Err((RC_TIMED_OUT,s)) => {
+                Err(response.command_timed_out(request, &output.get_result(cmd, &s, RC_TIMED_OUT)))
+            },
+            Err((rc,s)) => {
+                Err(response.command_failed(request, &output.get_result(cmd, &s, rc)))
+            }
+        };
+    }
+
+    fn write_data(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, data: &String, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        let mut reader = data.as_bytes();
+        return self.transfer(response, request, &mut reader, remote_path);
+    }
+
+    fn copy_file(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, src: &Path, remote_path: &String) -> Result<(), Arc<TaskResponse>> {
+        let mut src_file = match jet_file_open(src) {
+            Ok(x) => x,
+            Err(y) => return Err(response.is_failed(&request, &y))
+        };
+        return self.transfer(response, request, &mut src_file, remote_path);
+    }
+
+}
+
+impl WinRmConnection {
+
+    // files are streamed to a script that decodes each line of stdin and appends it to the file.  remote.rs takes care
+    // of moving the file into place.
+
+    fn transfer(&self, response: &Arc<Response>, request: &Arc<TaskRequest>, reader: &mut dyn Read, remote_path: &String) -> Result<(),Arc<TaskResponse>> {
+        let script = format!("$o = [IO.File]::Create('{}')\n\
+            try {{ while (($line = [Console]::In.ReadLine()) -ne $null) {{ $b = [Convert]::FromBase64String($line); $o.Write($b, 0, $b.Length) }} }}\n\
+            finally {{ $o.Close() }}", remote_path.replace("'", "''"));
+        let output = CommandOutput::none();
+        return match self.run_powershell(&script, Some(reader), None, &output) {
+            Ok((0, _)) => Ok(()),
+            Ok((rc, out)) | Err((rc, out)) => Err(response.is_failed(request, &format!("failed to write: {}: rc={}, out={}", remote_path, rc, out)))
+        };
+    }
+
+    // runs a script to completion (or until the deadline), feeding it stdin if given, and returns the exit code and
+    // the combined output.  The streams are collected separately in output, as for the other connections.
+
+    fn run_powershell(&self, script: &String, stdin: Option<&mut dyn Read>, deadline: Option<Instant>, output: &CommandOutput)
+        -> Result<(i32,String),(i32,String)> {
+        let options = String::from("<w:OptionSet><w:Option Name=\"WINRS_CONSOLEMODE_STDIN\">TRUE</w:Option><w:Option Name=\"WINRS_SKIP_CMD_SHELL\">TRUE</w:Option></w:OptionSet>");
+        let body = format!("<rsp:CommandLine><rsp:Command>powershell.exe</rsp:Command><rsp:Arguments>-NoProfile -NonInteractive -ExecutionPolicy Bypass -EncodedCommand {}</rsp:Arguments></rsp:CommandLine>",
+            encode_powershell(script));
+        let reply = self.soap(ACTION_COMMAND, true, &options, &body, RECEIVE_WAIT).map_err(|y| (500, y))?;
+        let command_id = match xml_elements(&reply, "CommandId").into_iter().next() {
+            Some((_, x)) => x,
+            None => { return Err((500, String::from("WinRM did not return a command id"))); }
+        };
+
+        if stdin.is_some() {
+            self.send_stdin(&command_id, stdin.unwrap()).map_err(|y| (500, y))?;
+        }
+
+        let result = self.receive(&command_id, deadline, output);
+        // the command is over either way, this releases it on the server (and stops it if it timed out)
+        let signal = format!("<rsp:Signal CommandId=\"{}\"><rsp:Code>{}</rsp:Code></rsp:Signal>", command_id, SIGNAL_TERMINATE);
+        let _s = self.soap(ACTION_SIGNAL, true, &String::new(), &signal, RECEIVE_WAIT);
+        output.finish();
+        let out = output.get_combined().replace("\r\n", "\n").trim_end().to_string();
+        return match result {
+            Ok(rc) => Ok((rc, out)),
+            Err((RC_TIMED_OUT, _)) => Err((RC_TIMED_OUT, out)),
+            Err(x) => Err(x)
+        };
+    }
+
+    fn send_stdin(&self, command_id: &String, reader: &mut dyn Read) -> Result<(),String> {
+        let mut buf = vec![0u8; TRANSFER_CHUNK];
+        loop {
+            let n = read_full(reader, &mut buf).map_err(|y| format!("failed to read input: {}", y))?;
+            let end = match n < TRANSFER_CHUNK { true => " End=\"true\"", false => "" };
+            let line = match n { 0 => String::new(), _ => format!("{}\r\n", base64_encode(&buf[..n])) };
+            let body = format!("<rsp:Send><rsp:Stream Name=\"stdin\" CommandId=\"{}\"{}>{}</rsp:Stream></rsp:Send>",
+                command_id, end, base64_encode(line.as_bytes()));
+            self.soap(ACTION_SEND, true, &String::new(), &body, RECEIVE_WAIT)?;
+            if n < TRANSFER_CHUNK {
+                return Ok(());
+            }
+        }
+    }
+
+    // output arrives in base64 stream elements, possibly over many replies, the last of which carries the exit code
+
+    fn receive(&self, command_id: &String, deadline: Option<Instant>, output: &CommandOutput) -> Result<i32,(i32,String)> {
+        let body = format!("<rsp:Receive><rsp:DesiredStream CommandId=\"{}\">stdout stderr</rsp:DesiredStream></rsp:Receive>", command_id);
+        loop {
+            let wait = match deadline {
+                Some(x) => {
+                    let remaining = x.saturating_duration_since(Instant::now()).as_secs();
+                    if remaining == 0 {
+                        return Err((RC_TIMED_OUT, String::new()));
+                    }
+                    std::cmp::min(remaining, RECEIVE_WAIT)
+                },
+                None => RECEIVE_WAIT
+            };
+            let reply = match self.soap(ACTION_RECEIVE, true, &String::new(), &body, wait) {
+                Ok(x) => x,
+                Err(y) if y.contains(FAULT_OPERATION_TIMEOUT) => { continue; },
+                Err(y) => { return Err((500, format!("{} in WinRM reply", y))); }
+            };
+            for (attrs, text) in xml_elements(&reply, "Stream").iter() {
+                let kind = match xml_attribute(attrs, "Name").as_deref() {
+                    Some("stderr") => OutputKind::Stderr,
+                    _ => OutputKind::Stdout
+                };
+                match base64_decode(text) {
+                    Ok(bytes) => output.push(kind, &bytes),
+                    Err(y) => { return Err((500, format!("{} in WinRM reply", y))); }
+                }
+            }
+            let done = xml_elements(&reply, "CommandState").iter().any(|(attrs, _)| xml_attribute(attrs, "State").as_deref() == Some(STATE_DONE));
+            if done {
+                return match xml_elements(&reply, "ExitCode").into_iter().next() {
+                    Some((_, x)) => match x.trim().parse::<i64>() {
+                        // exit codes are unsigned on Windows
+                        Ok(rc) => Ok(rc as i32),
+                        Err(_) => Err((500, format!("invalid exit code from WinRM: {}", x)))
+                    },
+                    None => Ok(0)
+                };
+            }
+        }
+    }
+
+    // sends one WS-Management request and returns the body of the reply.  Faults come back as errors with the message
+    // from the fault (which includes the fault code).
+
+    fn soap(&self, action: &str, with_shell: bool, options: &String, body: &String, wait: u64) -> Result<String,String> {
+        let url = format!("http://{}:{}/wsman", self.hostname, self.port);
+        let selector = match (with_shell, self.shell_id.as_ref()) {
+            (true, Some(x)) => format!("<w:SelectorSet><w:Selector Name=\"ShellId\">{}</w:Selector></w:SelectorSet>", x),
+            _ => String::new()
+        };
+        let envelope = format!("<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" \
+            xmlns:a=\"http://schemas.xmlsoap.org/ws/2004/08/addressing\" \
+            xmlns:w=\"http://schemas.dmtf.org/wbem/wsman/1/wsman.xsd\" \
+            xmlns:rsp=\"http://schemas.microsoft.com/wbem/wsman/1/windows/shell\">\
+            <s:Header>\
+            <a:To>{url}</a:To>\
+            <a:ReplyTo><a:Address s:mustUnderstand=\"true\">http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</a:Address></a:ReplyTo>\
+            <w:MaxEnvelopeSize s:mustUnderstand=\"true\">512000</w:MaxEnvelopeSize>\
+            <a:MessageID>uuid:{id}</a:MessageID>\
+            <w:Locale xml:lang=\"en-US\" s:mustUnderstand=\"false\"/>\
+            <w:OperationTimeout>PT{wait}S</w:OperationTimeout>\
+            <w:ResourceURI s:mustUnderstand=\"true\">{resource}</w:ResourceURI>\
+            <a:Action s:mustUnderstand=\"true\">{action}</a:Action>\
+            {selector}{options}\
+            </s:Header>\
+            <s:Body>{body}</s:Body>\
+            </s:Envelope>",
+            url=url, id=GUID::rand().to_string(), wait=wait, resource=RESOURCE_URI, action=action, selector=selector, options=options, body=body);
+        let (status, schemes, reply) = self.post(&envelope, wait + self.connect_timeout)?;
+        return match status {
+            200 => Ok(reply),
+            // servers left at their defaults only offer Negotiate (Kerberos or NTLM), which would otherwise look like a bad password
+            401 if ! schemes.is_empty() && ! schemes.iter().any(|x| x.eq_ignore_ascii_case("Basic")) => Err(format!(
+                "WinRM on {} only offers {} authentication, jet needs Basic authentication over HTTP (set Basic and AllowUnencrypted \
+                in the WinRM service configuration)", self.hostname, schemes.join("/"))),
+            401 => Err(format!("WinRM authentication failed for {} on {}", self.user, self.hostname)),
+            _ => {
+                let message = xml_elements(&reply, "Message").into_iter().chain(xml_elements(&reply, "Text").into_iter()).next();
+                let code = xml_elements(&reply, "WSManFault").into_iter().next().and_then(|(attrs, _)| xml_attribute(&attrs, "Code"));
+                match (message, code) {
+                    (Some((_, m)), Some(c)) => Err(format!("WinRM fault {}: {}", c, m.trim())),
+                    (Some((_, m)), None) => Err(format!("WinRM fault: {}", m.trim())),
+                    _ => Err(format!("WinRM request failed with HTTP status {}", status))
+                }
+            }
+        };
+    }
+
+    // a minimal HTTP/1.1 client, one connection per request
+
+    fn post(&self, envelope: &String, read_timeout: u64) -> Result<(u16,Vec<String>,String),String> {
+        let address = format!("{}:{}", self.hostname, self.port);
+        let resolved = match address.to_socket_addrs() {
+            Ok(mut x) => match x.next() {
+                Some(y) => y,
+                None => { return Err(format!("unable to resolve: {}", address)); }
+            },
+            Err(y) => { return Err(format!("unable to resolve: {}: {}", address, y)); }
+        };
+        let mut stream = match TcpStream::connect_timeout(&resolved, Duration::from_secs(self.connect_timeout)) {
+            Ok(x) => x,
+            Err(y) => { return Err(format!("WinRM connection to {} failed: {}", address, y)); }
+        };
+        let _t = stream.set_read_timeout(Some(Duration::from_secs(read_timeout)));
+        let credentials = base64_encode(format!("{}:{}", self.user, self.password).as_bytes());
+        let request = format!("POST /wsman HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic {}\r\nContent-Type: application/soap+xml;charset=UTF-8\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n{}", address, credentials, envelope.len(), envelope);
+        match stream.write_all(request.as_bytes()) {
+            Ok(_) => {},
+            Err(y) => { return Err(format!("WinRM request to {} failed: {}", address, y)); }
+        }
+        let mut raw : Vec<u8> = Vec::new();
+        match stream.read_to_end(&mut raw) {
+            Ok(_) => {},
+            Err(y) => { return Err(format!("WinRM reply from {} failed: {}", address, y)); }
+        }
+        return parse_http_response(&raw);
+    }
+}
+
+// returns the status, the schemes offered in any WWW-Authenticate headers, and the body
+
+fn parse_http_response(raw: &Vec<u8>) -> Result<(u16,Vec<String>,String),String> {
+    let split = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(x) => x,
+        None => { return Err(String::from("incomplete HTTP reply from WinRM")); }
+    };
+    let head = String::from_utf8_lossy(&raw[..split]).to_string();
+    let mut body = raw[split+4..].to_vec();
+    let mut lines = head.lines();
+    let status = match lines.next().and_then(|x| x.split_whitespace().nth(1)).and_then(|x| x.parse::<u16>().ok()) {
+        Some(x) => x,
+        None => { return Err(format!("invalid HTTP reply from WinRM: {}", head)); }
+    };
+    let mut chunked = false;
+    let mut schemes : Vec<String> = Vec::new();
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((x, y)) => (x.trim().to_lowercase(), y.trim()),
+            None => { continue; }
+        };
+        if name == "transfer-encoding" && value.to_lowercase().contains("chunked") {
+            chunked = true;
+        } else if name == "www-authenticate" && ! value.is_empty() {
+            schemes.push(String::from(value.split_whitespace().next().unwrap()));
+        }
+    }
+    if chunked {
+        body = decode_chunked(&body)?;
+    }
+    return Ok((status, schemes, String::from_utf8_lossy(&body).to_string()));
+}
+
+fn decode_chunked(body: &Vec<u8>) -> Result<Vec<u8>,String> {
+    let mut result : Vec<u8> = Vec::new();
+    let mut rest = &body[..];
+    loop {
+        let line_end = match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(x) => x,
+            None => { return Err(String::from("invalid chunked reply from WinRM")); }
+        };
+        let size_text = String::from_utf8_lossy(&rest[..line_end]).to_string();
+        let size = match usize::from_str_radix(size_text.split(';').next().unwrap_or("").trim(), 16) {
+            Ok(x) => x,
+            Err(_) => { return Err(format!("invalid chunk size from WinRM: {}", size_text)); }
+        };
+        if size == 0 {
+            return Ok(result);
+        }
+        let start = line_end + 2;
+        if rest.len() < start + size + 2 {
+            return Err(String::from("truncated chunked reply from WinRM"));
+        }
+        result.extend_from_slice(&rest[start..start+size]);
+        rest = &rest[start+size+2..];
+    }
+}
+
+// errors in the script stop it with an exit code of 1 and the error on stderr, and the exit code of the last
+// program it ran is passed on, so return codes mean the same as they do for shell commands elsewhere.
+// -EncodedCommand takes the script as base64 of UTF-16LE, which avoids all quoting problems.
+
+fn encode_powershell(script: &String) -> String {
+    let wrapped = format!("$ProgressPreference = 'SilentlyContinue'\n$ErrorActionPreference = 'Stop'\n\
+        [Console]::OutputEncoding = [Text.Encoding]::UTF8\n\
+        try {{\n{}\n}} catch {{\n[Console]::Error.WriteLine($_)\nexit 1\n}}\n\
+        if ($LASTEXITCODE) {{ exit $LASTEXITCODE }}", script);
+    let utf16 : Vec<u8> = wrapped.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+    return base64_encode(&utf16);
+}
+
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read(&mut buf[total..])? {
+            0 => break,
+            n => { total += n; }
+        }
+    }
+    return Ok(total);
+}
+
+// just enough XML for WS-Management replies: elements are matched by local name (ignoring namespace prefixes) and
+// returned with their raw attribute text and their text content.  Elements of the same name never nest in these replies.
+
+fn xml_elements(xml: &String, name: &str) -> Vec<(String,String)> {
+    let mut results = Vec::new();
+    let mut rest = xml.as_str();
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start+1..];
+        let tag_end = match rest.find('>') {
+            Some(x) => x,
+            None => break
+        };
+        let tag = &rest[..tag_end];
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        let local_name = tag_name.rsplit(':').next().unwrap_or("");
+        if local_name != name || tag.starts_with('/') {
+            continue;
+        }
+        let attrs = tag[tag_name.len()..].trim_end_matches('/').to_string();
+        rest = &rest[tag_end+1..];
+        if tag.ends_with('/') {
+            results.push((attrs, String::new()));
+            continue;
+        }
+        let close = format!("</{}>", tag_name);
+        match rest.find(&close) {
+            Some(x) => {
+                results.push((attrs, xml_unescape(&rest[..x])));
+                rest = &rest[x+close.len()..];
+            },
+            None => break
+        }
+    }
+    return results;
+}
+
+fn xml_attribute(attrs: &String, name: &str) -> Option<String> {
+    for quote in [ '"', '\'' ] {
+        let pattern = format!("{}={}", name, quote);
+        let mut search = attrs.as_str();
+        while let Some(x) = search.find(&pattern) {
+            // make sure this is the whole attribute name, and not the end of a longer one
+            let whole = x == 0 || search[..x].ends_with(|c: char| c.is_whitespace() || c == ':');
+            let value = &search[x+pattern.len()..];
+            if whole {
+                return value.find(quote).map(|end| xml_unescape(&value[..end]));
+            }
+            search = value;
+        }
+    }
+    return None;
+}
+
+fn xml_unescape(text: &str) -> String {
+    return text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn http_responses() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Type: application/soap+xml\r\n\r\n<a/>".to_vec();
+        assert_eq!(parse_http_response(&raw), Ok((200, Vec::new(), String::from("<a/>"))));
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n".to_vec();
+        assert_eq!(parse_http_response(&raw), Ok((200, Vec::new(), String::from("Wikipedia"))));
+        let raw = b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Negotiate\r\nwww-authenticate: Kerberos realm=x\r\n\r\n".to_vec();
+        assert_eq!(parse_http_response(&raw), Ok((401, vec![String::from("Negotiate"), String::from("Kerberos")], String::new())));
+        assert!(parse_http_response(&b"HTTP/1.1 200 OK\r\n".to_vec()).is_err());
+        assert!(parse_http_response(&b"garbage\r\n\r\n".to_vec()).is_err());
+    }
+
+    #[test]
+    fn chunked_bodies() {
+        assert_eq!(decode_chunked(&b"3\r\nabc\r\nA\r\n0123456789\r\n0\r\n\r\n".to_vec()), Ok(b"abc0123456789".to_vec()));
+        assert!(decode_chunked(&b"5\r\nabc\r\n".to_vec()).is_err());
+        assert!(decode_chunked(&b"zz\r\nabc\r\n".to_vec()).is_err());
+        assert!(decode_chunked(&b"3\r\nabc".to_vec()).is_err());
+    }
+
+    #[test]
+    fn xml_helpers() {
+        let xml = String::from("<s:Body><rsp:Stream Name=\"stdout\" CommandId=\"1\">aGk=</rsp:Stream><rsp:Stream Name='stderr' End=\"true\"/>\
+            <rsp:CommandState CommandId=\"1\" State=\"done\"><rsp:ExitCode>3</rsp:ExitCode></rsp:CommandState>\
+            <f:Message>a &lt;b&gt; &amp; c</f:Message></s:Body>");
+        let streams = xml_elements(&xml, "Stream");
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].1, "aGk=");
+        assert_eq!(xml_attribute(&streams[0].0, "Name"), Some(String::from("stdout")));
+        assert_eq!(xml_attribute(&streams[1].0, "Name"), Some(String::from("stderr")));
+        assert_eq!(xml_attribute(&streams[1].0, "End"), Some(String::from("true")));
+        assert_eq!(streams[1].1, "");
+        // Id must not match the end of CommandId
+        assert_eq!(xml_attribute(&streams[0].0, "Id"), None);
+        assert_eq!(xml_elements(&xml, "ExitCode")[0].1, "3");
+        assert_eq!(xml_elements(&xml, "Message")[0].1, "a <b> & c");
+        assert!(xml_elements(&xml, "Missing").is_empty());
+    }
+
+    // a WinRM server that answers each action with a canned envelope.  The Receive replies come from the list in
+    // order, and everything seen is recorded as (action, authorization header, body).
+
+    type Seen = Arc<Mutex<Vec<(String,String,String)>>>;
+
+    fn envelope(body: &str) -> String {
+        return format!("<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\"><s:Body>{}</s:Body></s:Envelope>", body);
+    }
+
+    fn http_reply(status: &str, headers: &str, body: &String) -> String {
+        return format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\n\r\n{}", status, headers, body.len(), body);
+    }
+
+    fn serve(listener: TcpListener, receives: Vec<String>, seen: Seen) -> thread::JoinHandle<()> {
+        return thread::spawn(move || {
+            let mut receives = receives.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut raw : Vec<u8> = Vec::new();
+                let mut buf = [0u8; 4096];
+                let (head, body) = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    raw.extend_from_slice(&buf[..n]);
+                    let split = match raw.windows(4).position(|w| w == b"\r\n\r\n") { Some(x) => x, None => { continue; } };
+                    let head = String::from_utf8_lossy(&raw[..split]).to_string();
+                    let length = head.lines().find_map(|x| x.strip_prefix("Content-Length: ")).unwrap().parse::<usize>().unwrap();
+                    if raw.len() >= split + 4 + length {
+                        break (head, String::from_utf8_lossy(&raw[split+4..]).to_string());
+                    }
+                    if n == 0 { panic!("short request"); }
+                };
+                let auth = head.lines().find_map(|x| x.strip_prefix("Authorization: ")).unwrap_or("").to_string();
+                let action = xml_elements(&body, "Action").into_iter().next().unwrap().1;
+                seen.lock().unwrap().push((action.clone(), auth, body));
+                let reply = match action.as_str() {
+                    ACTION_CREATE => http_reply("200 OK", "", &envelope("<rsp:Shell><rsp:ShellId>SHELL-1</rsp:ShellId></rsp:Shell>")),
+                    ACTION_COMMAND => http_reply("200 OK", "", &envelope("<rsp:CommandResponse><rsp:CommandId>CMD-1</rsp:CommandId></rsp:CommandResponse>")),
+                    ACTION_RECEIVE => receives.next().expect("unexpected Receive"),
+                    _ => http_reply("200 OK", "", &envelope(""))
+                };
+                stream.write_all(reply.as_bytes()).unwrap();
+                if action == ACTION_DELETE {
+                    return;
+                }
+            }
+        });
+    }
+
+    fn done(streams: &[(&str, &str)], rc: i32) -> String {
+        let mut body = String::new();
+        for (name, text) in streams.iter() {
+            body.push_str(&format!("<rsp:Stream Name=\"{}\" CommandId=\"CMD-1\">{}</rsp:Stream>", name, base64_encode(text.as_bytes())));
+        }
+        body.push_str(&format!("<rsp:CommandState CommandId=\"CMD-1\" State=\"{}\"><rsp:ExitCode>{}</rsp:ExitCode></rsp:CommandState>", STATE_DONE, rc));
+        return envelope(&format!("<rsp:ReceiveResponse>{}</rsp:ReceiveResponse>", body));
+    }
+
+    #[test]
+    fn connection_against_stub_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port() as i64;
+        let timeout = envelope(&format!("<s:Fault><s:Detail><f:WSManFault Code=\"{}\" Machine=\"x\"><f:Message>timed out</f:Message></f:WSManFault></s:Detail></s:Fault>",
+            FAULT_OPERATION_TIMEOUT));
+        let os = done(&[("stdout", "Microsoft Windows NT 10.0.17763.0\r\n")], 0);
+        // the second reply is chunked, as IIS likes to send them
+        let os_chunked = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n", os.len(), os);
+        let command = done(&[("stdout", "hi\r\n"), ("stderr", "oops\r\n"), ("stdout", "there\r\n")], 3);
+        let receives = vec![ http_reply("500 Internal Server Error", "", &timeout), os_chunked, http_reply("200 OK", "", &command) ];
+        let seen : Seen = Arc::new(Mutex::new(Vec::new()));
+        let server = serve(listener, receives, Arc::clone(&seen));
+
+        let host = Arc::new(RwLock::new(Host::new(&String::from("win1"))));
+        let mut conn = WinRmConnection::new(Arc::clone(&host), &String::from("127.0.0.1"), port, &String::from("Administrator"),
+            &String::from("secret"), 5);
+        conn.connect().expect("connect");
+        assert!(matches!(host.read().unwrap().os_type, Some(crate::inventory::hosts::HostOSType::Windows)));
+
+        let output = CommandOutput::none();
+        let result = conn.run_powershell(&String::from("Write-Output hi"), None, None, &output);
+        assert_eq!(result, Ok((3, String::from("hi\noops\nthere"))));
+        let streams = output.get_result(&String::new(), &String::new(), 3);
+        let streams = streams.as_ref().as_ref().unwrap();
+        assert_eq!(streams.stdout, "hi\nthere");
+        assert_eq!(streams.stderr, "oops");
+
+        drop(conn);
+        server.join().unwrap();
+        let seen = seen.lock().unwrap();
+        let actions : Vec<&str> = seen.iter().map(|(a,_,_)| a.as_str()).collect();
+        assert_eq!(actions, vec![ ACTION_CREATE, ACTION_COMMAND, ACTION_RECEIVE, ACTION_RECEIVE, ACTION_SIGNAL,
+            ACTION_COMMAND, ACTION_RECEIVE, ACTION_SIGNAL, ACTION_DELETE ]);
+        let credentials = format!("Basic {}", base64_encode(b"Administrator:secret"));
+        assert!(seen.iter().all(|(_, auth, _)| auth == &credentials));
+        // every request after Create is addressed to the shell
+        assert!(! seen[0].2.contains("SHELL-1"));
+        assert!(seen[1..].iter().all(|(_, _, body)| body.contains("<w:Selector Name=\"ShellId\">SHELL-1</w:Selector>")));
+        assert!(seen[4].2.contains(SIGNAL_TERMINATE) && seen[4].2.contains("CommandId=\"CMD-1\""));
+    }
+
+    #[test]
+    fn negotiate_only_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port() as i64;
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 8192];
+            let _r = stream.read(&mut buf);
+            let reply = http_reply("401 Unauthorized", "WWW-Authenticate: Negotiate\r\nWWW-Authenticate: Kerberos\r\n", &String::new());
+            stream.write_all(reply.as_bytes()).unwrap();
+        });
+        let host = Arc::new(RwLock::new(Host::new(&String::from("win1"))));
+        let mut conn = WinRmConnection::new(host, &String::from("127.0.0.1"), port, &String::from("Administrator"), &String::from("secret"), 5);
+        let error = conn.connect().unwrap_err();
+        assert!(error.contains("only offers Negotiate/Kerberos authentication"), "{}", error);
+        server.join().unwrap();
+    }
+}
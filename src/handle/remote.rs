@@ -17,7 +17,7 @@
 use std::sync::{Arc,Mutex,RwLock};
 use std::path::Path;
 use crate::connection::connection::Connection;
-use crate::connection::command::cmd_info;
+use crate::connection::command::{cmd_info,CommandResult,INTERNAL_PREFIX,get_kill_command};
 use crate::tasks::request::{TaskRequest, TaskRequestType};
 use crate::tasks::response::TaskResponse;
 use crate::inventory::hosts::{Host,HostOSType};
@@ -32,6 +32,8 @@ use crate::handle::response::Response;
 use crate::handle::template::Template;
 use crate::tasks::files::Recurse;
 use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration,Instant};
 
 // contains all code that eventually reaches out and touches systems to be configured.
 // this includes the local system (somewhat confusingly) in 'local' mode, and of course
@@ -88,23 +90,30 @@ impl Remote {
     // user the ability to replace unowned files
 
     pub fn make_temp_path(&self, who: &String, request: &Arc<TaskRequest>) -> Result<(PathBuf, PathBuf), Arc<TaskResponse>> {
-        let mut pb = PathBuf::new();
-        let tmpdir = match who.eq("root") {
-            false => match self.host.read().unwrap().os_type {
-                Some(HostOSType::MacOS) => format!("/Users/{}/.jet/tmp", who),
-                _ => format!("/home/{}/.jet/tmp", who),
-            }
-            true => String::from("/root/.jet/tmp")
-        };
-        pb.push(tmpdir);
+        let pb = self.get_jet_directory(who, "tmp");
         let mut pb2 = pb.clone();
         let guid = self.run_state.context.read().unwrap().get_guid();
         pb2.push(guid.as_str());
-        let create_tmp_dir = format!("mkdir -p '{}'", pb.display());
+        let create_tmp_dir = self.unwrap_string_result(request, 
+            &crate::tasks::cmd_library::get_create_directory_command(self.get_os_type(), &pb.display().to_string()))?;
         self.run_no_sudo(request, &create_tmp_dir, CheckRc::Checked)?;
         return Ok((pb.clone(), pb2.clone()));
     }
 
+    // jet keeps its own files (temp files, async job state) under ~/.jet of the connecting user
+
+    fn get_jet_directory(&self, who: &String, name: &str) -> PathBuf {
+        let mut pb = PathBuf::new();
+        let dir = match (self.host.read().unwrap().os_type, who.eq("root")) {
+            (Some(HostOSType::Windows), _) => format!("C:/Users/{}/.jet/{}", who, name),
+            (_, true) => format!("/root/.jet/{}", name),
+            (Some(HostOSType::MacOS), false) => format!("/Users/{}/.jet/{}", who, name),
+            (_, false) => format!("/home/{}/.jet/{}", who, name)
+        };
+        pb.push(dir);
+        return pb;
+    }
+
     // wrappers around running CLI commands
 
     pub fn run(&self, request: &Arc<TaskRequest>, cmd: &String, check_rc: CheckRc) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
@@ -126,9 +135,135 @@ impl Remote {
         return self.internal_run(request, cmd, Safety::Unsafe, check_rc, UseSudo::Yes, Forward::No);
     }
 
+    // independent commands that do not depend on each other's results can be run as a batch, which connections
+    // such as SSH run in parallel.  results come back in the same order as the commands.  Like run_unsafe, the caller
+    // is responsible for screening any variables in these commands.
+
+    pub fn run_parallel_unsafe(&self, request: &Arc<TaskRequest>, cmds: &Vec<(String,Forward)>, check_rc: CheckRc) -> Result<Vec<Arc<TaskResponse>>,Arc<TaskResponse>> {
+        return self.internal_run_parallel(request, cmds, Safety::Unsafe, check_rc);
+    }
+
     fn internal_run(&self, request: &Arc<TaskRequest>, cmd: &String, 
         safe: Safety, check_rc: CheckRc, use_sudo: UseSudo, forward: Forward) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
         
+        let cmd_out = self.prepare_command(request, cmd, safe, use_sudo)?;
+        // a forwarded agent only lives as long as the session that started the command, so those never run detached
+        if request.get_async().is_some() && forward == Forward::No {
+            let (limit, poll) = request.get_async().unwrap();
+            let result = self.run_async(request, &cmd_out, limit, poll);
+            return self.check_command_result(request, result, check_rc);
+        }
+        let result = self.connection.lock().unwrap().run_command(&self.response, request, &cmd_out, forward);
+        return self.check_command_result(request, result, check_rc);
+    }
+
+    // commands from tasks marked 'async' are started detached from the connection, with their output, process id and
+    // return code kept in files under ~/.jet/async.  The job is then checked on every 'poll' seconds over new commands,
+    // so it keeps going (and is still found) if the connection drops and comes back.  A job still running after 'async'
+    // seconds is killed.  Detached jobs have no terminal or stdin, so they cannot answer a become password prompt.
+    // The first check is made right away, so a job that turns out to be quick does not wait out a whole poll.
+
+    fn run_async(&self, request: &Arc<TaskRequest>, cmd: &String, limit: u64, poll: u64) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+
+        if request.get_become_password().is_some() {
+            return Err(self.response.is_failed(request, &String::from("async tasks cannot be used with a become password")));
+        }
+        if self.is_windows() {
+            return Err(self.response.is_failed(request, &String::from("async tasks are not supported on Windows hosts")));
+        }
+        let whoami = self.unwrap_string_result(request, &self.get_whoami())?;
+        let dir = self.get_jet_directory(&whoami, "async");
+        let job_id = self.run_state.context.read().unwrap().get_guid();
+        let job_file = |ext: &str| format!("{}/{}.{}", dir.display(), job_id, ext);
+
+        // the return code file is renamed into place so it is never seen half written
+        let job = format!("echo $$ > '{}'; {{ {}\n}} > '{}' 2> '{}'; echo $? > '{}' && mv '{}' '{}'", 
+            job_file("pid"), cmd, job_file("out"), job_file("err"), job_file("tmp"), job_file("tmp"), job_file("rc"));
+        let job = job.replace("'", "'\\''");
+        // the redirections must cover the whole backgrounded part, or it would hold the connection's output open
+        let launch = format!("mkdir -p '{}' || exit 1; cd /; {{ if command -v setsid >/dev/null 2>&1; then setsid sh -c '{}'; else sh -c '{}'; fi; }} >/dev/null 2>&1 </dev/null &",
+            dir.display(), job, job);
+        let started = self.run_job_command(request, &launch)?;
+        let (rc, out) = cmd_info(&started);
+        if rc != 0 {
+            return Err(self.response.is_failed(request, &format!("failed to start async job: rc={}, out={}", rc, out)));
+        }
+
+        let start_time = Instant::now();
+        let cleanup = format!("rm -f '{}/{}'.*", dir.display(), job_id);
+        let mut finished = false;
+        loop {
+            let elapsed = start_time.elapsed().as_secs();
+            // errors here are most likely a dropped connection, which the next poll will try to reconnect
+            // the status is printed as an internal line so it is not shown along with command output
+            let check = format!("printf '{}async %s\\n' \"$(cat '{}' 2>/dev/null || echo running)\"", INTERNAL_PREFIX, job_file("rc"));
+            match self.run_job_command(request, &check) {
+                Ok(status) => {
+                    let (_rc, status_out) = cmd_info(&status);
+                    let status_out = status_out.trim().strip_prefix(&format!("{}async", INTERNAL_PREFIX)).unwrap_or("").trim().to_string();
+                    match status_out.parse::<i32>() {
+                        Ok(job_rc) => {
+                            finished = true;
+                            // the saved streams are replayed on stdout and stderr, so the connection keeps them apart as usual.
+                            // the job files stay until they have been read, so a dropped connection here is retried as well
+                            match self.run_job_command(request, &format!("cat '{}'; cat '{}' >&2", job_file("out"), job_file("err"))) {
+                                Ok(output) => {
+                                    let job_result = output.command_result.as_ref().as_ref().unwrap();
+                                    let _ = self.run_job_command(request, &cleanup);
+                                    return Ok(self.response.command_ok(request, &Arc::new(Some(CommandResult { 
+                                        cmd: cmd.clone(), 
+                                        out: job_result.out.clone(), 
+                                        stdout: job_result.stdout.clone(), 
+                                        stderr: job_result.stderr.clone(), 
+                                        rc: job_rc 
+                                    }))));
+                                },
+                                Err(_) => {}
+                            }
+                        },
+                        Err(_) => {}
+                    }
+                },
+                Err(_) => {}
+            }
+            if elapsed >= limit {
+                if finished {
+                    return Err(self.response.is_failed(request, &format!("async job {} finished but its output could not be read, see {}", job_id, job_file("out"))));
+                }
+                let kill = format!("pid=$(cat '{}') && {}", job_file("pid"), get_kill_command(&String::from("$pid")));
+                let _ = self.run_job_command(request, &kill);
+                let _ = self.run_job_command(request, &cleanup);
+                return Err(self.response.is_failed(request, &format!("async job {} did not finish within {} seconds and was killed", job_id, limit)));
+            }
+            self.response.get_visitor().read().expect("read visitor").on_async_poll(&self.response.get_context(), &Arc::clone(&self.host), &job_id, elapsed, limit);
+            thread::sleep(Duration::from_secs(poll));
+        }
+    }
+
+    // commands used to manage async jobs are built here rather than from user input, and run as the connecting user
+    // since that user owns the job files
+
+    fn run_job_command(&self, request: &Arc<TaskRequest>, cmd: &String) -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
+        return self.connection.lock().unwrap().run_command(&self.response, request, cmd, Forward::No);
+    }
+
+    fn internal_run_parallel(&self, request: &Arc<TaskRequest>, cmds: &Vec<(String,Forward)>, 
+        safe: Safety, check_rc: CheckRc) -> Result<Vec<Arc<TaskResponse>>,Arc<TaskResponse>> {
+
+        let mut prepared : Vec<(String,Forward)> = Vec::new();
+        for (cmd, forward) in cmds.iter() {
+            prepared.push((self.prepare_command(request, cmd, safe, UseSudo::Yes)?, *forward));
+        }
+        let results = self.connection.lock().unwrap().run_commands(&self.response, request, &prepared);
+        let mut responses : Vec<Arc<TaskResponse>> = Vec::new();
+        for result in results.into_iter() {
+            responses.push(self.check_command_result(request, result, check_rc)?);
+        }
+        return Ok(responses);
+    }
+
+    fn prepare_command(&self, request: &Arc<TaskRequest>, cmd: &String, safe: Safety, use_sudo: UseSudo) -> Result<String,Arc<TaskResponse>> {
+
         assert!(request.request_type != TaskRequestType::Validate, "commands cannot be run in validate stage");
 
         // apply basic screening of the entire shell command, more filtering should already be done by cmd_library
@@ -142,20 +277,107 @@ impl Remote {
             }
         }
 
+        let cmd_out = self.wrap_sudo(request, &self.add_environment(request, cmd), use_sudo)?;
+        self.response.get_visitor().read().expect("read visitor").on_command_run(&self.response.get_context(), &Arc::clone(&self.host), &cmd);
+        return Ok(cmd_out);
+    }
+
+    // the environment is applied with env(1) around a shell, so it covers every part of a compound command, and still
+    // applies after sudo (which would otherwise reset it).  Values are single quoted and never screened.
+
+    fn add_environment(&self, request: &Arc<TaskRequest>, cmd: &String) -> String {
+        let environment = request.get_environment();
+        if environment.is_empty() {
+            return cmd.clone();
+        }
+        if self.is_windows() {
+            // every command is its own PowerShell process, so setting the variables first is enough
+            let assignments : Vec<String> = environment.iter().map(|(k,v)| format!("$env:{} = '{}'", k, v.replace("'", "''"))).collect();
+            return format!("{}\n{}", assignments.join("\n"), cmd);
+        }
+        let assignments : Vec<String> = environment.iter().map(|(k,v)| format!("{}='{}'", k, v.replace("'", "'\\''"))).collect();
+        return format!("env {} sh -c '{}'", assignments.join(" "), cmd.replace("'", "'\\''"));
+    }
+
+    fn wrap_sudo(&self, request: &Arc<TaskRequest>, cmd: &String, use_sudo: UseSudo) -> Result<String,Arc<TaskResponse>> {
+
         // use the sudo template to choose a new command to execute if specified.
         // this doesn't need to be sudo specifically, it's really a generic concept that can wrap a command with another tool
 
-        let cmd_out = match use_sudo {
+        if use_sudo == UseSudo::Yes && self.is_windows() && request.is_sudoing() {
+            return Err(self.response.is_failed(request, &String::from("sudo and other become methods are not supported on Windows hosts")));
+        }
+        return match use_sudo {
             UseSudo::Yes => match self.template.add_sudo_details(request, &cmd) {
-                Ok(x) => x,
-                Err(y) => { return Err(self.response.is_failed(request, &format!("failure constructing sudo command: {}", y))); }
+                Ok(x) => Ok(x),
+                Err(y) => Err(self.response.is_failed(request, &format!("failure constructing sudo command: {}", y)))
             },
-            UseSudo::No => cmd.clone() 
+            UseSudo::No => Ok(cmd.clone())
         };
+    }
 
-        self.response.get_visitor().read().expect("read visitor").on_command_run(&self.response.get_context(), &Arc::clone(&self.host), &cmd);
+    // several quick queries (usually from cmd_library) can be combined into one shell invocation, saving a round trip
+    // per command.  Each command's output is followed by a delimiter line carrying its return code, and the (rc, output)
+    // pairs are returned in order.  The whole batch runs under a single sudo wrapper.
 
-        let result = self.connection.lock().unwrap().run_command(&self.response, request, &cmd_out, forward);
+    pub fn run_batch(&self, request: &Arc<TaskRequest>, cmds: &Vec<String>) -> Result<Vec<(i32,String)>,Arc<TaskResponse>> {
+
+        assert!(request.request_type != TaskRequestType::Validate, "commands cannot be run in validate stage");
+
+        // the batch is a POSIX shell script, Windows hosts just run the commands one at a time
+        if self.is_windows() {
+            let mut results : Vec<(i32,String)> = Vec::new();
+            for cmd in cmds.iter() {
+                let result = self.run(request, cmd, CheckRc::Unchecked)?;
+                results.push(cmd_info(&result));
+            }
+            return Ok(results);
+        }
+
+        let delimiter = format!("__jet_batch_{}__", self.run_state.context.read().unwrap().get_guid());
+        let mut script = String::new();
+        for cmd in cmds.iter() {
+            match screen_general_input_loose(&cmd) {
+                Ok(_x) => {},
+                Err(y) => return Err(self.response.is_failed(request, &y.clone()))
+            }
+            self.response.get_visitor().read().expect("read visitor").on_command_run(&self.response.get_context(), &Arc::clone(&self.host), &cmd);
+            script.push_str(&format!("{} 2>&1; printf '\\n{} %d\\n' $?; ", cmd, delimiter));
+        }
+        let batch = format!("sh -c '{}'", script.replace("'", "'\\''"));
+        let cmd_out = self.wrap_sudo(request, &self.add_environment(request, &batch), UseSudo::Yes)?;
+
+        let result = self.connection.lock().unwrap().run_command(&self.response, request, &cmd_out, Forward::No)?;
+        let (rc, out) = cmd_info(&result);
+
+        let mut results : Vec<(i32,String)> = Vec::new();
+        let mut lines : Vec<&str> = Vec::new();
+        for line in out.lines() {
+            match line.strip_prefix(&delimiter) {
+                Some(x) => {
+                    let cmd_rc = match x.trim().parse::<i32>() {
+                        Ok(y) => y,
+                        Err(_) => { break; }
+                    };
+                    // the delimiter is printed on a line of its own, so drop the newline added before it
+                    let mut cmd_out = lines.join("\n");
+                    if cmd_out.ends_with('\n') {
+                        cmd_out.pop();
+                    }
+                    results.push((cmd_rc, cmd_out));
+                    lines.clear();
+                },
+                None => { lines.push(line); }
+            }
+        }
+        if results.len() != cmds.len() {
+            return Err(self.response.is_failed(request, &format!("batched command failed: rc={}, out={}", rc, out)));
+        }
+        return Ok(results);
+    }
+
+    fn check_command_result(&self, request: &Arc<TaskRequest>, result: Result<Arc<TaskResponse>,Arc<TaskResponse>>, check_rc: CheckRc) 
+        -> Result<Arc<TaskResponse>,Arc<TaskResponse>> {
 
         // if requested, turn non-zero return codes into errors
 
@@ -180,6 +402,12 @@ impl Remote {
         return os_type.unwrap();
     }
 
+    // commands for Windows hosts are PowerShell rather than POSIX shell
+
+    fn is_windows(&self) -> bool {
+        return matches!(self.host.read().unwrap().os_type, Some(HostOSType::Windows));
+    }
+
     // when we need to write a file we need to place it in a particular temp location and then move it
 
     fn get_transfer_location(&self, request: &Arc<TaskRequest>, _path: &String) -> Result<(Option<PathBuf>, Option<PathBuf>), Arc<TaskResponse>> {
@@ -204,13 +432,34 @@ impl Remote {
         return result;
     }
 
-    // more supporting code for file transfer using temp files
+    // more supporting code for file transfer using temp files.  The jet temp directory is often on a different filesystem
+    // than the destination, where mv is a copy and not atomic, so the file is first staged under a hidden name next to the
+    // destination and then renamed over it.  A reader of the destination sees either the old or the new file, never part of one.
 
     fn conditionally_move_back(&self, request: &Arc<TaskRequest>, temp_dir: Option<PathBuf>, temp_path: Option<PathBuf>, desired_path: &String) -> Result<(), Arc<TaskResponse>> {
         if temp_dir.is_some() {
-            let move_to_correct_location = format!("mv '{}' '{}'", temp_path.as_ref().unwrap().display(), desired_path);
-            let delete_tmp_location = format!("rm '{}'", temp_path.as_ref().unwrap().display());
-            let result = self.run(request, &move_to_correct_location, CheckRc::Checked);
+            let temp_path = temp_path.as_ref().unwrap();
+            let desired = Path::new(desired_path);
+            let staged_path = match (desired.parent(), desired.file_name()) {
+                (Some(parent), Some(name)) => parent.join(format!(".{}.{}", name.to_string_lossy(), temp_path.file_name().unwrap().to_string_lossy())),
+                _ => { return Err(self.response.is_failed(request, &format!("invalid destination path: {}", desired_path))); }
+            };
+            let (move_to_staging, rename_into_place, delete_tmp_location) = match self.is_windows() {
+                false => (
+                    format!("mv '{}' '{}'", temp_path.display(), staged_path.display()),
+                    format!("mv -f '{}' '{}'", staged_path.display(), desired_path),
+                    format!("rm -f '{}' '{}'", temp_path.display(), staged_path.display())
+                ),
+                true => (
+                    format!("Move-Item -Force -LiteralPath '{}' -Destination '{}'", temp_path.display(), staged_path.display()),
+                    format!("Move-Item -Force -LiteralPath '{}' -Destination '{}'", staged_path.display(), desired_path),
+                    format!("Remove-Item -Force -ErrorAction SilentlyContinue -LiteralPath '{}', '{}'", temp_path.display(), staged_path.display())
+                )
+            };
+            let mut result = self.run(request, &move_to_staging, CheckRc::Checked);
+            if result.is_ok() {
+                result = self.run(request, &rename_into_place, CheckRc::Checked);
+            }
             if result.is_err() {
                 let _ = self.run(request, &delete_tmp_location, CheckRc::Unchecked);
                 return Err(result.unwrap_err());
@@ -225,7 +474,8 @@ impl Remote {
         where G: FnMut(&String) -> Result<(), Arc<TaskResponse>> {   
         let (temp_dir, temp_path) = self.get_transfer_location(request, path)?;
         let real_path = self.get_effective_filename(temp_dir.clone(), temp_path.clone(), path); /* will be either temp_path or path */
-        self.response.get_visitor().read().expect("read visitor").on_before_transfer(&self.response.get_context(), &Arc::clone(&self.host), &real_path);
+        self.response.get_visitor().read().expect("read visitor").on_before_transfer(&self.response.get_context(), &Arc::clone(&self.host), &real_path, 
+            data.len() as u64);
         let xfer_result = self.connection.lock().unwrap().write_data(&self.response, request, data, &real_path)?;
         before_complete(&real_path.clone())?;
         self.conditionally_move_back(request, temp_dir.clone(), temp_path.clone(), path)?;
@@ -238,7 +488,11 @@ impl Remote {
     where G: FnMut(&String) -> Result<(), Arc<TaskResponse>> {   
         let (temp_dir, temp_path) = self.get_transfer_location(request, dest)?;
         let real_path = self.get_effective_filename(temp_dir.clone(), temp_path.clone(), dest); /* will be either temp_path or path */
-        self.response.get_visitor().read().expect("read visitor").on_before_transfer(&self.response.get_context(), &Arc::clone(&self.host), &real_path);
+        let size = match src.metadata() {
+            Ok(x) => x.len(),
+            Err(y) => { return Err(self.response.is_failed(request, &format!("failed to stat source file: {}: {}", src.display(), y))); }
+        };
+        self.response.get_visitor().read().expect("read visitor").on_before_transfer(&self.response.get_context(), &Arc::clone(&self.host), &real_path, size);
         let xfer_result = self.connection.lock().unwrap().copy_file(&self.response, &request, src, &real_path)?;        
         before_complete(&real_path.clone())?;
         self.conditionally_move_back(request, temp_dir.clone(), temp_path.clone(), dest)?;
@@ -253,10 +507,14 @@ impl Remote {
         
         let result = self.run(request, &cmd, CheckRc::Unchecked)?;
         let (rc, out) = cmd_info(&result);
+        return Ok(self.parse_mode(rc, &out));
+    }
+
+    fn parse_mode(&self, rc: i32, out: &String) -> Option<String> {
         return match rc {
             // we can all unwrap because all possible string lists will have at least 1 element
-            0 => Ok(Some(out.split_whitespace().nth(0).unwrap().to_string())),
-            _ => Ok(None),
+            0 => Some(out.split_whitespace().nth(0).unwrap().to_string()),
+            _ => None,
         }
     }
 
@@ -313,21 +571,23 @@ impl Remote {
         return self.run(request, &cmd, CheckRc::Checked);  
     }
 
-    // return the (owner,group) tuple for a remote file.  If the command fails this will instead return None
-    // so consider running get_mode first.  See the various file modules for examples.
-
-    pub fn get_ownership(&self, request: &Arc<TaskRequest>, path: &String) -> Result<Option<(String,String)>,Arc<TaskResponse>> {
-        let get_cmd_result = crate::tasks::cmd_library::get_ownership_command(self.get_os_type(), path);
-        let cmd = self.unwrap_string_result(&request, &get_cmd_result)?;
-        
-        let result = self.run(request, &cmd, CheckRc::Unchecked)?;
-        let (rc, out) = cmd_info(&result);
+    // parses the (owner,group) tuple for a remote file from the output of get_ownership_command.  If the command failed
+    // this will instead return None.
 
+    fn parse_ownership(&self, request: &Arc<TaskRequest>, cmd: &String, rc: i32, out: &String) -> Result<Option<(String,String)>,Arc<TaskResponse>> {
         match rc {
             0 => {},
             _ => { return Ok(None); },
         }
 
+        if self.is_windows() {
+            let mut lines = out.lines();
+            return match (lines.next(), lines.next()) {
+                (Some(owner), Some(group)) => Ok(Some((owner.trim().to_string(), group.trim().to_string()))),
+                _ => Err(self.response.is_failed(request, &format!("unexpected output format from {}: {}", cmd, out)))
+            };
+        }
+
         let mut split = out.split_whitespace();
         let owner = match split.nth(2) {
             Some(x) => x,
@@ -400,23 +660,38 @@ impl Remote {
     pub fn query_common_file_attributes(&self, request: &Arc<TaskRequest>, remote_path: &String, 
         attributes_in: &Option<FileAttributesEvaluated>, changes: &mut Vec<Field>, recurse: Recurse) -> Result<Option<String>,Arc<TaskResponse>> {
 
-        let remote_mode = self.get_mode(request, remote_path)?;
-        
+        // when attributes need comparing, the mode and ownership queries are batched into one round trip
+
+        if attributes_in.is_none() || recurse == Recurse::Yes {
+            let remote_mode = self.get_mode(request, remote_path)?;
+            if remote_mode.is_none() {
+                changes.push(Field::Content);
+                return Ok(None);
+            }
+            if attributes_in.is_some() {
+                changes.push(Field::Owner);
+                changes.push(Field::Group);
+                changes.push(Field::Mode);
+            }
+            return Ok(remote_mode);
+        }
+
+        let os_type = self.get_os_type();
+        let mode_cmd = self.unwrap_string_result(&request, &crate::tasks::cmd_library::get_mode_command(os_type, remote_path))?;
+        let owner_cmd = self.unwrap_string_result(&request, &crate::tasks::cmd_library::get_ownership_command(os_type, remote_path))?;
+        let results = self.run_batch(request, &vec![mode_cmd, owner_cmd.clone()])?;
+        let (mode_rc, mode_out) = &results[0];
+        let (owner_rc, owner_out) = &results[1];
+
+        let remote_mode = self.parse_mode(*mode_rc, mode_out);
         if remote_mode.is_none() {
             changes.push(Field::Content);
             return Ok(None);
         }
 
-        if attributes_in.is_some() && recurse == Recurse::Yes {
-            changes.push(Field::Owner);
-            changes.push(Field::Group);
-            changes.push(Field::Mode);
-            return Ok(remote_mode);
-        }
-
-        if attributes_in.is_some() {
+        {
             let attributes = attributes_in.as_ref().unwrap();
-            let owner_result = self.get_ownership(request, remote_path)?;
+            let owner_result = self.parse_ownership(request, &owner_cmd, *owner_rc, owner_out)?;
             if owner_result.is_none() {
                 return Err(self.response.is_failed(request, &String::from("file was deleted unexpectedly mid-operation")));
             }
@@ -20,7 +20,7 @@ use crate::tasks::response::{TaskStatus, TaskResponse};
 use crate::inventory::hosts::Host;
 use crate::playbooks::traversal::RunState;
 use crate::tasks::fields::Field;
-use crate::connection::command::CommandResult;
+use crate::connection::command::{CommandResult,OutputKind};
 use crate::playbooks::context::PlaybookContext;
 use crate::playbooks::visitor::PlaybookVisitor;
 use std::sync::RwLock;
@@ -69,8 +69,9 @@ impl Response {
         return self.is_failed(request, &String::from("not supported"));
     }
 
-    pub fn command_failed(&self, _request: &Arc<TaskRequest>, result: &Arc<Option<CommandResult>>) -> Arc<TaskResponse> {
+    pub fn command_failed(&self, request: &Arc<TaskRequest>, result: &Arc<Option<CommandResult>>) -> Arc<TaskResponse> {
         // used internally by run functions in remote.rs when commands fail, suitable for use as a final module response
+        let result = &self.redact_command_result(request, result, true);
         self.get_visitor().read().expect("read visitor").on_command_failed(&self.get_context(), &Arc::clone(&self.host), &Arc::clone(result));
         return Arc::new(TaskResponse {
             status: TaskStatus::Failed,
@@ -82,15 +83,51 @@ impl Response {
         });
     }
 
-    pub fn command_ok(&self, _request: &Arc<TaskRequest>, result: &Arc<Option<CommandResult>>) -> Arc<TaskResponse> {
-        // used internally by run functions in remote.rs when commands succeed, suitable for use as a final module response
-        self.get_visitor().read().expect("read visitor").on_command_ok(&self.get_context(), &Arc::clone(&self.host), &Arc::clone(result));
+    pub fn command_timed_out(&self, request: &Arc<TaskRequest>, result: &Arc<Option<CommandResult>>) -> Arc<TaskResponse> {
+        // used by connections when a command was still running at the task timeout and had to be killed
+        let result = &self.redact_command_result(request, result, true);
+        self.get_visitor().read().expect("read visitor").on_command_timed_out(&self.get_context(), &Arc::clone(&self.host), &Arc::clone(result), request.get_timeout());
+        return Arc::new(TaskResponse {
+            status: TaskStatus::Failed,
+            changes: Vec::new(), 
+            msg: Some(format!("command timed out after {} seconds", request.get_timeout())), 
+            command_result: Arc::clone(&result), 
+            with: Arc::new(None), 
+            and: Arc::new(None)
+        });
+    }
+
+    pub fn command_ok(&self, request: &Arc<TaskRequest>, result: &Arc<Option<CommandResult>>) -> Arc<TaskResponse> {
+        // used internally by run functions in remote.rs when commands succeed, suitable for use as a final module response.
+        // modules still need the real output to parse, so only the displayed copy has environment values masked in it
+        let displayed = self.redact_command_result(request, result, true);
+        self.get_visitor().read().expect("read visitor").on_command_ok(&self.get_context(), &Arc::clone(&self.host), &displayed);
+        let result = &self.redact_command_result(request, result, false);
         return Arc::new(TaskResponse {
             status: TaskStatus::IsExecuted,
             changes: Vec::new(), msg: None, command_result: Arc::clone(&result), with: Arc::new(None), and: Arc::new(None)
         });
     }
 
+    pub fn command_output(&self, request: &Arc<TaskRequest>, kind: OutputKind, line: &String) {
+        // used by connections to show output while a command is still running
+        self.get_visitor().read().expect("read visitor").on_command_output(&self.get_context(), &Arc::clone(&self.host), kind, &request.redact(line));
+    }
+
+    fn redact_command_result(&self, request: &Arc<TaskRequest>, result: &Arc<Option<CommandResult>>, output: bool) -> Arc<Option<CommandResult>> {
+        if request.get_environment().is_empty() || result.is_none() {
+            return Arc::clone(result);
+        }
+        let r = result.as_ref().as_ref().unwrap();
+        return Arc::new(Some(CommandResult {
+            cmd: request.redact(&r.cmd),
+            out: match output { true => request.redact(&r.out), false => r.out.clone() },
+            stdout: match output { true => request.redact(&r.stdout), false => r.stdout.clone() },
+            stderr: match output { true => request.redact(&r.stderr), false => r.stderr.clone() },
+            rc: r.rc
+        }));
+    }
+
     pub fn is_skipped(&self, request: &Arc<TaskRequest>) -> Arc<TaskResponse> {
         // returned by playbook traversal code when skipping over a task due to a condition not being met or other factors
         assert!(request.request_type == TaskRequestType::Validate, "is_skipped response can only be returned for a validation request");
@@ -205,7 +205,13 @@ impl Template {
         }
     }
 
-
+    pub fn path_option(&self, request: &Arc<TaskRequest>, tm: TemplateMode, field: &String, template: &Option<String>) -> Result<Option<String>,Arc<TaskResponse>> {
+        // a version of path for optional parameters
+        return match template {
+            Some(x) => Ok(Some(self.path(request, tm, field, x)?)),
+            None => Ok(None)
+        };
+    }
 
     pub fn string_option(&self, request: &Arc<TaskRequest>, tm: TemplateMode, field: &String, template: &Option<String>) -> Result<Option<String>,Arc<TaskResponse>> {
         // templates an optional string
@@ -391,6 +397,9 @@ impl Template {
         let mut data = serde_yaml::Mapping::new();            
         data.insert(serde_yaml::Value::String(String::from("jet_sudo_user")), serde_yaml::Value::String(user.clone()));
         data.insert(serde_yaml::Value::String(String::from("jet_command")), serde_yaml::Value::String(cmd.to_string()));
+        // for tools like su that take the command as a single argument
+        data.insert(serde_yaml::Value::String(String::from("jet_command_quoted")), 
+            serde_yaml::Value::String(format!("'{}'", cmd.replace("'", "'\\''"))));
         let result = self.detached_templar.render(&sudo_template, data, TemplateMode::Strict)?;
         return Ok(result)
     }
@@ -26,6 +26,7 @@ use serde_yaml;
 pub enum HostOSType {
     Linux,
     MacOS,
+    Windows,
 }
 
 #[derive(Clone,Copy,Debug)]
@@ -101,12 +102,14 @@ impl Host {
         }
     }
 
-    // used by connection class on initial connect
+    // used by connection class on initial connect.  WinRM connections have no uname and pass the Windows version string instead.
     pub fn set_os_info(&mut self, uname_output: &String) -> Result<(),String> {
         if uname_output.starts_with("Linux") {
             self.os_type = Some(HostOSType::Linux);
         } else if uname_output.starts_with("Darwin") {
             self.os_type = Some(HostOSType::MacOS);
+        } else if uname_output.starts_with("Microsoft Windows") {
+            self.os_type = Some(HostOSType::Windows);
         } else {
             return Err(format!("OS Type could not be detected from uname -a: {}", uname_output));
         }
@@ -28,8 +28,10 @@ use crate::util::io::{quit};
 use crate::inventory::inventory::Inventory;
 use crate::inventory::loading::{load_inventory};
 use crate::cli::show::{show_inventory_group,show_inventory_host};
+use crate::cli::roles::roles_install;
 use crate::cli::parser::{CliParser};
 use crate::cli::playbooks::{playbook_ssh,playbook_local,playbook_check_ssh,playbook_check_local,playbook_simulate}; // FIXME: check modes coming
+use crate::cli::playbooks::{playbook_container,playbook_check_container};
 use std::sync::{Arc,RwLock};
 use std::process;
 
@@ -55,7 +57,8 @@ fn liftoff() -> Result<(),String> {
     let inventory : Arc<RwLock<Inventory>> = Arc::new(RwLock::new(Inventory::new()));
 
     match cli_parser.mode {
-        cli::parser::CLI_MODE_SSH | cli::parser::CLI_MODE_CHECK_SSH | cli::parser::CLI_MODE_SHOW | cli::parser::CLI_MODE_SIMULATE => {
+        cli::parser::CLI_MODE_SSH | cli::parser::CLI_MODE_CHECK_SSH | cli::parser::CLI_MODE_SHOW | cli::parser::CLI_MODE_SIMULATE |
+        cli::parser::CLI_MODE_CONTAINER | cli::parser::CLI_MODE_CHECK_CONTAINER => {
             load_inventory(&inventory, Arc::clone(&cli_parser.inventory_paths))?;
             if ! cli_parser.inventory_set {
                 return Err(String::from("--inventory is required"));
@@ -70,7 +73,7 @@ fn liftoff() -> Result<(),String> {
     };
 
     match cli_parser.mode {
-        cli::parser::CLI_MODE_SHOW => {},
+        cli::parser::CLI_MODE_SHOW | cli::parser::CLI_MODE_ROLES_INSTALL => {},
         _ => {
             if ! cli_parser.playbook_set {
                 return Err(String::from("--playbook is required"));
@@ -90,11 +93,20 @@ fn liftoff() -> Result<(),String> {
                 1
             }
         }
+        cli::parser::CLI_MODE_ROLES_INSTALL => match roles_install(&cli_parser) {
+            Ok(_) => 0,
+            Err(s) => {
+                println!("{}", s);
+                1
+            }
+        }
         cli::parser::CLI_MODE_SSH         => playbook_ssh(&inventory, &cli_parser),
         cli::parser::CLI_MODE_CHECK_SSH   => playbook_check_ssh(&inventory, &cli_parser),
         cli::parser::CLI_MODE_LOCAL       => playbook_local(&inventory, &cli_parser),
         cli::parser::CLI_MODE_CHECK_LOCAL => playbook_check_local(&inventory, &cli_parser),
         cli::parser::CLI_MODE_SIMULATE    => playbook_simulate(&inventory, &cli_parser),
+        cli::parser::CLI_MODE_CONTAINER   => playbook_container(&inventory, &cli_parser),
+        cli::parser::CLI_MODE_CHECK_CONTAINER => playbook_check_container(&inventory, &cli_parser),
 
         _ => { println!("invalid CLI mode"); 1 }
     };
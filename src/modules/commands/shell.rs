@@ -16,7 +16,8 @@
 
 use crate::tasks::*;
 use crate::handle::handle::TaskHandle;
-use crate::connection::command::cmd_info;
+use crate::connection::command::CommandResult;
+use crate::tasks::cmd_library::screen_general_input_loose;
 //#[allow(unused_imports)]
 use serde::{Deserialize};
 use std::sync::{Arc,RwLock};
@@ -34,6 +35,11 @@ pub struct ShellTask {
     pub changed_when: Option<String>, 
     #[serde(rename = "unsafe")]
     pub unsafe_: Option<String>, /* FIXME: can use r#unsafe instead */
+    pub chdir: Option<String>,
+    pub umask: Option<String>,
+    pub creates: Option<String>,
+    pub removes: Option<String>,
+    pub stdin: Option<String>,
     pub with: Option<PreLogicInput>,
     pub and: Option<PostLogicInput>,
 }
@@ -43,6 +49,11 @@ struct ShellAction {
     pub failed_when: Option<String>,
     pub changed_when: Option<String>,
     pub unsafe_: bool,
+    pub chdir: Option<String>,
+    pub umask: Option<String>,
+    pub creates: Option<String>,
+    pub removes: Option<String>,
+    pub stdin: Option<String>,
 }
 
 
@@ -69,6 +80,20 @@ impl IsTask for ShellTask {
                     save: handle.template.string_option_no_spaces(&request, tm, &String::from("save"), &self.save)?,
                     failed_when: handle.template.string_option_unsafe_for_shell(&request, tm, &String::from("failed_when"), &self.failed_when)?,
                     changed_when: handle.template.string_option_unsafe_for_shell(&request, tm, &String::from("changed_when"), &self.changed_when)?,
+                    chdir: handle.template.path_option(&request, tm, &String::from("chdir"), &self.chdir)?,
+                    umask: {
+                        let umask = handle.template.string_option_no_spaces(&request, tm, &String::from("umask"), &self.umask)?;
+                        match umask.as_ref() {
+                            Some(x) if tm != TemplateMode::Off && (x.len() < 3 || x.len() > 4 || ! x.chars().all(|c| c >= '0' && c <= '7')) => {
+                                return Err(handle.response.is_failed(request, &format!("(umask) must be 3 or 4 octal digits, such as 022, was {}", x)));
+                            },
+                            _ => umask
+                        }
+                    },
+                    creates: handle.template.path_option(&request, tm, &String::from("creates"), &self.creates)?,
+                    removes: handle.template.path_option(&request, tm, &String::from("removes"), &self.removes)?,
+                    // stdin is quoted when passed to the command, so it does not need to be safe for the shell
+                    stdin: handle.template.string_option_unsafe_for_shell(&request, tm, &String::from("stdin"), &self.stdin)?,
 
                 }),
                 with: Arc::new(PreLogicInput::template(&handle, &request, tm, &self.with)?),
@@ -86,18 +111,37 @@ impl IsAction for ShellAction {
         match request.request_type {
 
             TaskRequestType::Query => {
+                // creates and removes make the command idempotent, skipping it when its work has already been done
+                if self.creates.is_some() && handle.remote.get_mode(request, self.creates.as_ref().unwrap())?.is_some() {
+                    return Ok(handle.response.is_matched(&request));
+                }
+                if self.removes.is_some() && handle.remote.get_mode(request, self.removes.as_ref().unwrap())?.is_none() {
+                    return Ok(handle.response.is_matched(&request));
+                }
                 return Ok(handle.response.needs_execution(&request));
             },
 
             TaskRequestType::Execute => {
                 let task_result : Arc<TaskResponse>;
-                if self.unsafe_ {
-                    task_result = handle.remote.run_unsafe(&request, &self.cmd.clone(), CheckRc::Unchecked)?;
+                // with async set on the task, this is the command that runs detached
+                let detachable = request.detachable();
+                if self.chdir.is_some() || self.umask.is_some() || self.stdin.is_some() {
+                    // the user command gets the usual screening, the parts added around it are already screened or quoted
+                    if ! self.unsafe_ {
+                        match screen_general_input_loose(&self.cmd) {
+                            Ok(_) => {},
+                            Err(y) => { return Err(handle.response.is_failed(request, &y)); }
+                        }
+                    }
+                    task_result = handle.remote.run_unsafe(&detachable, &self.get_full_command(), CheckRc::Unchecked)?;
+                } else if self.unsafe_ {
+                    task_result = handle.remote.run_unsafe(&detachable, &self.cmd.clone(), CheckRc::Unchecked)?;
                 } else {
-                    task_result = handle.remote.run(&request, &self.cmd.clone(), CheckRc::Unchecked)?;
+                    task_result = handle.remote.run(&detachable, &self.cmd.clone(), CheckRc::Unchecked)?;
                 }
-                let (rc, out) = cmd_info(&task_result);
-                let map_data = build_results_map(rc, &out);
+                let command_result = task_result.command_result.as_ref().as_ref().unwrap();
+                let rc = command_result.rc;
+                let map_data = build_results_map(command_result);
 
                 let should_fail = match self.failed_when.is_none() {
                     true => match rc { 0 => false, _ => true },
@@ -136,13 +180,39 @@ impl IsAction for ShellAction {
 
 }
 
-fn build_results_map(rc: i32, out: &String) -> serde_yaml::Mapping {
+impl ShellAction {
+
+    // builds "umask 022 && cd 'dir' && printf '%s\n' 'input' | { cmd }" from whichever options were given.  The group is
+    // closed on a new line so commands ending in ; or & still parse.  The whole script is handed to its own shell, as
+    // the sudo template would otherwise only wrap the part before the first &&.
+
+    fn get_full_command(&self) -> String {
+        let mut prefix = String::new();
+        if self.umask.is_some() {
+            prefix.push_str(&format!("umask {} && ", self.umask.as_ref().unwrap()));
+        }
+        if self.chdir.is_some() {
+            prefix.push_str(&format!("cd '{}' && ", self.chdir.as_ref().unwrap()));
+        }
+        let script = match self.stdin.as_ref() {
+            Some(x) => format!("{}printf '%s\\n' '{}' | {{ {}\n}}", prefix, x.replace("'", "'\\''"), self.cmd),
+            None => format!("{}{{ {}\n}}", prefix, self.cmd)
+        };
+        return format!("sh -c '{}'", script.replace("'", "'\\''"));
+    }
+}
+
+// 'out' has both streams combined in the order they were written, 'stdout' and 'stderr' have them apart
+
+fn build_results_map(command_result: &CommandResult) -> serde_yaml::Mapping {
     let mut result = serde_yaml::Mapping::new();
-    let num : serde_yaml::Value = serde_yaml::from_str(&format!("{}", rc)).unwrap();
+    let num : serde_yaml::Value = serde_yaml::from_str(&format!("{}", command_result.rc)).unwrap();
     result.insert(serde_yaml::Value::String(String::from("rc")), num);
     //result.insert(serde_yaml::Value::String(String::from("rc")),  serde_yaml::Value::String(format!("{}", rc)));
 
-    result.insert(serde_yaml::Value::String(String::from("out")), serde_yaml::Value::String(out.clone()));
+    result.insert(serde_yaml::Value::String(String::from("out")), serde_yaml::Value::String(command_result.out.clone()));
+    result.insert(serde_yaml::Value::String(String::from("stdout")), serde_yaml::Value::String(command_result.stdout.clone()));
+    result.insert(serde_yaml::Value::String(String::from("stderr")), serde_yaml::Value::String(command_result.stderr.clone()));
     return result;
 }
 
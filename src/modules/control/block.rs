@@ -0,0 +1,49 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::tasks::*;
+use crate::handle::handle::TaskHandle;
+use crate::registry::list::Task;
+use serde::Deserialize;
+use std::sync::Arc;
+
+const MODULE: &str = "block";
+
+// a block groups tasks so that hosts failing part way through can be recovered by the rescue tasks, and so that the
+// always tasks run no matter what.  Blocks are not run like other modules, traversal.rs walks the task lists itself
+// (see process_block), and the with/and settings of the block are passed down to each task inside it.
+
+#[derive(Deserialize,Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BlockTask {
+    pub name: Option<String>,
+    pub tasks: Vec<Task>,
+    pub rescue: Option<Vec<Task>>,
+    pub always: Option<Vec<Task>>,
+    pub with: Option<PreLogicInput>,
+    pub and: Option<PostLogicInput>
+}
+
+impl IsTask for BlockTask {
+
+    fn get_module(&self) -> String { String::from(MODULE) }
+    fn get_name(&self) -> Option<String> { self.name.clone() }
+    fn get_with(&self) -> Option<PreLogicInput> { self.with.clone() }
+
+    fn evaluate(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>, _tm: TemplateMode) -> Result<EvaluatedTask, Arc<TaskResponse>> {
+        return Err(handle.response.is_failed(request, &String::from("blocks cannot be run as a single task")));
+    }
+}
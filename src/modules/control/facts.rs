@@ -80,7 +80,8 @@ impl FactsAction {
         let facts = Arc::new(RwLock::new(serde_yaml::Mapping::new()));
         match os_type {
             Some(HostOSType::Linux) => { self.do_linux_facts(handle, request, &facts)?; },
-            Some(HostOSType::MacOS) => { self.do_mac_facts(handle, request, &facts)?;   }
+            Some(HostOSType::MacOS) => { self.do_mac_facts(handle, request, &facts)?;   },
+            Some(HostOSType::Windows) => { self.do_windows_facts(handle, request, &facts)?; }
             None => { return Err(handle.response.is_failed(request, &String::from("facts not implemented for OS Type"))); }
         };
         handle.host.write().unwrap().update_facts(&facts);
@@ -99,6 +100,14 @@ impl FactsAction {
         return Ok(());
     }
 
+    fn do_windows_facts(&self, _handle: &Arc<TaskHandle>, _request: &Arc<TaskRequest>, mapping: &Arc<RwLock<serde_yaml::Mapping>>) -> Result<(), Arc<TaskResponse>> {
+        // sets jet_os_type=Windows
+        self.insert_string(mapping, &String::from("jet_os_type"), &String::from("Windows"));
+        self.insert_string(mapping, &String::from("jet_os_flavor"), &String::from("Windows"));
+
+        return Ok(());
+    }
+
     fn do_linux_facts(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>, mapping: &Arc<RwLock<serde_yaml::Mapping>>) -> Result<(), Arc<TaskResponse>> {
         // sets jet_os_type=Linux
         self.insert_string(mapping, &String::from("jet_os_type"), &String::from("Linux"));
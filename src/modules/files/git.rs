@@ -22,6 +22,7 @@ use serde::{Deserialize};
 use std::sync::Arc;
 use std::vec::Vec;
 use crate::tasks::files::Recurse;
+use crate::connection::command::Forward;
 use std::collections::HashMap;
 
 const MODULE: &str = "git";
@@ -124,18 +125,17 @@ impl IsAction for GitAction {
                             // minor FIXME: this module does not currently deal with repo URLs changing
                             // when a git directory has already been checked out at a given location
                             _ => {
-                                let local_version = self.get_local_version(handle, request)?;
-                                if local_version.is_none() {
-                                    changes.push(Field::Version);
-                                }
-                                else {
-                                    let remote_version = self.get_remote_version(handle, request)?;
-                                    let local_branch = self.get_local_branch(handle, request)?;
-                                    if self.update && (! remote_version.eq(&local_version.unwrap())) {
+                                match self.get_versions(handle, request)? {
+                                    None => {
                                         changes.push(Field::Version);
-                                    }
-                                    if ! local_branch.eq(&self.branch) {
-                                        changes.push(Field::Branch);
+                                    },
+                                    Some((local_version, remote_version, local_branch)) => {
+                                        if self.update && (! remote_version.eq(&local_version)) {
+                                            changes.push(Field::Version);
+                                        }
+                                        if ! local_branch.eq(&self.branch) {
+                                            changes.push(Field::Branch);
+                                        }
                                     }
                                 }
 
@@ -203,28 +203,35 @@ impl GitAction {
         }
     }
 
-    fn get_local_version(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>) -> Result<Option<String>, Arc<TaskResponse>> {
-        let cmd = format!("git -C {} rev-parse HEAD", self.path);
-        let result = handle.remote.run_unsafe(request, &cmd, CheckRc::Unchecked)?;
-        let (rc, out) = cmd_info(&result);
-        if rc == 0 {
-            return Ok(Some(out.replace("\n","")));
-        } else {
-            return Ok(None);
-        }
-    }
+    // the local version, remote version, and local branch are independent queries, so they are run as one parallel batch.
+    // returns None if the checkout has no HEAD yet (and so has no version or branch to compare)
 
-    fn get_remote_version(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>) -> Result<String, Arc<TaskResponse>> {
+    fn get_versions(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>) -> Result<Option<(String,String,String)>, Arc<TaskResponse>> {
         let ssh_options = self.get_ssh_options_string();
-        let cmd = format!("{} git ls-remote {} | head -n 1 | cut -f 1", ssh_options, self.repo);
-        let result = match self.is_ssh_repo() {
-            true  => handle.remote.run_forwardable(request, &cmd, CheckRc::Checked)?,
-            false => handle.remote.run_unsafe(&request, &cmd, CheckRc::Checked)?
+        let forward = match self.is_ssh_repo() {
+            true  => Forward::Yes,
+            false => Forward::No
         };
-        let (_rc, out) = cmd_info(&result);
-        return Ok(out);
+        let cmds = vec![
+            (format!("git -C {} rev-parse HEAD", self.path), Forward::No),
+            (format!("{} git ls-remote {} | head -n 1 | cut -f 1", ssh_options, self.repo), forward),
+            (format!("git -C {} rev-parse --abbrev-ref HEAD", self.path), Forward::No)
+        ];
+        let results = handle.remote.run_parallel_unsafe(request, &cmds, CheckRc::Unchecked)?;
+        let (rc, local_version) = cmd_info(&results[0]);
+        if rc != 0 {
+            return Ok(None);
+        }
+        let mut outputs : Vec<String> = Vec::new();
+        for result in results[1..].iter() {
+            let (rc, out) = cmd_info(result);
+            if rc != 0 {
+                return Err(handle.response.command_failed(request, &Arc::new(result.command_result.as_ref().clone())));
+            }
+            outputs.push(out);
+        }
+        return Ok(Some((local_version.replace("\n",""), outputs[0].clone(), outputs[1].clone())));
     }
-    
 
     fn pull(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>) -> Result<(), Arc<TaskResponse>> {
         let ssh_options = self.get_ssh_options_string();
@@ -236,13 +243,6 @@ impl GitAction {
         return Ok(());
     }
 
-    fn get_local_branch(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>) -> Result<String, Arc<TaskResponse>> {
-        let cmd = format!("git -C {} rev-parse --abbrev-ref HEAD", self.path);
-        let result = handle.remote.run_unsafe(request, &cmd, CheckRc::Checked)?;
-        let (_rc, out) = cmd_info(&result);
-        return Ok(out);
-    }
-
     fn clone(&self, handle: &Arc<TaskHandle>, request: &Arc<TaskRequest>) -> Result<(),Arc<TaskResponse>> {
         let ssh_options = self.get_ssh_options_string();
         handle.remote.create_directory(request, &self.path)?;
@@ -14,14 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // long with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::util::io::{path_as_string,directory_as_string};
+use crate::util::io::{path_as_string,directory_as_string,expand_home_path};
 use crate::playbooks::language::{Play,Role,RoleInvocation};
 use std::path::PathBuf;
 use std::collections::HashMap;
 use crate::inventory::hosts::Host;
 use std::sync::{Arc,RwLock};
 use crate::connection::cache::ConnectionCache;
+use crate::connection::ssh_config::SshConfig;
 use crate::registry::list::Task;
+use crate::tasks::logic::{PreLogicInput,PostLogicInput};
 use crate::util::yaml::blend_variables;
 use crate::playbooks::templar::{Templar,TemplateMode};
 use crate::cli::parser::CliParser;
@@ -44,6 +46,12 @@ pub struct PlaybookContext {
     
     pub role: Option<Role>,
     pub role_path: Option<String>,
+    pub role_environment: serde_yaml::Mapping,
+    // settings from the with and and sections of the blocks being run, innermost last, see PreLogicInput::inherit
+    pub block_with: Vec<PreLogicInput>,
+    pub block_and: Vec<PostLogicInput>,
+    pub task_files: Vec<PathBuf>,
+    pub include_items: Vec<HashMap<String, serde_yaml::Value>>,
     pub play_count: usize,
     pub role_count: usize,
 
@@ -64,6 +72,7 @@ pub struct PlaybookContext {
     matched_count_for_host:   HashMap<String, usize>,
     skipped_count_for_host:   HashMap<String, usize>,
     failed_count_for_host:    HashMap<String, usize>,
+    rescued_count_for_host:   HashMap<String, usize>,
     
     // TODO: some of these don't need to be pub.
     pub failed_tasks:           usize,
@@ -72,14 +81,24 @@ pub struct PlaybookContext {
     pub role_defaults_storage:  RwLock<serde_yaml::Mapping>,
     pub role_vars_storage:      RwLock<serde_yaml::Mapping>,
     pub env_storage:            RwLock<serde_yaml::Mapping>,
+    pub include_vars_storage:   RwLock<Vec<serde_yaml::Mapping>>,
     
     pub connection_cache:     RwLock<ConnectionCache>,
     pub templar:              RwLock<Templar>,
 
     pub ssh_user:             String,
+    pub ssh_user_set:         bool,
     pub ssh_port:             i64,
+    pub ssh_connect_timeout:  u64,
+    pub ssh_keepalive:        u32,
+    pub command_timeout:      u64,
+    pub idle_timeout:         u64,
+    pub container_runtime:    String,
     pub sudo:                 Option<String>,
+    pub become_method:        Option<String>,
+    sudo_password:            Option<String>,
     extra_vars:               serde_yaml::Value,
+    ssh_config:               SshConfig,
 
 }
 
@@ -101,6 +120,11 @@ impl PlaybookContext {
             targetted_hosts: HashMap::new(),
             failed_hosts: HashMap::new(),
             role_path: None,
+            role_environment: serde_yaml::Mapping::new(),
+            block_with: Vec::new(),
+            block_and: Vec::new(),
+            task_files: Vec::new(),
+            include_items: Vec::new(),
             adjusted_count_for_host:  HashMap::new(),
             attempted_count_for_host: HashMap::new(),
             created_count_for_host:   HashMap::new(),
@@ -110,6 +134,7 @@ impl PlaybookContext {
             passive_count_for_host:   HashMap::new(),
             matched_count_for_host:   HashMap::new(),
             failed_count_for_host:    HashMap::new(),
+            rescued_count_for_host:   HashMap::new(),
             skipped_count_for_host:   HashMap::new(),
             connection_cache:         RwLock::new(ConnectionCache::new()),
             templar:                  RwLock::new(Templar::new()),
@@ -118,10 +143,20 @@ impl PlaybookContext {
             role_vars_storage:        RwLock::new(serde_yaml::Mapping::new()),
             role_defaults_storage:    RwLock::new(serde_yaml::Mapping::new()),
             env_storage:              RwLock::new(serde_yaml::Mapping::new()),
+            include_vars_storage:     RwLock::new(Vec::new()),
             ssh_user:                 parser.default_user.clone(),
+            ssh_user_set:             parser.default_user_set,
             ssh_port:                 parser.default_port,
+            ssh_connect_timeout:      parser.connect_timeout,
+            ssh_keepalive:            parser.keepalive_interval,
+            command_timeout:          parser.command_timeout,
+            idle_timeout:             parser.idle_timeout,
+            container_runtime:        parser.container_runtime.clone(),
             sudo:                     parser.sudo.clone(),
+            become_method:            parser.become_method.clone(),
+            sudo_password:            parser.sudo_password.clone(),
             extra_vars:               parser.extra_vars.clone(),
+            ssh_config:               SshConfig::load(),
         };
         s.load_environment();
         return s;
@@ -144,6 +179,7 @@ impl PlaybookContext {
 
     pub fn set_ssh_user(&mut self, ssh_user: &String) {
         self.ssh_user = ssh_user.clone();
+        self.ssh_user_set = true;
     }
 
     pub fn set_ssh_port(&mut self, ssh_port: i64) {
@@ -181,6 +217,78 @@ impl PlaybookContext {
         self.failed_hosts.insert(hostname.clone(), Arc::clone(&host));
     }
 
+    pub fn is_host_failed(&self, host: &Arc<RwLock<Host>>) -> bool {
+        return self.failed_hosts.contains_key(&host.read().unwrap().name);
+    }
+
+    // blocks (see traversal::process_block) run their sections on only some of the hosts, so the others are set aside
+    // from the targetted pool for a while, and hosts that failed are brought back for the rescue and always tasks.
+
+    pub fn set_aside_hosts(&mut self, hosts: &Vec<Arc<RwLock<Host>>>) {
+        for host in hosts.iter() {
+            self.targetted_hosts.remove(&host.read().unwrap().name);
+        }
+    }
+
+    pub fn restore_hosts(&mut self, hosts: &Vec<Arc<RwLock<Host>>>) {
+        for host in hosts.iter() {
+            let hostname = host.read().unwrap().name.clone();
+            if ! self.failed_hosts.contains_key(&hostname) {
+                self.targetted_hosts.insert(hostname, Arc::clone(&host));
+            }
+        }
+    }
+
+    pub fn unfail_host(&mut self, host: &Arc<RwLock<Host>>) {
+        let hostname = host.read().unwrap().name.clone();
+        if self.failed_hosts.remove(&hostname).is_some() {
+            self.failed_tasks = self.failed_tasks - 1;
+            self.targetted_hosts.insert(hostname, Arc::clone(&host));
+        }
+    }
+
+    // a host recovered by a block no longer counts the failures it had inside the block, so it does not fail the run
+
+    pub fn get_failed_count_for_host(&self, host: &String) -> usize {
+        return *self.failed_count_for_host.get(host).unwrap_or(&0);
+    }
+
+    pub fn forgive_failures_for_host(&mut self, host: &String, failed_count: usize) {
+        match failed_count {
+            0 => { self.failed_count_for_host.remove(host); },
+            _ => { self.failed_count_for_host.insert(host.clone(), failed_count); }
+        }
+    }
+
+    pub fn push_block(&mut self, with: &Option<PreLogicInput>, and: &Option<PostLogicInput>) {
+        self.block_with.push(with.clone().unwrap_or_default());
+        self.block_and.push(and.clone().unwrap_or_default());
+    }
+
+    pub fn pop_block(&mut self) {
+        self.block_with.pop();
+        self.block_and.pop();
+    }
+
+    // the task files being run (role task files and included files, innermost last) are tracked so that includes can
+    // be found relative to the file that includes them, and so that a file cannot include itself
+
+    pub fn push_task_file(&mut self, path: &PathBuf, vars: &Option<serde_yaml::Mapping>) {
+        self.task_files.push(path.clone());
+        self.include_vars_storage.write().unwrap().push(vars.clone().unwrap_or_default());
+    }
+
+    pub fn pop_task_file(&mut self) {
+        self.task_files.pop();
+        self.include_vars_storage.write().unwrap().pop();
+    }
+
+    // tasks without items of their own see the item of the include they are in, as 'item'
+
+    pub fn get_include_item(&self, host: &String) -> Option<serde_yaml::Value> {
+        return self.include_items.iter().rev().find_map(|x| x.get(host).cloned());
+    }
+
     pub fn set_playbook_path(&mut self, path: &PathBuf) {
         self.playbook_path = Some(path_as_string(&path));
         self.playbook_directory = Some(directory_as_string(&path));
@@ -205,17 +313,33 @@ impl PlaybookContext {
     pub fn set_role(&mut self, role: &Role, invocation: &RoleInvocation, role_path: &String) {
         self.role = Some(role.clone());
         self.role_path = Some(role_path.clone());
-        if role.defaults.is_some() { 
-             *self.role_defaults_storage.write().unwrap() = role.defaults.as_ref().unwrap().clone();
+        // roles run one after another (dependencies first) so nothing may be left over from the previous role
+        let mut role_defaults = serde_yaml::Mapping::new();
+        for param in role.params.as_ref().unwrap_or(&Vec::new()).iter() {
+            if param.default.is_some() {
+                role_defaults.insert(serde_yaml::Value::String(param.name.clone()), param.default.as_ref().unwrap().clone());
+            }
+        }
+        for (k, v) in role.defaults.clone().unwrap_or_default().into_iter() {
+            role_defaults.insert(k, v);
         }
-        if invocation.vars.is_some() { 
-            *self.role_vars_storage.write().unwrap() = invocation.vars.as_ref().unwrap().clone();
+        *self.role_defaults_storage.write().unwrap() = role_defaults;
+        *self.role_vars_storage.write().unwrap() = invocation.vars.clone().unwrap_or_default();
+        // the environment from role.yml can be extended or overridden where the role is used
+        self.role_environment.clear();
+        for environment in [&role.environment, &invocation.environment] {
+            if environment.is_some() {
+                for (k, v) in environment.as_ref().unwrap().iter() {
+                    self.role_environment.insert(k.clone(), v.clone());
+                }
+            }
         }
     }
 
     pub fn unset_role(&mut self) {
         self.role = None;
         self.role_path = None;
+        self.role_environment.clear();
         self.role_defaults_storage.write().unwrap().clear();
         self.role_vars_storage.write().unwrap().clear();
     }
@@ -253,6 +377,10 @@ impl PlaybookContext {
         let src3ar = src3r.deref();
         blend_variables(&mut blended, serde_yaml::Value::Mapping(src3ar.clone()));
 
+        for src3i in self.include_vars_storage.read().unwrap().iter() {
+            blend_variables(&mut blended, serde_yaml::Value::Mapping(src3i.clone()));
+        }
+
         blend_variables(&mut blended, self.extra_vars.clone());
 
         match blend_target {
@@ -295,27 +423,45 @@ impl PlaybookContext {
         };
     }
 
+    // as with ssh -l, a user given with -u, $JET_SSH_USER, or on the play wins over the User in the ssh config, which
+    // in turn wins over $USER
+
+    fn get_default_ssh_user(&self, config_user: Option<String>) -> String {
+        return match (self.ssh_user_set, config_user) {
+            (false, Some(x)) => x,
+            _ => self.ssh_user.clone()
+        };
+    }
+
     // when a host needs to connect over SSH it asks this function - we can use some settings configured
-    // already on the context or check some variables in inventory.
+    // already on the context or check some variables in inventory.  The OpenSSH client config (~/.ssh/config)
+    // is consulted too, using jet_ssh_hostname (or the inventory name) as the Host alias.  Inventory variables
+    // win over the ssh config, which wins over the play and CLI defaults (except for an explicitly given user).
 
     pub fn get_ssh_connection_details(&self, host: &Arc<RwLock<Host>>) -> (String,String,i64) {
 
         let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
         let host2 = host.read().unwrap();
 
-        let remote_hostname = match vars.contains_key(&String::from("jet_ssh_hostname")) {
-            true => match vars.get(&String::from("jet_ssh_hostname")).unwrap().as_str() {
-                Some(x) => String::from(x),
+        let alias = match vars.get(&String::from("jet_ssh_hostname")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
                 None => host2.name.clone()
             },
-            false => host2.name.clone()
+            None => host2.name.clone()
+        };
+        let config = self.ssh_config.lookup(&alias);
+
+        let remote_hostname = match config.hostname {
+            Some(x) => x,
+            None => alias
         };
         let remote_user = match vars.contains_key(&String::from("jet_ssh_user")) {
             true => match vars.get(&String::from("jet_ssh_user")).unwrap().as_str() {
                 Some(x) => String::from(x),
-                None => self.ssh_user.clone()
+                None => self.get_default_ssh_user(config.user)
             },
-            false => self.ssh_user.clone()
+            false => self.get_default_ssh_user(config.user)
         };
         let remote_port = match vars.contains_key(&String::from("jet_ssh_port")) {
             true => match vars.get(&String::from("jet_ssh_port")).unwrap().as_i64() {
@@ -323,17 +469,241 @@ impl PlaybookContext {
                     x
                 },
                 None => {
-                    self.ssh_port
+                    config.port.unwrap_or(self.ssh_port)
                 }
             },
             false => {
-                self.ssh_port
+                config.port.unwrap_or(self.ssh_port)
             }
         };
 
         return (remote_hostname, remote_user, remote_port)
     } 
 
+    // the connect timeout and keepalive interval (both in seconds) come from the CLI but may be overridden
+    // for slow or flaky hosts with jet_ssh_connect_timeout and jet_ssh_keepalive.
+
+    pub fn get_ssh_timeouts(&self, host: &Arc<RwLock<Host>>) -> (u64,u32) {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let connect_timeout = match vars.get(&String::from("jet_ssh_connect_timeout")) {
+            Some(x) => match x.as_u64() {
+                Some(y) => y,
+                None => self.ssh_connect_timeout
+            },
+            None => self.ssh_connect_timeout
+        };
+        let keepalive = match vars.get(&String::from("jet_ssh_keepalive")) {
+            Some(x) => match x.as_u64() {
+                Some(y) => y as u32,
+                None => self.ssh_keepalive
+            },
+            None => self.ssh_keepalive
+        };
+        return (connect_timeout, keepalive);
+    }
+
+    // a private key file for SSH can be set per host or group with jet_ssh_private_key_file, a leading ~ is
+    // expanded to the home directory of the user running jetp.  If not set, IdentityFile from ~/.ssh/config is used.
+
+    pub fn get_ssh_private_key_file(&self, host: &Arc<RwLock<Host>>) -> Option<String> {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let from_vars = match vars.get(&String::from("jet_ssh_private_key_file")) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(expand_home_path(&String::from(y))),
+                None => None
+            },
+            None => None
+        };
+        if from_vars.is_some() {
+            return from_vars;
+        }
+        let alias = match vars.get(&String::from("jet_ssh_hostname")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => host.read().unwrap().name.clone()
+            },
+            None => host.read().unwrap().name.clone()
+        };
+        return self.ssh_config.lookup(&alias).identity_file;
+    }
+
+    // hosts behind a bastion set jet_ssh_jump_host, which may be chained like OpenSSH's ProxyJump, either as a
+    // comma separated string or a list: "[user@]host[:port],...", outermost hop first.  Without the variable
+    // ProxyJump from ~/.ssh/config is used.  Each hop is resolved through the ssh config like any other host, and
+    // returned as (hostname, user, port, private key file).
+
+    pub fn get_ssh_jump_hosts(&self, host: &Arc<RwLock<Host>>) -> Vec<(String,String,i64,Option<String>)> {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let specs : Vec<String> = match vars.get(&String::from("jet_ssh_jump_host")) {
+            Some(serde_yaml::Value::String(x)) => x.split(',').map(|y| String::from(y.trim())).collect(),
+            Some(serde_yaml::Value::Sequence(x)) => x.iter().filter_map(|y| y.as_str()).map(|y| String::from(y.trim())).collect(),
+            _ => {
+                let alias = match vars.get(&String::from("jet_ssh_hostname")) {
+                    Some(x) => match x.as_str() {
+                        Some(y) => String::from(y),
+                        None => host.read().unwrap().name.clone()
+                    },
+                    None => host.read().unwrap().name.clone()
+                };
+                match self.ssh_config.lookup(&alias).proxy_jump {
+                    Some(x) => x.split(',').map(|y| String::from(y.trim())).collect(),
+                    None => Vec::new()
+                }
+            }
+        };
+
+        let mut results : Vec<(String,String,i64,Option<String>)> = Vec::new();
+        for spec in specs.iter().filter(|x| ! x.is_empty()) {
+            let (user, rest) = match spec.split_once('@') {
+                Some((u,r)) => (Some(String::from(u)), String::from(r)),
+                None => (None, spec.clone())
+            };
+            let (alias, port) = match rest.rsplit_once(':') {
+                Some((h,p)) => (String::from(h), p.parse::<i64>().ok()),
+                None => (rest.clone(), None)
+            };
+            let config = self.ssh_config.lookup(&alias);
+            results.push((
+                config.hostname.unwrap_or(alias),
+                user.unwrap_or(self.get_default_ssh_user(config.user)),
+                port.or(config.port).unwrap_or(22),
+                config.identity_file
+            ));
+        }
+        return results;
+    }
+
+    // hosts may choose how they are connected to with jet_connection (ssh, local, container, winrm, or chroot), for
+    // instance a play run in ssh mode can still include a few containers.  None means the default for the CLI mode.
+
+    pub fn get_connection_type(&self, host: &Arc<RwLock<Host>>) -> Option<String> {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        return match vars.get(&String::from("jet_connection")) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(String::from(y)),
+                None => None
+            },
+            None => None
+        };
+    }
+
+    // how to run commands as another user may differ per host (for instance doas on Alpine), jet_become_method overrides
+    // the play and CLI settings.  jet_become_password (or jet_sudo_password) answers the password prompt, if there is one,
+    // and otherwise the password from --ask-sudo-password is used.
+
+    pub fn get_become_details(&self, host: &Arc<RwLock<Host>>) -> (Option<String>,Option<String>) {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let method = match vars.get(&String::from("jet_become_method")) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(String::from(y)),
+                None => None
+            },
+            None => None
+        };
+        let password = match vars.get(&String::from("jet_become_password")).or(vars.get(&String::from("jet_sudo_password"))) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(String::from(y)),
+                None => self.sudo_password.clone()
+            },
+            None => self.sudo_password.clone()
+        };
+        return (method, password);
+    }
+
+    // container connections need to know which command line tool to use (docker or podman), the name of the container,
+    // and optionally the user to run commands as.  The container name defaults to the inventory hostname.
+
+    pub fn get_container_details(&self, host: &Arc<RwLock<Host>>) -> (String,String,Option<String>) {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let runtime = match vars.get(&String::from("jet_container_runtime")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => self.container_runtime.clone()
+            },
+            None => self.container_runtime.clone()
+        };
+        let container = match vars.get(&String::from("jet_container_name")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => host.read().unwrap().name.clone()
+            },
+            None => host.read().unwrap().name.clone()
+        };
+        let user = match vars.get(&String::from("jet_container_user")) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(String::from(y)),
+                None => None
+            },
+            None => None
+        };
+        return (runtime, container, user);
+    }
+
+    // WinRM connections use jet_winrm_hostname (or the inventory name), jet_winrm_port (5985 for plain HTTP), and
+    // jet_winrm_user (or the default user).  There is no key based login, so jet_winrm_password has to be set.
+
+    pub fn get_winrm_details(&self, host: &Arc<RwLock<Host>>) -> (String,i64,String,Option<String>) {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let hostname = match vars.get(&String::from("jet_winrm_hostname")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => host.read().unwrap().name.clone()
+            },
+            None => host.read().unwrap().name.clone()
+        };
+        let port = match vars.get(&String::from("jet_winrm_port")) {
+            Some(x) => match x.as_i64() {
+                Some(y) => y,
+                None => 5985
+            },
+            None => 5985
+        };
+        let user = match vars.get(&String::from("jet_winrm_user")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => self.ssh_user.clone()
+            },
+            None => self.ssh_user.clone()
+        };
+        let password = match vars.get(&String::from("jet_winrm_password")) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(String::from(y)),
+                None => None
+            },
+            None => None
+        };
+        return (hostname, port, user, password);
+    }
+
+    // chroot connections work on the root filesystem in jet_chroot_path (or the inventory hostname), entering it with
+    // jet_chroot_method (chroot or nspawn, chroot by default) and optionally running commands as jet_chroot_user.
+
+    pub fn get_chroot_details(&self, host: &Arc<RwLock<Host>>) -> (String,String,Option<String>) {
+        let vars = self.get_complete_blended_variables(host,BlendTarget::NotTemplateModule);
+        let method = match vars.get(&String::from("jet_chroot_method")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => String::from("chroot")
+            },
+            None => String::from("chroot")
+        };
+        let root = match vars.get(&String::from("jet_chroot_path")) {
+            Some(x) => match x.as_str() {
+                Some(y) => String::from(y),
+                None => host.read().unwrap().name.clone()
+            },
+            None => host.read().unwrap().name.clone()
+        };
+        let user = match vars.get(&String::from("jet_chroot_user")) {
+            Some(x) => match x.as_str() {
+                Some(y) => Some(String::from(y)),
+                None => None
+            },
+            None => None
+        };
+        return (method, root, user);
+    }
+
     // loads environment variables into the context, adding an "ENV_foo" prefix
     // to each environment variable "foo". These variables will only be made available
     // to the template module since we use them for secret management features.
@@ -414,6 +784,10 @@ impl PlaybookContext {
         *self.failed_count_for_host.entry(host.clone()).or_insert(0) += 1;
     }
 
+    pub fn increment_rescued_for_host(&mut self, host: &String) {
+        *self.rescued_count_for_host.entry(host.clone()).or_insert(0) += 1;
+    }
+
     pub fn increment_passive_for_host(&mut self, host: &String) {
         *self.passive_count_for_host.entry(host.clone()).or_insert(0) += 1;
     }
@@ -498,6 +872,14 @@ impl PlaybookContext {
         return self.failed_count_for_host.keys().len();
     }
 
+    pub fn get_total_rescued_count(&self) -> usize {
+        return self.rescued_count_for_host.values().fold(0, |ttl, &x| ttl + x);
+    }
+
+    pub fn get_hosts_rescued_count(&self) -> usize {
+        return self.rescued_count_for_host.keys().len();
+    }
+
     pub fn get_hosts_adjusted_count(&self) -> usize {
         return self.adjusted_count_for_host.keys().len();
     }
@@ -30,6 +30,8 @@ pub struct Play {
     pub vars_files: Option<Vec<String>>,
     pub sudo: Option<String>,
     pub sudo_template: Option<String>,
+    pub become_method: Option<String>,
+    pub environment: Option<serde_yaml::Mapping>,
     pub ssh_user : Option<String>,
     pub ssh_port : Option<i64>,
     pub tasks : Option<Vec<Task>>,
@@ -42,15 +44,43 @@ pub struct Play {
 pub struct Role {
     pub name: String,
     pub defaults: Option<serde_yaml::Mapping>,
+    pub environment: Option<serde_yaml::Mapping>,
     pub tasks: Option<Vec<String>>,
-    pub handlers: Option<Vec<String>>
+    pub handlers: Option<Vec<String>>,
+    pub dependencies: Option<Vec<RoleInvocation>>,
+    pub params: Option<Vec<RoleParam>>
 }
 
-#[derive(Debug,Deserialize)]
+// parameters are the vars a role expects to be given where it is used, those without a default must be given
+
+#[derive(Debug,Deserialize,Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RoleParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub param_type: Option<RoleParamType>,
+    pub default: Option<serde_yaml::Value>,
+    pub description: Option<String>
+}
+
+#[derive(Debug,Deserialize,Clone,Copy,PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoleParamType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    List,
+    Mapping,
+    Any
+}
+
+#[derive(Debug,Deserialize,Clone)]
 #[serde(deny_unknown_fields)]
 pub struct RoleInvocation {
     pub role: String,
     pub vars: Option<serde_yaml::Mapping>,
+    pub environment: Option<serde_yaml::Mapping>,
     pub tags: Option<Vec<String>>
 }
 
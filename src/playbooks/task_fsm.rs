@@ -21,11 +21,11 @@ use crate::playbooks::traversal::RunState;
 use crate::inventory::hosts::Host;
 use crate::playbooks::traversal::HandlerMode;
 use crate::playbooks::language::Play;
-use crate::tasks::request::SudoDetails;
+use crate::tasks::request::{SudoDetails,BecomeMethod};
 use crate::tasks::*;
 use crate::handle::template::BlendTarget;
 use crate::playbooks::templar::TemplateMode;
-use crate::tasks::logic::template_items;
+use crate::tasks::logic::{template_items,template_environment};
 use std::sync::{Arc,RwLock,Mutex};
 use std::collections::HashMap;
 use rayon::prelude::*;
@@ -50,14 +50,16 @@ pub fn fsm_run_task(run_state: &Arc<RunState>, play: &Play, task: &Task, are_han
     // use rayon to process hosts in different threads
     let _total : i64 = host_objects.par_iter().map(|host| {
 
-        // get the connection to each host, which should be left open until the play ends
-        let connection_result = run_state.connection_factory.read().unwrap().get_connection(&run_state.context, &host);
+        // get the connection to each host, which is left open for the rest of the run (see ConnectionCache)
+        let connection_result = get_connection(run_state, &host);
         match connection_result {
             Ok(_)  => {
                 let connection = connection_result.unwrap();
                 run_state.visitor.read().unwrap().on_host_task_start(&run_state.context, &host);
                 // the actual task is invoked here
                 let task_response = run_task_on_host(&run_state,connection,&host,play,task,are_handlers);
+                // a long task counts as using the connection, so it is not closed as idle right afterwards
+                run_state.context.read().unwrap().connection_cache.write().unwrap().touch(&host);
 
                 match task_response {
                     Ok(x) => {
@@ -88,12 +90,47 @@ pub fn fsm_run_task(run_state: &Arc<RunState>, play: &Play, task: &Task, are_han
     return Ok(());
 }
 
+// connections come from the factory, which reuses the ones already made.  Only connections that end up in the cache are
+// new ones worth reporting, the shared connection to localhost is made before the run starts and never closed.  A cached
+// connection made with other settings (such as a user that changed between plays) is replaced by the factory.
+
+fn get_connection(run_state: &Arc<RunState>, host: &Arc<RwLock<Host>>) -> Result<Arc<Mutex<dyn Connection>>, String> {
+    let existing = get_cached_connection(run_state, host);
+    let started = time::Instant::now();
+    let result = run_state.connection_factory.read().unwrap().get_connection(&run_state.context, host);
+    let replaced = run_state.context.read().unwrap().connection_cache.write().unwrap().take_replaced();
+    for (old_host, open_for) in replaced.iter() {
+        run_state.visitor.read().unwrap().on_host_disconnect(&run_state.context, old_host, *open_for, &String::from("connection settings changed"));
+    }
+    let connection = result?;
+    let added = match (existing, get_cached_connection(run_state, host)) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(x), Some(y)) => ! Arc::ptr_eq(&x, &y)
+    };
+    if added {
+        run_state.visitor.read().unwrap().on_host_connect(&run_state.context, host, started.elapsed());
+    }
+    run_state.context.read().unwrap().connection_cache.write().unwrap().touch(host);
+    return Ok(connection);
+}
+
+fn get_cached_connection(run_state: &Arc<RunState>, host: &Arc<RwLock<Host>>) -> Option<Arc<Mutex<dyn Connection>>> {
+    let ctx = run_state.context.read().unwrap();
+    let cache = ctx.connection_cache.read().unwrap();
+    return match cache.has_connection(host) {
+        true => Some(cache.get_connection(host)),
+        false => None
+    };
+}
+
 fn get_actual_connection(run_state: &Arc<RunState>, host: &Arc<RwLock<Host>>, task: &Task, input_connection: Arc<Mutex<dyn Connection>>) -> Result<(Option<String>,Arc<Mutex<dyn Connection>>), String> {
     
     // usually the connection we already have is the one we will use, but this is not the case for using the delegate_to feature
     // this is a bit complex...
 
-    return match task.get_with() {
+    let blocks = run_state.context.read().unwrap().block_with.clone();
+    return match PreLogicInput::inherit(&task.get_with(), &blocks) {
         
         // if the task has a with section then the task might be delegated
         Some(task_with) => match task_with.delegate_to {
@@ -130,7 +167,7 @@ fn get_actual_connection(run_state: &Arc<RunState>, host: &Arc<RwLock<Host>>, ta
                         return Err(format!("cannot delegate to a host not found in inventory: {}", delegate));
                     }
                     let host = run_state.inventory.read().unwrap().get_host(&delegate);
-                    return Ok((Some(delegate.clone()), run_state.connection_factory.read().unwrap().get_connection(&run_state.context, &host)?));
+                    return Ok((Some(delegate.clone()), get_connection(run_state, &host)?));
                 } 
             },
             // there was no delegate keyword, use the original connection
@@ -192,7 +229,13 @@ fn run_task_on_host(
     let mut last : Option<Result<Arc<TaskResponse>,Arc<TaskResponse>>> = None;
 
     // even if we are not iterating over a list of items, make a list of one item to simplify the logic
-    let evaluated_items = template_items(&handle, &validate, TemplateMode::Strict, &items_input)?;
+    let mut evaluated_items = template_items(&handle, &validate, TemplateMode::Strict, &items_input)?;
+    if items_input.is_none() {
+        let include_item = run_state.context.read().unwrap().get_include_item(&host.read().unwrap().name);
+        if include_item.is_some() {
+            evaluated_items = vec![include_item.unwrap()];
+        }
+    }
 
     // walking over each item or just the single task if 'with_items' was not used
     for item in evaluated_items.iter() {
@@ -274,9 +317,18 @@ fn run_task_on_host_inner(
         // minor FIXME: parameters like this are usually set on the run_state
         false => run_state.context.read().unwrap().sudo.clone() 
     };
-    // see if the sudo template is configured, if not use the most basic default
+    // the become method may come from the host, the play, or the CLI, in that order, and defaults to sudo
+    let (host_become_method, become_password) = run_state.context.read().unwrap().get_become_details(host);
+    let become_method = match host_become_method.or(play.become_method.clone()).or(run_state.context.read().unwrap().become_method.clone()) {
+        None => BecomeMethod::Sudo,
+        Some(x) => match BecomeMethod::from_string(&x) {
+            Ok(y) => y,
+            Err(y) => { return Err(handle.response.is_failed(&validate, &y)); }
+        }
+    };
+    // see if the sudo template is configured, if not use the default for the become method
     let sudo_template = match &play.sudo_template {
-        None => String::from("/usr/bin/sudo -u '{{jet_sudo_user}}' {{jet_command}}"),
+        None => become_method.get_default_template(become_password.is_some()),
         Some(x) => x.clone()
     };
     
@@ -284,7 +336,7 @@ fn run_task_on_host_inner(
     if pre_logic.is_some() {
         let logic = pre_logic.as_ref().as_ref().unwrap();
         let my_host = host.read().unwrap();
-        if are_handlers == HandlerMode::Handlers  {
+        if are_handlers == HandlerMode::Handlers && logic.subscribe.is_some() {
             // if we are running handlers at the moment, skip any un-notified handlers
             if ! my_host.is_notified(play_count, &logic.subscribe.as_ref().unwrap().clone()) {
                 return Ok(handle.response.is_skipped(&Arc::clone(&validate))); 
@@ -301,9 +353,39 @@ fn run_task_on_host_inner(
         }
     }
 
+    // the environment for commands comes from the play, then the role, then the task, with later values winning
+    let mut environment_input = play.environment.clone().unwrap_or(serde_yaml::Mapping::new());
+    for (k, v) in run_state.context.read().unwrap().role_environment.iter() {
+        environment_input.insert(k.clone(), v.clone());
+    }
+    let mut environment = template_environment(handle, validate, TemplateMode::Strict, &Some(environment_input))?;
+    if pre_logic.is_some() {
+        for (k, v) in pre_logic.as_ref().as_ref().unwrap().environment.iter() {
+            environment.retain(|(k2, _)| ! k2.eq(k));
+            environment.push((k.clone(), v.clone()));
+        }
+        environment.sort();
+    }
+
+    // a timeout on the task wins over the one from the CLI, so a single slow task can be given more room (or none)
+    let timeout = match post_logic.as_ref() {
+        Some(x) if x.timeout.is_some() => x.timeout.unwrap(),
+        _ => run_state.context.read().unwrap().command_timeout
+    };
+    let (async_limit, async_poll) = match post_logic.as_ref() {
+        Some(x) => (x.async_limit, x.poll),
+        None => (0, 0)
+    };
+
     let sudo_details = SudoDetails {
-        user     : sudo.clone(),
-        template : sudo_template.clone()
+        user        : sudo.clone(),
+        template    : sudo_template.clone(),
+        method      : become_method,
+        password    : become_password,
+        environment : environment,
+        timeout     : timeout,
+        async_limit : async_limit,
+        async_poll  : async_poll
     };
 
     // we're about to get to the task finite state machine guts.
@@ -17,19 +17,26 @@
 use crate::playbooks::language::Play;
 use crate::playbooks::visitor::PlaybookVisitor;
 use crate::playbooks::context::PlaybookContext;
-use crate::playbooks::language::{Role,RoleInvocation};
+use crate::playbooks::language::{Role,RoleInvocation,RoleParamType};
 use crate::connection::factory::ConnectionFactory;
 use crate::registry::list::Task;
+use crate::modules::control::block::BlockTask;
+use crate::modules::control::include::IncludeTask;
+use crate::tasks::logic::ItemsInput;
+use crate::tasks::common::IsTask;
+use crate::handle::template::BlendTarget;
+use crate::playbooks::templar::TemplateMode;
 use crate::playbooks::task_fsm::fsm_run_task;
 use crate::inventory::inventory::Inventory;
 use crate::inventory::hosts::Host;
-use crate::util::io::{jet_file_open,directory_as_string};
+use crate::util::io::{jet_file_open,directory_as_string,path_as_string};
 use crate::util::yaml::{blend_variables,show_yaml_error_in_context};
 use std::path::PathBuf;
-use std::collections::HashMap;
+use std::collections::{HashMap,HashSet};
 use std::sync::{Arc,RwLock};
 use std::path::Path;
 use std::env;
+use std::time::Duration;
 
 // this module contains the start of everything related to playbook evaluation
 
@@ -97,13 +104,10 @@ pub fn playbook_traversal(run_state: &Arc<RunState>) -> Result<(), String> {
         for play in plays.iter() {
             match handle_play(&run_state, play) {
                 Ok(_) => {},
-                Err(s) => { return Err(s); }
+                Err(s) => { close_connections(run_state, None, "end of run"); return Err(s); }
             }
-            // disconnect from all hosts between plays
-            run_state.context.read().unwrap().connection_cache.write().unwrap().clear();
+            // connections stay open between plays and playbooks, as the same hosts are often used again
         }
-        // disconnect from all hosts between playbooks
-        run_state.context.read().unwrap().connection_cache.write().unwrap().clear();
 
         // switch back to the original directory
         env::set_current_dir(&previous).expect("could not restore previous directory");
@@ -111,7 +115,7 @@ pub fn playbook_traversal(run_state: &Arc<RunState>) -> Result<(), String> {
 
     }
     // disconnect from all hosts and exit. 
-    run_state.context.read().unwrap().connection_cache.write().unwrap().clear();
+    close_connections(run_state, None, "end of run");
     run_state.visitor.read().unwrap().on_exit(&run_state.context);
     return Ok(())
 }
@@ -124,6 +128,8 @@ fn handle_play(run_state: &Arc<RunState>, play: &Play) -> Result<(), String> {
         // or play settings for these, feed them into the context so these
         // functions can know what to do when called
 
+        // connections kept from earlier plays are only reused if these (and the host variables) still resolve to
+        // the same connection details, see ConnectionCache
         let mut ctx = run_state.context.write().unwrap();
         ctx.set_play(play);
         if play.ssh_user.is_some() {
@@ -141,6 +147,7 @@ fn handle_play(run_state: &Arc<RunState>, play: &Play) -> Result<(), String> {
     let hosts = get_play_hosts(run_state, play);
     validate_hosts(run_state, play, &hosts)?;
     load_vars_into_context(run_state, play)?;
+    validate_roles(run_state, play, &hosts)?;
 
     // support for serialization if using push configuration
     // means we may not configure hosts all at once but may take
@@ -167,7 +174,9 @@ fn handle_play(run_state: &Arc<RunState>, play: &Play) -> Result<(), String> {
         }
         // disconect from hosts between batches, one of the reasons we may be using
         // this is we have a very large number of machines to manage
-        run_state.context.read().unwrap().connection_cache.write().unwrap().clear();
+        if batch_count > 1 {
+            close_connections(run_state, Some(hosts), "end of batch");
+        }
     }
     
     // we're done, generate our summary/report & output regardless of failures
@@ -188,7 +197,8 @@ fn handle_batch(run_state: &Arc<RunState>, play: &Play, hosts: &Vec<Arc<RwLock<H
     // handle role tasks
     if play.roles.is_some() {
         let roles = play.roles.as_ref().unwrap();
-        for invocation in roles.iter() { process_role(run_state, &play, &invocation, HandlerMode::NormalTasks)?; }
+        let mut seen : HashSet<String> = HashSet::new();
+        for invocation in roles.iter() { process_role(run_state, &play, &invocation, HandlerMode::NormalTasks, &Vec::new(), &mut seen)?; }
     }
     { let mut ctx = run_state.context.write().unwrap(); ctx.unset_role(); }
 
@@ -201,7 +211,8 @@ fn handle_batch(run_state: &Arc<RunState>, play: &Play, hosts: &Vec<Arc<RwLock<H
     // handle role handlers
     if play.roles.is_some() {
         let roles = play.roles.as_ref().unwrap();
-        for invocation in roles.iter() { process_role(run_state, &play, &invocation, HandlerMode::Handlers)?; }
+        let mut seen : HashSet<String> = HashSet::new();
+        for invocation in roles.iter() { process_role(run_state, &play, &invocation, HandlerMode::Handlers, &Vec::new(), &mut seen)?; }
     }   
     { let mut ctx = run_state.context.write().unwrap(); ctx.unset_role(); }  
 
@@ -214,6 +225,42 @@ fn handle_batch(run_state: &Arc<RunState>, play: &Play, hosts: &Vec<Arc<RwLock<H
 
 }
 
+// closes the connections to the given hosts, or all of them, letting the visitor know about each one
+
+fn close_connections(run_state: &Arc<RunState>, hosts: Option<&Vec<Arc<RwLock<Host>>>>, reason: &str) {
+    let closed = {
+        let ctx = run_state.context.read().unwrap();
+        let mut cache = ctx.connection_cache.write().unwrap();
+        match hosts {
+            Some(x) => cache.close_hosts(x),
+            None    => cache.clear()
+        }
+    };
+    let reason = String::from(reason);
+    for (host, open_for) in closed.iter() {
+        run_state.visitor.read().unwrap().on_host_disconnect(&run_state.context, host, *open_for, &reason);
+    }
+}
+
+// with --idle-timeout, connections nobody has used for that long are closed before each task.  A host that is needed
+// again later is simply connected to again.
+
+fn close_idle_connections(run_state: &Arc<RunState>) {
+    let idle_timeout = run_state.context.read().unwrap().idle_timeout;
+    if idle_timeout == 0 {
+        return;
+    }
+    let closed = {
+        let ctx = run_state.context.read().unwrap();
+        let mut cache = ctx.connection_cache.write().unwrap();
+        cache.close_idle(Duration::from_secs(idle_timeout))
+    };
+    let reason = format!("idle for {}s", idle_timeout);
+    for (host, open_for) in closed.iter() {
+        run_state.visitor.read().unwrap().on_host_disconnect(&run_state.context, host, *open_for, &reason);
+    }
+}
+
 fn check_tags(run_state: &Arc<RunState>, task: &Task, role_invocation: Option<&RoleInvocation>) -> bool {
 
     // a given task may have tags associated from either the current role or directly on the task
@@ -245,6 +292,25 @@ fn check_tags(run_state: &Arc<RunState>, task: &Task, role_invocation: Option<&R
                 },
                 None => {}
             };
+            // tags on a block select every task inside it
+            for block_with in run_state.context.read().unwrap().block_with.iter() {
+                if block_with.tags.is_some() {
+                    for x in block_with.tags.as_ref().unwrap().iter() { if cli_tags.contains(&x) { return true; } }
+                }
+            }
+            // includes are always entered, as the included file is only read when the include runs, and tags on the
+            // include are passed down to its tasks like tags on a block
+            if let Task::Include(_) = task {
+                return true;
+            }
+            // a block is entered when any task inside it is selected
+            if let Task::Block(block) = task {
+                for section in [ Some(&block.tasks), block.rescue.as_ref(), block.always.as_ref() ] {
+                    if section.is_some() && section.unwrap().iter().any(|x| check_tags(run_state, x, role_invocation)) {
+                        return true;
+                    }
+                }
+            }
         }
         // no CLI tags so run the task
         None => { return true; }
@@ -260,10 +326,17 @@ fn process_task(run_state: &Arc<RunState>, play: &Play, task: &Task, are_handler
 
     let hosts : HashMap<String, Arc<RwLock<Host>>> = run_state.context.read().unwrap().get_remaining_hosts();
     if hosts.len() == 0 { return Err(String::from("no hosts remaining")) }
+    close_idle_connections(run_state);
 
     // we will run tasks with the FSM only if not skipped by tags
     let should_run = check_tags(run_state, task, role_invocation);
     if should_run {
+        match task {
+            // blocks and includes in a handler list run when notified, their tasks are then run like any other tasks
+            Task::Block(block) => { return process_block(run_state, play, block, "block", are_handlers, role_invocation); },
+            Task::Include(include) => { return process_include(run_state, play, include, are_handlers, role_invocation); },
+            _ => {}
+        }
         run_state.context.write().unwrap().set_task(&task);
         run_state.visitor.read().unwrap().on_task_start(&run_state.context, are_handlers);
         run_state.context.write().unwrap().increment_task_count();
@@ -273,16 +346,360 @@ fn process_task(run_state: &Arc<RunState>, play: &Play, task: &Task, are_handler
     return Ok(());
 }
 
-fn process_role(run_state: &Arc<RunState>, play: &Play, invocation: &RoleInvocation, are_handlers: HandlerMode) -> Result<(), String> {
+// blocks run their tasks on the hosts that enter them (those for which the block condition is true).  Hosts that fail
+// a task stay out of the rest of the block, as usual, but once the block tasks are done the failed hosts are brought
+// back for the rescue tasks, and a host that gets through those is no longer failed.  Finally every host that entered
+// the block runs the always tasks, after which hosts that are still failed (unless the block has ignore_errors) are
+// failed for the rest of the play.
+
+fn process_block(run_state: &Arc<RunState>, play: &Play, block: &BlockTask, kind: &str, are_handlers: HandlerMode, 
+    role_invocation: Option<&RoleInvocation>) -> Result<(), String> {
+
+    let name = block.get_display_name();
+    if block.with.is_some() && block.with.as_ref().unwrap().items.is_some() {
+        return Err(format!("{} {}: with/items cannot be used on a block", kind, name));
+    }
+
+    // work out which hosts enter the block, the others sit it out
+    let mut hosts : Vec<Arc<RwLock<Host>>> = Vec::new();
+    let mut set_aside : Vec<Arc<RwLock<Host>>> = Vec::new();
+    let remaining = run_state.context.read().unwrap().get_remaining_hosts();
+    for (_, host) in remaining {
+        match enters_block(run_state, block, are_handlers, &host) {
+            Ok(true) => { hosts.push(host); },
+            Ok(false) => { set_aside.push(host); },
+            Err(msg) => {
+                run_state.visitor.read().unwrap().debug_host(&host, &msg);
+                let mut ctx = run_state.context.write().unwrap();
+                ctx.fail_host(&host);
+                ctx.increment_failed_for_host(&host.read().unwrap().name);
+            }
+        }
+    }
+    if hosts.is_empty() {
+        return Ok(());
+    }
+    let failed_before : HashMap<String, usize> = {
+        let mut ctx = run_state.context.write().unwrap();
+        ctx.set_aside_hosts(&set_aside);
+        ctx.push_block(&block.with, &block.and);
+        hosts.iter().map(|h| { let n = h.read().unwrap().name.clone(); let c = ctx.get_failed_count_for_host(&n); (n, c) }).collect()
+    };
+
+    let result = process_block_sections(run_state, play, block, &name, kind, &hosts, HandlerMode::NormalTasks, role_invocation);
+
+    {
+        let mut ctx = run_state.context.write().unwrap();
+        ctx.pop_block();
+        ctx.restore_hosts(&set_aside);
+    }
+    let rescued = result?;
+
+    // only now that the block is over can hosts be recovered, a failure in the always tasks undoes the rescue
+    for host in hosts.iter() {
+        let failed = run_state.context.read().unwrap().is_host_failed(host);
+        let recovered = match failed {
+            false => rescued.iter().any(|x| Arc::ptr_eq(x, host)),
+            true  => match ignores_block_errors(run_state, block, host) {
+                Ok(x) => x,
+                Err(msg) => { run_state.visitor.read().unwrap().debug_host(&host, &msg); false }
+            }
+        };
+        if recovered {
+            {
+                let mut ctx = run_state.context.write().unwrap();
+                ctx.unfail_host(host);
+                ctx.forgive_failures_for_host(&host.read().unwrap().name, *failed_before.get(&host.read().unwrap().name).unwrap());
+            }
+            run_state.visitor.read().unwrap().on_host_block_recovered(&run_state.context, host, ! failed);
+        }
+    }
+    return Ok(());
+}
+
+// runs the three sections of a block, returning the hosts that were rescued
+
+fn process_block_sections(run_state: &Arc<RunState>, play: &Play, block: &BlockTask, name: &String, kind: &str, hosts: &Vec<Arc<RwLock<Host>>>, 
+    tasks_mode: HandlerMode, role_invocation: Option<&RoleInvocation>) -> Result<Vec<Arc<RwLock<Host>>>, String> {
+
+    process_block_section(run_state, play, &block.tasks, name, kind, tasks_mode, role_invocation)?;
+
+    let mut rescued : Vec<Arc<RwLock<Host>>> = Vec::new();
+    let failed = get_failed_hosts(run_state, hosts);
+    if ! failed.is_empty() && block.rescue.is_some() {
+        let healthy : Vec<Arc<RwLock<Host>>> = hosts.iter().filter(|h| ! failed.iter().any(|f| Arc::ptr_eq(f, h))).cloned().collect();
+        {
+            let mut ctx = run_state.context.write().unwrap();
+            ctx.set_aside_hosts(&healthy);
+            for host in failed.iter() { ctx.unfail_host(host); }
+        }
+        let result = process_block_section(run_state, play, block.rescue.as_ref().unwrap(), name, "rescue", tasks_mode, role_invocation);
+        run_state.context.write().unwrap().restore_hosts(&healthy);
+        result?;
+        let still_failed = get_failed_hosts(run_state, &failed);
+        rescued = failed.iter().filter(|h| ! still_failed.iter().any(|f| Arc::ptr_eq(f, h))).cloned().collect();
+    }
+
+    if block.always.is_some() {
+        let failed = get_failed_hosts(run_state, hosts);
+        {
+            let mut ctx = run_state.context.write().unwrap();
+            for host in failed.iter() { ctx.unfail_host(host); }
+        }
+        let result = process_block_section(run_state, play, block.always.as_ref().unwrap(), name, "always", tasks_mode, role_invocation);
+        {
+            // running the always tasks does not make up for the failure
+            let mut ctx = run_state.context.write().unwrap();
+            for host in failed.iter() {
+                if ! ctx.is_host_failed(host) { ctx.fail_host(host); }
+            }
+        }
+        result?;
+    }
+    return Ok(rescued);
+}
+
+fn process_block_section(run_state: &Arc<RunState>, play: &Play, tasks: &Vec<Task>, name: &String, section: &str, tasks_mode: HandlerMode, 
+    role_invocation: Option<&RoleInvocation>) -> Result<(), String> {
+    if run_state.context.read().unwrap().get_remaining_hosts().is_empty() {
+        return Ok(());
+    }
+    run_state.visitor.read().unwrap().on_block_start(&run_state.context, name, section);
+    for task in tasks.iter() {
+        // hosts that fail leave the section, when none are left the block moves on to the next section
+        if run_state.context.read().unwrap().get_remaining_hosts().is_empty() {
+            break;
+        }
+        process_task(run_state, play, task, tasks_mode, role_invocation)?;
+    }
+    return Ok(());
+}
+
+fn get_failed_hosts(run_state: &Arc<RunState>, hosts: &Vec<Arc<RwLock<Host>>>) -> Vec<Arc<RwLock<Host>>> {
+    let ctx = run_state.context.read().unwrap();
+    return hosts.iter().filter(|h| ctx.is_host_failed(h)).cloned().collect();
+}
+
+// a block (or include) listed as a handler runs, as a whole, on the hosts that notified it.  The tasks inside it are
+// then run as normal tasks.
+
+fn enters_block(run_state: &Arc<RunState>, block: &BlockTask, are_handlers: HandlerMode, host: &Arc<RwLock<Host>>) -> Result<bool, String> {
+    if block.with.is_none() {
+        return Ok(true);
+    }
+    let logic = block.with.as_ref().unwrap();
+    let ctx = run_state.context.read().unwrap();
+    if are_handlers == HandlerMode::Handlers && logic.subscribe.is_some() {
+        if ! host.read().unwrap().is_notified(ctx.play_count, &logic.subscribe.as_ref().unwrap().trim().to_string()) {
+            return Ok(false);
+        }
+    }
+    return match &logic.condition {
+        Some(cond) => ctx.test_condition(cond, host, TemplateMode::Strict),
+        None => Ok(true)
+    };
+}
+
+fn ignores_block_errors(run_state: &Arc<RunState>, block: &BlockTask, host: &Arc<RwLock<Host>>) -> Result<bool, String> {
+    if block.and.is_none() || block.and.as_ref().unwrap().ignore_errors.is_none() {
+        return Ok(false);
+    }
+    let template = block.and.as_ref().unwrap().ignore_errors.as_ref().unwrap();
+    let value = run_state.context.read().unwrap().render_template(template, host, BlendTarget::NotTemplateModule, TemplateMode::Strict)?;
+    return match value.parse::<bool>() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(format!("field (ignore_errors) value is not a boolean: {}", value))
+    };
+}
+
+// an include runs the tasks from another file as a block.  with/items repeats the whole file, each host going through
+// the file once for each of its items (hosts with fewer items sit out the later passes).  The task files being run are
+// tracked in the context so that nested includes are found relative to the file including them, and to catch cycles.
+// In a handler list an include is a handler like a block is: its own subscribe decides which hosts run the file, and
+// the tasks in the file are not checked for notifications again.
+
+fn process_include(run_state: &Arc<RunState>, play: &Play, include: &IncludeTask, are_handlers: HandlerMode, role_invocation: Option<&RoleInvocation>) -> Result<(), String> {
+
+    let path = find_include(run_state, &include.file)?;
+    {
+        let ctx = run_state.context.read().unwrap();
+        if ctx.task_files.contains(&path) {
+            let mut chain : Vec<String> = ctx.task_files.iter().skip_while(|x| ! x.eq(&&path)).map(|x| path_as_string(x)).collect();
+            chain.push(path_as_string(&path));
+            return Err(format!("include cycle: {}", chain.join(" -> ")));
+        }
+    }
+    let tasks = load_task_file(&path)?;
+
+    // the items are worked out before the include vars apply, as they come from the including file
+    let mut host_items : Vec<(Arc<RwLock<Host>>, Vec<serde_yaml::Value>)> = Vec::new();
+    let items = include.with.as_ref().and_then(|x| x.items.clone());
+    let remaining = run_state.context.read().unwrap().get_remaining_hosts();
+    for (_, host) in remaining {
+        match &items {
+            None => { host_items.push((host, Vec::new())); },
+            Some(items) => {
+                let values = get_include_items(run_state, items, &host).map_err(|x| format!("include {}: {}", include.file, x))?;
+                host_items.push((host, values));
+            }
+        }
+    }
+
+    let mut with = include.with.clone();
+    if with.is_some() {
+        with.as_mut().unwrap().items = None;
+    }
+    let block = BlockTask {
+        name: Some(include.name.clone().unwrap_or(include.file.clone())),
+        tasks: tasks,
+        rescue: None,
+        always: None,
+        with: with,
+        and: include.and.clone()
+    };
+
+    {
+        let mut ctx = run_state.context.write().unwrap();
+        ctx.push_task_file(&path, &include.vars);
+        if items.is_some() {
+            ctx.include_items.push(HashMap::new());
+        }
+    }
+    let passes = match items {
+        None => 1,
+        Some(_) => host_items.iter().map(|(_, values)| values.len()).max().unwrap_or(0)
+    };
+    let mut result = Ok(());
+    for pass in 0..passes {
+        let mut set_aside : Vec<Arc<RwLock<Host>>> = Vec::new();
+        if items.is_some() {
+            let mut ctx = run_state.context.write().unwrap();
+            let mut pass_items : HashMap<String, serde_yaml::Value> = HashMap::new();
+            for (host, values) in host_items.iter() {
+                match values.get(pass) {
+                    Some(value) => { pass_items.insert(host.read().unwrap().name.clone(), value.clone()); },
+                    None => { set_aside.push(Arc::clone(host)); }
+                }
+            }
+            *ctx.include_items.last_mut().unwrap() = pass_items;
+            ctx.set_aside_hosts(&set_aside);
+        }
+        let remaining = run_state.context.read().unwrap().get_remaining_hosts();
+        if ! remaining.is_empty() {
+            result = process_block(run_state, play, &block, "include", are_handlers, role_invocation);
+        }
+        run_state.context.write().unwrap().restore_hosts(&set_aside);
+        if result.is_err() {
+            break;
+        }
+    }
+    {
+        let mut ctx = run_state.context.write().unwrap();
+        ctx.pop_task_file();
+        if items.is_some() {
+            ctx.include_items.pop();
+        }
+    }
+    return result;
+}
+
+// relative includes are looked up next to the file doing the including, which outside of a role task file or another
+// include is the playbook
+
+fn find_include(run_state: &Arc<RunState>, file: &String) -> Result<PathBuf, String> {
+    let mut pb = PathBuf::new();
+    if ! file.starts_with("/") {
+        let ctx = run_state.context.read().unwrap();
+        match ctx.task_files.last() {
+            Some(x) => { pb.push(directory_as_string(x)); },
+            None => { pb.push(ctx.playbook_directory.clone().unwrap_or(String::from("."))); }
+        }
+    }
+    pb.push(file);
+    return match pb.canonicalize() {
+        Ok(x) => Ok(x),
+        Err(y) => Err(format!("include file not found: {}, {}", path_as_string(&pb), y))
+    };
+}
+
+fn load_task_file(path: &PathBuf) -> Result<Vec<Task>, String> {
+    let task_fh = jet_file_open(&path.as_path())?;
+    let parsed: Result<Vec<Task>, serde_yaml::Error> = serde_yaml::from_reader(task_fh);
+    if parsed.is_err() {
+        show_yaml_error_in_context(&parsed.unwrap_err(), &path.as_path());
+        return Err(format!("edit the file and try again?"));
+    }
+    return Ok(parsed.unwrap());
+}
+
+// the include equivalent of template_items in tasks/logic.rs, which needs a task handle
+
+fn get_include_items(run_state: &Arc<RunState>, items: &ItemsInput, host: &Arc<RwLock<Host>>) -> Result<Vec<serde_yaml::Value>, String> {
+    let ctx = run_state.context.read().unwrap();
+    let values = match items {
+        ItemsInput::ItemsString(x) => {
+            let blended = ctx.get_complete_blended_variables(host, BlendTarget::NotTemplateModule);
+            match blended.get(&x) {
+                Some(serde_yaml::Value::Sequence(vs)) => vs.clone(),
+                Some(_) => { return Err(format!("with/items variable did not resolve to a list")); },
+                None => { return Err(format!("variable not found for items: {}", x)); }
+            }
+        },
+        ItemsInput::ItemsList(x) => x.iter().map(|v| serde_yaml::Value::String(v.clone())).collect()
+    };
+    let mut output : Vec<serde_yaml::Value> = Vec::new();
+    for value in values.into_iter() {
+        output.push(match value {
+            serde_yaml::Value::String(x) => serde_yaml::Value::String(ctx.render_template(&x, host, BlendTarget::NotTemplateModule, TemplateMode::Strict)?),
+            x => x
+        });
+    }
+    return Ok(output);
+}
+
+fn process_role(run_state: &Arc<RunState>, play: &Play, invocation: &RoleInvocation, are_handlers: HandlerMode, 
+    dependents: &Vec<String>, seen: &mut HashSet<String>) -> Result<(), String> {
 
     // traversal code for roles.  This is called twice, once for normal tasks and again when processing handler tasks.
 
     // we traverse roles by seeing the 'invocation' in the playbook, which is different from the definition.
     // the definition involves all of the role files in the role directory
     let role_name = invocation.role.clone();
+    if dependents.contains(&role_name) {
+        return Err(format!("role dependency cycle: {} -> {}", dependents.join(" -> "), role_name));
+    }
+
+    // a role runs once per play for each set of vars and environment it is given, whether it is listed in the play or
+    // pulled in as a dependency.  Invoking it again with the same values does nothing, while different values run it
+    // again, as they are most likely meant to configure something else (a second site, another user).
+    let key = match serde_yaml::to_string(&(&invocation.vars, &invocation.environment)) {
+        Ok(x) => format!("{}\n{}", role_name, x),
+        Err(y) => { return Err(format!("role {}: {}", role_name, y)); }
+    };
+    if seen.contains(&key) {
+        return Ok(());
+    }
 
     // can we find a role directory in the configured role paths?
     let (role, role_path) = find_role(run_state, &play, role_name.clone())?;
+    seen.insert(key);
+
+    // the roles this role depends on run first.  Tags on the invocation of this role also select the tasks of its
+    // dependencies.
+    if role.dependencies.is_some() {
+        let mut chain = dependents.clone();
+        chain.push(role_name.clone());
+        for dependency in role.dependencies.as_ref().unwrap().iter() {
+            let mut dependency = dependency.clone();
+            if invocation.tags.is_some() {
+                let mut tags = dependency.tags.unwrap_or_default();
+                tags.extend(invocation.tags.as_ref().unwrap().iter().cloned());
+                dependency.tags = Some(tags);
+            }
+            process_role(run_state, play, &dependency, are_handlers, &chain, seen)?;
+        }
+    }
+
     {
         // we're good.
         let mut ctx = run_state.context.write().unwrap();
@@ -338,20 +755,20 @@ fn process_role(run_state: &Arc<RunState>, play: &Play, invocation: &RoleInvocat
 
             // parse the YAML file
 
-            let task_fh = jet_file_open(&task_buf.as_path())?;
-            let parsed: Result<Vec<Task>, serde_yaml::Error> = serde_yaml::from_reader(task_fh);
-            if parsed.is_err() {
-                show_yaml_error_in_context(&parsed.unwrap_err(), &task_buf.as_path());
-                return Err(format!("edit the file and try again?"));
-            }   
-            let tasks = parsed.unwrap();
+            let tasks = load_task_file(&task_buf)?;
+            run_state.context.write().unwrap().push_task_file(&task_buf.canonicalize().unwrap_or(task_buf.clone()), &None);
             for task in tasks.iter() {
 
                 // process all tasks in the YAML file, this is the same function used
                 // for processing loose tasks outside of roles
 
-                process_task(run_state, &play, &task, are_handlers, Some(invocation))?;
+                let result = process_task(run_state, &play, &task, are_handlers, Some(invocation));
+                if result.is_err() {
+                    run_state.context.write().unwrap().pop_task_file();
+                    return result;
+                }
             }
+            run_state.context.write().unwrap().pop_task_file();
         }
 
         // we're done with the role so flip back to the previous directory
@@ -501,6 +918,124 @@ fn validate_hosts(_run_state: &Arc<RunState>, _play: &Play, hosts: &Vec<Arc<RwLo
     return Ok(());
 }
 
+fn validate_roles(run_state: &Arc<RunState>, play: &Play, hosts: &Vec<Arc<RwLock<Host>>>) -> Result<(), String> {
+
+    // the vars given to each role (and each role it depends on) are checked against the params the role declares
+    // before anything runs, rather than failing part way through the play on a missing or mistyped variable
+
+    if play.roles.is_some() {
+        for invocation in play.roles.as_ref().unwrap().iter() {
+            validate_role_params(run_state, play, invocation, &Vec::new(), hosts)?;
+        }
+    }
+    return Ok(());
+}
+
+fn validate_role_params(run_state: &Arc<RunState>, play: &Play, invocation: &RoleInvocation, dependents: &Vec<String>, 
+    hosts: &Vec<Arc<RwLock<Host>>>) -> Result<(), String> {
+
+    if dependents.contains(&invocation.role) {
+        return Err(format!("role dependency cycle: {} -> {}", dependents.join(" -> "), invocation.role));
+    }
+    let (role, role_path) = find_role(run_state, play, invocation.role.clone())?;
+
+    for param in role.params.as_ref().unwrap_or(&Vec::new()).iter() {
+        let param_type = param.param_type.unwrap_or(RoleParamType::Any);
+        if param.default.is_some() && ! param_type_accepts(param_type, param.default.as_ref().unwrap()) {
+            return Err(format!("role {} has a default for `{}` that is not a {}", role.name, param.name, get_param_type_name(param_type)));
+        }
+    }
+
+    // a param may also be set by host, play or extra vars, and its value may be a template, so each host is checked
+    // with the variables the tasks of the role will see
+    {
+        let mut ctx = run_state.context.write().unwrap();
+        ctx.set_role(&role, invocation, &directory_as_string(&role_path));
+        let result = hosts.iter().try_for_each(|host| check_role_params(&ctx, &role, host));
+        ctx.unset_role();
+        result?;
+    }
+
+    if role.dependencies.is_some() {
+        let mut chain = dependents.clone();
+        chain.push(invocation.role.clone());
+        for dependency in role.dependencies.as_ref().unwrap().iter() {
+            validate_role_params(run_state, play, dependency, &chain, hosts)?;
+        }
+    }
+    return Ok(());
+}
+
+// strings are rendered before their type is checked, so "{{ web_port }}" can be given for an integer.  A template that
+// cannot be rendered yet (it may use facts, or a variable saved by an earlier task) is left to be checked when it is used.
+
+fn check_role_params(ctx: &PlaybookContext, role: &Role, host: &Arc<RwLock<Host>>) -> Result<(), String> {
+    let vars = ctx.get_complete_blended_variables(host, BlendTarget::NotTemplateModule);
+    let host_name = host.read().unwrap().name.clone();
+    for param in role.params.as_ref().unwrap_or(&Vec::new()).iter() {
+        let param_type = param.param_type.unwrap_or(RoleParamType::Any);
+        let type_name = get_param_type_name(param_type);
+        let value = match vars.get(&param.name) {
+            Some(serde_yaml::Value::String(x)) => match ctx.render_template(x, host, BlendTarget::NotTemplateModule, TemplateMode::Strict) {
+                Ok(rendered) => match param_type {
+                    RoleParamType::String | RoleParamType::Any => serde_yaml::Value::String(rendered),
+                    _ => serde_yaml::from_str(&rendered).unwrap_or(serde_yaml::Value::String(rendered))
+                },
+                Err(_) => { continue; }
+            },
+            Some(x) => x.clone(),
+            None => {
+                let about = match &param.description {
+                    Some(x) => format!(": {}", x),
+                    None => String::new()
+                };
+                return Err(format!("role {} requires `{}` ({}){} (host {})", role.name, param.name, type_name, about, host_name));
+            }
+        };
+        if ! param_type_accepts(param_type, &value) {
+            return Err(format!("role {} expects `{}` ({}) but was given a {} (host {})", role.name, param.name, type_name, 
+                get_value_type_name(&value), host_name));
+        }
+    }
+    return Ok(());
+}
+
+fn param_type_accepts(param_type: RoleParamType, value: &serde_yaml::Value) -> bool {
+    return match param_type {
+        RoleParamType::String  => value.is_string(),
+        RoleParamType::Integer => value.is_i64() || value.is_u64(),
+        RoleParamType::Float   => value.is_number(),
+        RoleParamType::Boolean => value.is_bool(),
+        RoleParamType::List    => value.is_sequence(),
+        RoleParamType::Mapping => value.is_mapping(),
+        RoleParamType::Any     => true
+    };
+}
+
+fn get_param_type_name(param_type: RoleParamType) -> &'static str {
+    return match param_type {
+        RoleParamType::String  => "string",
+        RoleParamType::Integer => "integer",
+        RoleParamType::Float   => "float",
+        RoleParamType::Boolean => "boolean",
+        RoleParamType::List    => "list",
+        RoleParamType::Mapping => "mapping",
+        RoleParamType::Any     => "any"
+    };
+}
+
+fn get_value_type_name(value: &serde_yaml::Value) -> &'static str {
+    return match value {
+        serde_yaml::Value::Null        => "null",
+        serde_yaml::Value::Bool(_)     => "boolean",
+        serde_yaml::Value::Number(_)   => "number",
+        serde_yaml::Value::String(_)   => "string",
+        serde_yaml::Value::Sequence(_) => "list",
+        serde_yaml::Value::Mapping(_)  => "mapping",
+        serde_yaml::Value::Tagged(_)   => "tagged value"
+    };
+}
+
 fn load_vars_into_context(run_state: &Arc<RunState>, play: &Play) -> Result<(), String> {
 
     // the context object is fairly pervasive throughout the running of the program
@@ -19,10 +19,11 @@ use crate::playbooks::context::PlaybookContext;
 use std::sync::Arc;
 use crate::tasks::*;
 use std::sync::RwLock;
+use std::time::Duration;
 use crate::inventory::hosts::Host;
 use inline_colorization::{color_red,color_blue,color_green,color_cyan,color_reset,color_yellow};
 use std::marker::{Send,Sync};
-use crate::connection::command::CommandResult;
+use crate::connection::command::{CommandResult,OutputKind};
 use crate::playbooks::traversal::HandlerMode;
 
 // visitor contains various functions that are called from all over the program
@@ -110,6 +111,17 @@ pub trait PlaybookVisitor : Send + Sync {
         }
     }
 
+    // blocks show a banner for each of their sections (block, rescue, and always) before the tasks in it
+
+    fn on_block_start(&self, context: &Arc<RwLock<PlaybookContext>>, name: &String, section: &str) {
+        let context = context.read().unwrap();
+        self.banner();
+        match &context.role {
+            None => println!("> begin {}: {}", section, name),
+            Some(role) => println!("> ({}) begin {}: {}", role.name, section, name)
+        }
+    }
+
     fn on_batch(&self, batch_num: usize, batch_count: usize, batch_size: usize) {
         self.banner();
         println!("> batch {}/{}, {} hosts", batch_num+1, batch_count, batch_size);
@@ -224,6 +236,18 @@ pub trait PlaybookVisitor : Send + Sync {
         }
     }
 
+    // a host that failed inside a block was recovered, either by the rescue tasks or by ignore_errors on the block
+
+    fn on_host_block_recovered(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, rescued: bool) {
+        let host2 = host.read().unwrap();
+        if rescued {
+            context.write().unwrap().increment_rescued_for_host(&host2.name);
+            println!("{color_green}✓ {} => rescued{color_reset}", &host2.name);
+        } else {
+            println!("{color_yellow}✓ {} => block failed (ignored){color_reset}", &host2.name);
+        }
+    }
+
     fn on_host_task_retry(&self, _context: &Arc<RwLock<PlaybookContext>>,host: &Arc<RwLock<Host>>, retries: u64, delay: u64) {
         let host2 = host.read().unwrap();
         println!("{color_blue}! {} => retrying ({} retries left) in {} seconds{color_reset}",host2.name,retries,delay);
@@ -239,7 +263,12 @@ pub trait PlaybookVisitor : Send + Sync {
                     let _lock = context.write().unwrap();
                     println!("{color_red}! {} => failed", host2.name);
                     println!("    cmd: {}", cmd_result.cmd);
-                    println!("    out: {}", cmd_result.out);
+                    if cmd_result.stderr.is_empty() {
+                        println!("    out: {}", cmd_result.out);
+                    } else {
+                        println!("    out: {}", cmd_result.stdout);
+                        println!("    err: {color_yellow}{}{color_red}", cmd_result.stderr);
+                    }
                     println!("    rc: {}{color_reset}", cmd_result.rc);
                 }
             } else {
@@ -252,6 +281,28 @@ pub trait PlaybookVisitor : Send + Sync {
         context.write().unwrap().increment_failed_for_host(&host2.name);
     }
 
+    fn on_host_reconnect(&self, _context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, attempt: u64, reason: &String) {
+        let host2 = host.read().unwrap();
+        println!("{color_blue}! {} => connection lost ({}), reconnecting (attempt {}){color_reset}", host2.name, reason, attempt);
+    }
+
+    // connections are made the first time a host is needed and kept until the run ends (or they sit idle for too long),
+    // these report each one along with how long connecting took and how long the connection was open
+
+    fn on_host_connect(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, elapsed: Duration) {
+        let host2 = host.read().unwrap();
+        if context.read().unwrap().verbosity > 0 {
+            println!("{color_blue}! {} => connected in {}ms{color_reset}", host2.name, elapsed.as_millis());
+        }
+    }
+
+    fn on_host_disconnect(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, open_for: Duration, reason: &String) {
+        let host2 = host.read().unwrap();
+        if context.read().unwrap().verbosity > 0 {
+            println!("{color_blue}! {} => disconnected ({}) after {}s{color_reset}", host2.name, reason, open_for.as_secs());
+        }
+    }
+
     fn on_host_connect_failed(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>) {
         let host2 = host.read().unwrap();
         context.write().unwrap().increment_failed_for_host(&host2.name);
@@ -266,10 +317,17 @@ pub trait PlaybookVisitor : Send + Sync {
         };
     }
     
-    fn on_before_transfer(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, path: &String) {
+    fn on_before_transfer(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, path: &String, size: u64) {
         let host2 = host.read().unwrap();
         if context.read().unwrap().verbosity > 0 {
-            println!("{color_blue}! {} => transferring to: {}", host2.name, &path.clone());
+            println!("{color_blue}! {} => transferring {} bytes to: {}", host2.name, size, &path.clone());
+        }
+    }
+
+    fn on_transfer_progress(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, path: &String, transferred: u64, total: u64) {
+        let host2 = host.read().unwrap();
+        if context.read().unwrap().verbosity > 1 {
+            println!("{color_blue}! {} => {}: {}/{} bytes ({}%)", host2.name, &path.clone(), transferred, total, transferred * 100 / total);
         }
     }
 
@@ -280,6 +338,16 @@ pub trait PlaybookVisitor : Send + Sync {
         }
     }
 
+    fn on_command_output(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, kind: OutputKind, line: &String) {
+        if context.read().unwrap().verbosity > 1 {
+            let host2 = host.read().unwrap();
+            match kind {
+                OutputKind::Stdout => println!("{color_blue}  {} | {}{color_reset}", host2.name, line),
+                OutputKind::Stderr => println!("{color_yellow}  {} ! {}{color_reset}", host2.name, line)
+            }
+        }
+    }
+
     fn on_command_ok(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, result: &Arc<Option<CommandResult>>,) {
         let host2 = host.read().unwrap();
         let cmd_result = result.as_ref().as_ref().expect("missing command result");
@@ -287,7 +355,12 @@ pub trait PlaybookVisitor : Send + Sync {
             let _ctx2 = context.write().unwrap(); // lock for multi-line output
             println!("{color_blue}! {} ... command ok", host2.name);
             println!("    cmd: {}", cmd_result.cmd);           
-            println!("    out: {}", cmd_result.out.clone());
+            if cmd_result.stderr.is_empty() {
+                println!("    out: {}", cmd_result.out);
+            } else {
+                println!("    out: {}", cmd_result.stdout);
+                println!("    err: {}", cmd_result.stderr);
+            }
             println!("    rc: {}{color_reset}", cmd_result.rc);
         }
     }
@@ -299,11 +372,31 @@ pub trait PlaybookVisitor : Send + Sync {
             let _ctx2 = context.write().unwrap(); // lock for multi-line output
             println!("{color_red}! {} ... command failed", host2.name);
             println!("    cmd: {}", cmd_result.cmd);
-            println!("    out: {}", cmd_result.out.clone());
+            if cmd_result.stderr.is_empty() {
+                println!("    out: {}", cmd_result.out);
+            } else {
+                println!("    out: {}", cmd_result.stdout);
+                println!("    err: {color_yellow}{}{color_red}", cmd_result.stderr);
+            }
             println!("    rc: {}{color_reset}", cmd_result.rc);
         }
     }
 
+    fn on_command_timed_out(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, result: &Arc<Option<CommandResult>>, seconds: u64) {
+        let host2 = host.read().expect("context read");
+        let cmd_result = result.as_ref().as_ref().expect("missing command result");
+        let _ctx2 = context.write().unwrap(); // lock for multi-line output
+        println!("{color_red}! {} => command timed out after {} seconds and was killed", host2.name, seconds);
+        println!("    cmd: {}{color_reset}", cmd_result.cmd);
+    }
+
+    fn on_async_poll(&self, context: &Arc<RwLock<PlaybookContext>>, host: &Arc<RwLock<Host>>, job_id: &String, elapsed: u64, limit: u64) {
+        if context.read().unwrap().verbosity > 0 {
+            let host2 = host.read().unwrap();
+            println!("… {} => async job {} still running ({}/{} seconds)", host2.name, job_id, elapsed, limit);
+        }
+    }
+
     fn is_check_mode(&self) -> bool;
 
 }
@@ -336,6 +429,8 @@ pub fn show_playbook_summary(context: &Arc<RwLock<PlaybookContext>>) {
     let unchanged_ct = action_ct - adjusted_ct;
     let failed_ct    = ctx.get_total_failed_count();
     let failed_hosts = ctx.get_hosts_failed_count();
+    let rescued_ct = ctx.get_total_rescued_count();
+    let rescued_hosts = ctx.get_hosts_rescued_count();
 
     let summary = match failed_hosts {
         0 => match adjusted_hosts {
@@ -362,6 +457,7 @@ pub fn show_playbook_summary(context: &Arc<RwLock<PlaybookContext>>) {
                       | Unchanged | {unchanged_ct} | {unchanged_hosts}\n\
                       | Changed | {adjusted_ct} | {adjusted_hosts}\n\
                       | Failed | {failed_ct} | {failed_hosts}\n\
+                      | Rescued | {rescued_ct} | {rescued_hosts}\n\
                       |-|-|-");
 
     crate::util::terminal::markdown_print(&mode_table);
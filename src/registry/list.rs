@@ -31,9 +31,11 @@ use crate::modules::commands::shell::ShellTask;
 
 // control
 use crate::modules::control::assert::AssertTask;
+use crate::modules::control::block::BlockTask;
 use crate::modules::control::debug::DebugTask;
 use crate::modules::control::echo::EchoTask;
 use crate::modules::control::fail::FailTask;
+use crate::modules::control::include::IncludeTask;
 use crate::modules::control::facts::FactsTask;
 use crate::modules::control::set::SetTask;
 
@@ -58,6 +60,7 @@ pub enum Task {
     // ADD NEW MODULES HERE, KEEP ALPHABETIZED BY NAME
     Apt(AptTask),
     Assert(AssertTask),
+    Block(BlockTask),
     Copy(CopyTask),
     Debug(DebugTask),
     Dnf(YumDnfTask),
@@ -67,6 +70,7 @@ pub enum Task {
     Facts(FactsTask),
     File(FileTask),
     Git(GitTask),
+    Include(IncludeTask),
     Sd_Service(SystemdServiceTask),
     Set(SetTask),
     Shell(ShellTask),
@@ -80,6 +84,7 @@ impl Task {
         return match self {
             Task::Apt(x)        => x.get_module(),
             Task::Assert(x)     => x.get_module(),
+            Task::Block(x)      => x.get_module(),
             Task::Copy(x)       => x.get_module(),
             Task::Debug(x)      => x.get_module(),
             Task::Dnf(x)        => x.get_module(),
@@ -89,6 +94,7 @@ impl Task {
             Task::Fail(x)       => x.get_module(), 
             Task::File(x)       => x.get_module(),
             Task::Git(x)        => x.get_module(), 
+            Task::Include(x)    => x.get_module(),
             Task::Sd_Service(x) => x.get_module(),
             Task::Set(x)        => x.get_module(), 
             Task::Shell(x)      => x.get_module(), 
@@ -101,6 +107,7 @@ impl Task {
         return match self {
             Task::Apt(x)        => x.get_name(),
             Task::Assert(x)     => x.get_name(),
+            Task::Block(x)      => x.get_name(),
             Task::Copy(x)       => x.get_name(),
             Task::Debug(x)      => x.get_name(), 
             Task::Dnf(x)        => x.get_name(),
@@ -110,6 +117,7 @@ impl Task {
             Task::Fail(x)       => x.get_name(), 
             Task::File(x)       => x.get_name(), 
             Task::Git(x)        => x.get_name(),
+            Task::Include(x)    => x.get_name(),
             Task::Sd_Service(x) => x.get_name(),
             Task::Set(x)        => x.get_name(),
             Task::Shell(x)      => x.get_name(), 
@@ -122,6 +130,7 @@ impl Task {
         return match self {
             Task::Apt(x)        => x.get_with(),
             Task::Assert(x)     => x.get_with(),
+            Task::Block(x)      => x.get_with(),
             Task::Copy(x)       => x.get_with(),
             Task::Debug(x)      => x.get_with(), 
             Task::Dnf(x)        => x.get_with(),
@@ -131,6 +140,7 @@ impl Task {
             Task::Fail(x)       => x.get_with(), 
             Task::File(x)       => x.get_with(),
             Task::Git(x)        => x.get_with(), 
+            Task::Include(x)    => x.get_with(),
             Task::Sd_Service(x) => x.get_with(),
             Task::Set(x)        => x.get_with(),
             Task::Shell(x)      => x.get_with(), 
@@ -144,6 +154,7 @@ impl Task {
         return match self {
             Task::Apt(x)        => x.evaluate(handle, request, tm),
             Task::Assert(x)     => x.evaluate(handle, request, tm),
+            Task::Block(x)      => x.evaluate(handle, request, tm),
             Task::Copy(x)       => x.evaluate(handle, request, tm),
             Task::Debug(x)      => x.evaluate(handle, request, tm), 
             Task::Dnf(x)        => x.evaluate(handle, request, tm),
@@ -153,6 +164,7 @@ impl Task {
             Task::Facts(x)      => x.evaluate(handle, request, tm),
             Task::File(x)       => x.evaluate(handle, request, tm), 
             Task::Git(x)        => x.evaluate(handle, request, tm),
+            Task::Include(x)    => x.evaluate(handle, request, tm),
             Task::Sd_Service(x) => x.evaluate(handle, request, tm),
             Task::Set(x)        => x.evaluate(handle, request, tm),
             Task::Shell(x)      => x.evaluate(handle, request, tm), 
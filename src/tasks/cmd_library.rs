@@ -32,6 +32,12 @@ use crate::tasks::files::Recurse;
 //
 // any argument that allows spaces (such as paths) should be the *last*
 // command in any command sequence.
+//
+// commands for Windows hosts are PowerShell (see connection/winrm.rs), and produce
+// output in the same shape as the POSIX commands so remote.rs can parse either.
+// They go through the same screening as everything else, so they get by without
+// braces, semicolons, or variables.  Windows has no file modes, the attribute
+// string (such as -a----) stands in for one.
 
 pub fn screen_path(path: &String) -> Result<String,String> {
     // NOTE: this only checks paths used in commands
@@ -88,6 +94,7 @@ pub fn get_mode_command(os_type: HostOSType, untrusted_path: &String) -> Result<
     return match os_type {
         HostOSType::Linux => Ok(format!("stat --format '%a' '{}'", path)),
         HostOSType::MacOS => Ok(format!("stat -f '%A' '{}'", path)),
+        HostOSType::Windows => Ok(format!("(Get-Item -Force -LiteralPath '{}').Mode", path)),
     }
 }
         
@@ -96,65 +103,95 @@ pub fn get_sha512_command(os_type: HostOSType, untrusted_path: &String) -> Resul
     return match os_type {
         HostOSType::Linux => Ok(format!("sha512sum '{}'", path)),
         HostOSType::MacOS => Ok(format!("shasum -b -a 512 '{}'", path)),
+        HostOSType::Windows => Ok(format!("(Get-FileHash -Algorithm SHA512 -LiteralPath '{}').Hash.ToLower()", path)),
     }
 }
 
-pub fn get_ownership_command(_os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
+// on Windows, owner and group names often contain spaces (NT AUTHORITY\SYSTEM), so they are printed one per line
+
+pub fn get_ownership_command(os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
     let path = screen_path(untrusted_path)?;
-    return Ok(format!("ls -ld '{}'", path));
+    return match os_type {
+        HostOSType::Windows => Ok(format!("(Get-Acl -LiteralPath '{p}').Owner, (Get-Acl -LiteralPath '{p}').Group", p=path)),
+        _ => Ok(format!("ls -ld '{}'", path))
+    }
 }
 
-pub fn get_is_directory_command(_os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
+pub fn get_is_directory_command(os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
     let path = screen_path(untrusted_path)?;
-    return Ok(format!("ls -ld '{}'", path));
+    return match os_type {
+        // the mode string starts with 'd' for directories, as with ls -ld
+        HostOSType::Windows => Ok(format!("(Get-Item -Force -LiteralPath '{}').Mode", path)),
+        _ => Ok(format!("ls -ld '{}'", path))
+    }
 }
 
-pub fn get_touch_command(_os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
+pub fn get_touch_command(os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
     let path = screen_path(untrusted_path)?;
-    return Ok(format!("touch '{}'", path));
+    return match os_type {
+        // only creates missing files, existing ones are left alone
+        HostOSType::Windows => Ok(format!("New-Item -ItemType File -ErrorAction SilentlyContinue -Path '{}' | Out-Null", path)),
+        _ => Ok(format!("touch '{}'", path))
+    }
 }
 
-pub fn get_create_directory_command(_os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
+pub fn get_create_directory_command(os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
     let path = screen_path(untrusted_path)?;
-    return Ok(format!("mkdir -p '{}'", path));
+    return match os_type {
+        HostOSType::Windows => Ok(format!("New-Item -ItemType Directory -Force -Path '{}' | Out-Null", path)),
+        _ => Ok(format!("mkdir -p '{}'", path))
+    }
 }
 
-pub fn get_delete_file_command(_os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
+pub fn get_delete_file_command(os_type: HostOSType, untrusted_path: &String) -> Result<String,String>  {
     let path = screen_path(untrusted_path)?;
-    return Ok(format!("rm -f '{}'", path));
+    return match os_type {
+        HostOSType::Windows => Ok(format!("Get-Item -Force -ErrorAction SilentlyContinue -LiteralPath '{}' | Remove-Item -Force", path)),
+        _ => Ok(format!("rm -f '{}'", path))
+    }
 }
 
-pub fn get_delete_directory_command(_os_type: HostOSType, untrusted_path: &String, recurse: Recurse) -> Result<String,String>  {
+pub fn get_delete_directory_command(os_type: HostOSType, untrusted_path: &String, recurse: Recurse) -> Result<String,String>  {
     let path = screen_path(untrusted_path)?;
-    match recurse {
-        Recurse::No  => { return Ok(format!("rm -d '{}'", path));    },
-        Recurse::Yes => { return Ok(format!("rm -rf '{}'", path)); }
+    match (os_type, recurse) {
+        (HostOSType::Windows, Recurse::No)  => { return Ok(format!("Remove-Item -LiteralPath '{}'", path)); },
+        (HostOSType::Windows, Recurse::Yes) => { return Ok(format!("Get-Item -Force -ErrorAction SilentlyContinue -LiteralPath '{}' | Remove-Item -Recurse -Force", path)); },
+        (_, Recurse::No)  => { return Ok(format!("rm -d '{}'", path));    },
+        (_, Recurse::Yes) => { return Ok(format!("rm -rf '{}'", path)); }
     }
 }
 
-pub fn set_owner_command(_os_type: HostOSType, untrusted_path: &String, untrusted_owner: &String, recurse: Recurse) -> Result<String,String> {
+pub fn set_owner_command(os_type: HostOSType, untrusted_path: &String, untrusted_owner: &String, recurse: Recurse) -> Result<String,String> {
     let path = screen_path(untrusted_path)?;
     let owner = screen_general_input_strict(untrusted_owner)?;
-    match recurse {
-        Recurse::No   => { return Ok(format!("chown '{}' '{}'", owner, path));    },
-        Recurse::Yes  => { return Ok(format!("chown -R '{}' '{}'", owner, path)); }
+    match (os_type, recurse) {
+        (HostOSType::Windows, Recurse::No)  => { return Ok(format!("icacls '{}' /setowner '{}' /Q", path, owner)); },
+        (HostOSType::Windows, Recurse::Yes) => { return Ok(format!("icacls '{}' /setowner '{}' /T /Q", path, owner)); },
+        (_, Recurse::No)   => { return Ok(format!("chown '{}' '{}'", owner, path));    },
+        (_, Recurse::Yes)  => { return Ok(format!("chown -R '{}' '{}'", owner, path)); }
     }
 }
 
-pub fn set_group_command(_os_type: HostOSType, untrusted_path: &String, untrusted_group: &String, recurse: Recurse) -> Result<String,String> {
+pub fn set_group_command(os_type: HostOSType, untrusted_path: &String, untrusted_group: &String, recurse: Recurse) -> Result<String,String> {
     let path = screen_path(untrusted_path)?;
     let group = screen_general_input_strict(untrusted_group)?;
+    if matches!(os_type, HostOSType::Windows) {
+        return Err(String::from("setting the group of a file is not supported on Windows"));
+    }
     match recurse {
         Recurse::No   => { return Ok(format!("chgrp '{}' '{}'", group, path));    },
         Recurse::Yes  => { return Ok(format!("chgrp -R '{}' '{}'", group, path)); }
     }
 }
 
-pub fn set_mode_command(_os_type: HostOSType, untrusted_path: &String, untrusted_mode: &String, recurse: Recurse) -> Result<String,String> {
+pub fn set_mode_command(os_type: HostOSType, untrusted_path: &String, untrusted_mode: &String, recurse: Recurse) -> Result<String,String> {
     // mode generally does not have to be screened but someone could call a command directly without going through FileAttributes
     // so let's be thorough.
     let path = screen_path(untrusted_path)?;
     let mode = screen_mode(untrusted_mode)?;
+    if matches!(os_type, HostOSType::Windows) {
+        return Err(String::from("file modes are not supported on Windows"));
+    }
     match recurse {
         Recurse::No  => { return Ok(format!("chmod '{}' '{}'", mode, path));    },
         Recurse::Yes => { return Ok(format!("chmod -R '{}' '{}'", mode, path)); }
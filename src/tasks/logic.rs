@@ -25,7 +25,7 @@ use crate::playbooks::templar::TemplateMode;
 // this is storage behind all 'and' and 'with' statements in the program, which
 // are mostly implemented in task_fsm
 
-#[derive(Deserialize,Debug,Clone)]
+#[derive(Deserialize,Debug,Clone,Default)]
 #[serde(deny_unknown_fields)]
 pub struct PreLogicInput {
     pub condition: Option<String>,
@@ -33,7 +33,8 @@ pub struct PreLogicInput {
     pub sudo: Option<String>,
     pub items: Option<ItemsInput>,
     pub tags: Option<Vec<String>>,
-    pub delegate_to: Option<String>
+    pub delegate_to: Option<String>,
+    pub environment: Option<serde_yaml::Mapping>
 }
 
 #[derive(Deserialize,Debug,Clone)]
@@ -49,16 +50,21 @@ pub struct PreLogicEvaluated {
     pub subscribe: Option<String>,
     pub sudo: Option<String>,
     pub items: Option<ItemsInput>,
-    pub tags: Option<Vec<String>>
+    pub tags: Option<Vec<String>>,
+    pub environment: Vec<(String,String)>
 }
 
-#[derive(Deserialize,Debug)]
+#[derive(Deserialize,Debug,Clone,Default)]
 #[serde(deny_unknown_fields)]
 pub struct PostLogicInput {
     pub notify: Option<String>,
     pub ignore_errors: Option<String>,
     pub retry: Option<String>,
-    pub delay: Option<String>
+    pub delay: Option<String>,
+    pub timeout: Option<String>,
+    #[serde(rename = "async")]
+    pub async_limit: Option<String>,
+    pub poll: Option<String>
 }
 
 #[derive(Debug)]
@@ -67,12 +73,19 @@ pub struct PostLogicEvaluated {
     pub ignore_errors: bool,
     pub retry: u64,
     pub delay: u64,
+    // None means the global default (--timeout) applies
+    pub timeout: Option<u64>,
+    // seconds an async job may run, 0 when the task is not async
+    pub async_limit: u64,
+    pub poll: u64,
 }
 
 
 impl PreLogicInput {
 
     pub fn template(handle: &TaskHandle, request: &Arc<TaskRequest>, tm: TemplateMode, input: &Option<Self>) -> Result<Option<PreLogicEvaluated>,Arc<TaskResponse>> {
+        let blocks = handle.run_state.context.read().unwrap().block_with.clone();
+        let input = &Self::inherit(input, &blocks);
         if input.is_none() {
             return Ok(None);
         }
@@ -85,15 +98,45 @@ impl PreLogicInput {
             sudo: handle.template.string_option_no_spaces(request, tm, &String::from("sudo"), &input2.sudo)?,
             subscribe: handle.template.no_template_string_option_trim(&input2.subscribe),
             items: input2.items.clone(),
-            tags: input2.tags.clone()
+            tags: input2.tags.clone(),
+            environment: template_environment(handle, request, tm, &input2.environment)?
         }));
     }
 
+    // tasks inside blocks (innermost last) pick up sudo, delegate_to, and environment from them unless they set their own.
+    // The condition, subscribe, and tags of a block apply to the block as a whole (see traversal.rs) and items cannot be
+    // used on blocks, so those are not passed down.
+
+    pub fn inherit(input: &Option<Self>, blocks: &Vec<Self>) -> Option<Self> {
+        let mut result = input.clone().unwrap_or_default();
+        let mut environment = serde_yaml::Mapping::new();
+        for block in blocks.iter().rev() {
+            result.sudo = result.sudo.or(block.sudo.clone());
+            result.delegate_to = result.delegate_to.or(block.delegate_to.clone());
+        }
+        for source in blocks.iter().map(|x| &x.environment).chain([&result.environment]) {
+            if source.is_some() {
+                for (k, v) in source.as_ref().unwrap().iter() {
+                    environment.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        if ! environment.is_empty() {
+            result.environment = Some(environment);
+        }
+        if input.is_none() && result.sudo.is_none() && result.delegate_to.is_none() && result.environment.is_none() {
+            return None;
+        }
+        return Some(result);
+    }
+
 }
 
 impl PostLogicInput {
 
     pub fn template(handle: &TaskHandle, request: &Arc<TaskRequest>, tm: TemplateMode, input: &Option<Self>) -> Result<Option<PostLogicEvaluated>,Arc<TaskResponse>> {
+        let blocks = handle.run_state.context.read().unwrap().block_and.clone();
+        let input = &Self::inherit(input, &blocks);
         if input.is_none() {
             return Ok(None);
         }
@@ -104,8 +147,66 @@ impl PostLogicInput {
             delay:         handle.template.integer_option(request, tm, &String::from("delay"), &input2.delay, 1)?,
             ignore_errors: handle.template.boolean_option_default_false(request, tm, &String::from("ignore_errors"), &input2.ignore_errors)?,
             retry:         handle.template.integer_option(request, tm, &String::from("retry"), &input2.retry, 0)?,
+            timeout:       match input2.timeout.is_some() {
+                true  => Some(handle.template.integer_option(request, tm, &String::from("timeout"), &input2.timeout, 0)?),
+                false => None
+            },
+            async_limit:   handle.template.integer_option(request, tm, &String::from("async"), &input2.async_limit, 0)?,
+            poll:          handle.template.integer_option(request, tm, &String::from("poll"), &input2.poll, 10)?,
         }));
     }
+
+    // as with PreLogicInput::inherit, except ignore_errors, which decides whether a failed block counts as a failure
+    // after rescue and always have run, rather than letting each task fail quietly
+
+    pub fn inherit(input: &Option<Self>, blocks: &Vec<Self>) -> Option<Self> {
+        let mut result = input.clone().unwrap_or_default();
+        for block in blocks.iter().rev() {
+            result.notify = result.notify.or(block.notify.clone());
+            result.retry = result.retry.or(block.retry.clone());
+            result.delay = result.delay.or(block.delay.clone());
+            result.timeout = result.timeout.or(block.timeout.clone());
+            result.async_limit = result.async_limit.or(block.async_limit.clone());
+            result.poll = result.poll.or(block.poll.clone());
+        }
+        if input.is_none() && result.notify.is_none() && result.retry.is_none() && result.delay.is_none() && result.timeout.is_none()
+            && result.async_limit.is_none() && result.poll.is_none() {
+            return None;
+        }
+        return Some(result);
+    }
+}
+
+// environment mappings (at play, role, and task level) are templated into sorted name/value pairs.  Values are
+// quoted when they are put on the command line, so they do not need to be safe for the shell.
+
+pub fn template_environment(handle: &TaskHandle, request: &Arc<TaskRequest>, tm: TemplateMode, input: &Option<serde_yaml::Mapping>) 
+    -> Result<Vec<(String,String)>, Arc<TaskResponse>> {
+
+    let mut results : Vec<(String,String)> = Vec::new();
+    if input.is_none() {
+        return Ok(results);
+    }
+    for (k, v) in input.as_ref().unwrap().iter() {
+        let name = match k.as_str() {
+            Some(x) => String::from(x),
+            None => { return Err(handle.response.is_failed(request, &format!("environment variable names must be strings: {:?}", k))); }
+        };
+        let valid = name.chars().enumerate().all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if name.is_empty() || ! valid {
+            return Err(handle.response.is_failed(request, &format!("invalid environment variable name: {}", name)));
+        }
+        let value = match v {
+            serde_yaml::Value::String(x) => x.clone(),
+            serde_yaml::Value::Number(x) => x.to_string(),
+            serde_yaml::Value::Bool(x) => x.to_string(),
+            _ => { return Err(handle.response.is_failed(request, &format!("environment variable {} must be a string", name))); }
+        };
+        let field = format!("environment/{}", name);
+        results.push((name, handle.template.string_unsafe_for_shell(request, tm, &field, &value)?));
+    }
+    results.sort();
+    return Ok(results);
 }
 
 /* this is called from the task_fsm, not above */
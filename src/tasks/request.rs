@@ -39,13 +39,97 @@ pub enum TaskRequestType {
 pub struct TaskRequest {
     pub request_type: TaskRequestType,
     pub changes: Vec<Field>,
-    pub sudo_details: Option<SudoDetails>
+    pub sudo_details: Option<SudoDetails>,
+    // set by a module on the request for its main command, see detachable()
+    pub detachable: bool
 }
 
-#[derive(Debug,PartialEq,Clone)]
+#[derive(PartialEq,Clone)]
 pub struct SudoDetails {
     pub user: Option<String>,
-    pub template: String
+    pub template: String,
+    pub method: BecomeMethod,
+    pub password: Option<String>,
+    // not strictly about sudo, but like the sudo template this wraps every command the task runs
+    pub environment: Vec<(String,String)>,
+    // seconds any one command may run before it is killed, 0 for no limit
+    pub timeout: u64,
+    // seconds a detached (async) command may run and how often it is checked on, a limit of 0 runs commands directly
+    pub async_limit: u64,
+    pub async_poll: u64
+}
+
+// the password must never show up in debug output
+
+impl std::fmt::Debug for SudoDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SudoDetails")
+            .field("user", &self.user)
+            .field("template", &self.template)
+            .field("method", &self.method)
+            .field("password", &self.password.as_ref().map(|_| "********"))
+            .field("environment", &self.environment.iter().map(|(k,_)| k).collect::<Vec<_>>())
+            .field("timeout", &self.timeout)
+            .field("async_limit", &self.async_limit)
+            .field("async_poll", &self.async_poll)
+            .finish()
+    }
+}
+
+// the prompt sudo is told to print before reading a password from stdin.  The connection only sends the password
+// after seeing it, so a command run without needing a password never receives it on its own stdin.
+
+pub const SUDO_PROMPT: &str = "[jet sudo password]:";
+
+// printed on stderr by the default sudo template once sudo has let the command through.  When sudo does not ask (cached
+// credentials or NOPASSWD) this is how the connection knows to close stdin, rather than leaving a command that reads
+// its input waiting on a password that will never be sent.
+
+pub const BECOME_MARKER: &str = "__jet_become_ok__";
+
+// see TaskRequest::redact
+
+const MIN_REDACTED_LENGTH: usize = 4;
+
+// the tool used to run commands as another user. All are expressed as templates (see Template::add_sudo_details)
+// but they differ in how the command is passed and how they ask for a password.
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum BecomeMethod {
+    Sudo,
+    Su,
+    Doas,
+    Run0
+}
+
+impl BecomeMethod {
+
+    pub fn from_string(value: &String) -> Result<Self, String> {
+        return match value.as_str() {
+            "sudo" => Ok(BecomeMethod::Sudo),
+            "su"   => Ok(BecomeMethod::Su),
+            "doas" => Ok(BecomeMethod::Doas),
+            "run0" => Ok(BecomeMethod::Run0),
+            _ => Err(format!("invalid become_method: {} (expecting sudo, su, doas, or run0)", value))
+        };
+    }
+
+    // su only accepts a single command string, so it gets the whole command quoted rather than appended.
+    // when there is a password, sudo reads it from stdin (-S) after printing a known prompt, see SUDO_PROMPT, and the
+    // command runs after BECOME_MARKER.  exec "$@" keeps the arguments exactly as they would have been given to sudo.
+
+    pub fn get_default_template(&self, has_password: bool) -> String {
+        return match self {
+            BecomeMethod::Sudo => match has_password {
+                true  => format!("/usr/bin/sudo -S -p '{}' -u '{{{{jet_sudo_user}}}}' sh -c 'echo {} >&2; exec \"$@\"' jet {{{{jet_command}}}}",
+                    SUDO_PROMPT, BECOME_MARKER),
+                false => String::from("/usr/bin/sudo -u '{{jet_sudo_user}}' {{jet_command}}")
+            },
+            BecomeMethod::Su   => String::from("su '{{jet_sudo_user}}' -c {{jet_command_quoted}}"),
+            BecomeMethod::Doas => String::from("doas -u '{{jet_sudo_user}}' {{jet_command}}"),
+            BecomeMethod::Run0 => String::from("run0 --user='{{jet_sudo_user}}' {{jet_command}}")
+        };
+    }
 }
 
 // most of the various methods in task requests are constructors for different TaskRequest type variants
@@ -58,7 +142,8 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Validate, 
                 changes: Vec::new(),
-                sudo_details: None
+                sudo_details: None,
+                detachable: false
             }
         )
     }
@@ -68,7 +153,8 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Query, 
                 changes: Vec::new(),
-                sudo_details: Some(sudo_details.clone())
+                sudo_details: Some(sudo_details.clone()),
+                detachable: false
             }
         )
     }
@@ -78,7 +164,8 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Create, 
                 changes: Vec::new(),
-                sudo_details: Some(sudo_details.clone())
+                sudo_details: Some(sudo_details.clone()),
+                detachable: false
             }
         )
     }
@@ -88,7 +175,8 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Remove, 
                 changes: Vec::new(),
-                sudo_details: Some(sudo_details.clone())
+                sudo_details: Some(sudo_details.clone()),
+                detachable: false
             }
         )
     }
@@ -98,7 +186,8 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Modify, 
                 changes: changes,
-                sudo_details: Some(sudo_details.clone())
+                sudo_details: Some(sudo_details.clone()),
+                detachable: false
             }
         )
     }
@@ -108,7 +197,8 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Execute, 
                 changes: Vec::new(),
-                sudo_details: Some(sudo_details.clone())
+                sudo_details: Some(sudo_details.clone()),
+                detachable: false
             }
         )
     }
@@ -118,11 +208,85 @@ impl TaskRequest {
             Self { 
                 request_type: TaskRequestType::Passive, 
                 changes: Vec::new(),
-                sudo_details: Some(sudo_details.clone())
+                sudo_details: Some(sudo_details.clone()),
+                detachable: false
             }
         )
     }
 
+    // the password to answer a become prompt with, if one is configured and this request is changing users
+
+    pub fn get_become_password(&self) -> Option<String> {
+        if ! self.is_sudoing() {
+            return None;
+        }
+        return self.sudo_details.as_ref().unwrap().password.clone();
+    }
+
+    pub fn get_environment(&self) -> Vec<(String,String)> {
+        return match self.sudo_details.as_ref() {
+            Some(x) => x.environment.clone(),
+            None => Vec::new()
+        };
+    }
+
+    pub fn get_timeout(&self) -> u64 {
+        return match self.sudo_details.as_ref() {
+            Some(x) => x.timeout,
+            None => 0
+        };
+    }
+
+    // async only applies to the long running command of a task, which the module runs with a detachable copy of the
+    // Execute request.  The temp files, moves and permission changes made around it are quick and run directly, as
+    // do all the commands of modules that do not ask for this.
+
+    pub fn detachable(&self) -> Arc<Self> {
+        assert!(self.request_type == TaskRequestType::Execute, "only execute requests can be detached");
+        return Arc::new(
+            Self {
+                request_type: TaskRequestType::Execute,
+                changes: self.changes.clone(),
+                sudo_details: self.sudo_details.clone(),
+                detachable: true
+            }
+        )
+    }
+
+    pub fn get_async(&self) -> Option<(u64,u64)> {
+        if ! self.detachable {
+            return None;
+        }
+        return match self.sudo_details.as_ref() {
+            Some(x) if x.async_limit > 0 => Some((x.async_limit, std::cmp::max(x.async_poll, 1))),
+            _ => None
+        };
+    }
+
+    // environment values may be secrets (API tokens and the like), so they are masked wherever commands and their
+    // output are shown.  Any of them can be (a DATABASE_URL carries its password) so all are masked, except very short
+    // values such as "1" or "C", as replacing every occurrence of those would mangle the output.
+
+    pub fn redact(&self, text: &String) -> String {
+        let mut result = text.clone();
+        for (_k, v) in self.get_environment().iter() {
+            if v.len() < MIN_REDACTED_LENGTH {
+                continue;
+            }
+            result = result.replace(&v.replace("'", "'\\''"), "********").replace(v, "********");
+        }
+        return result;
+    }
+
+    // sudo reads the password from stdin, the other tools insist on a terminal
+
+    pub fn become_needs_pty(&self) -> bool {
+        return match self.sudo_details.as_ref() {
+            Some(x) => x.method != BecomeMethod::Sudo,
+            None => false
+        };
+    }
+
     pub fn is_sudoing(&self) -> bool {
         let sudo_details = &self.sudo_details;
         if sudo_details.is_none() || sudo_details.as_ref().unwrap().user.is_none() {
@@ -0,0 +1,83 @@
+// Jetporch
+// Copyright (C) 2023 - Michael DeHaan <michael@michaeldehaan.net> + contributors
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// long with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// base64 is needed for WinRM messages and known_hosts lines, which is not enough to pull in another crate for
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [ chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0) ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    return result;
+}
+
+pub fn base64_decode(text: &String) -> Result<Vec<u8>,String> {
+    let mut result : Vec<u8> = Vec::with_capacity(text.len() / 4 * 3);
+    let mut n : u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => { break; },
+            b' ' | b'\r' | b'\n' | b'\t' => { continue; },
+            _ => { return Err(format!("invalid base64: {}", text)); }
+        };
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push(((n >> bits) & 0xFF) as u8);
+        }
+    }
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        for (plain, encoded) in [ ("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("foobar", "Zm9vYmFy") ] {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(&String::from(encoded)), Ok(plain.as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn round_trip_and_whitespace() {
+        let data : Vec<u8> = (0..=255u8).collect();
+        let encoded = base64_encode(&data);
+        assert_eq!(base64_decode(&encoded), Ok(data.clone()));
+        let wrapped = format!("{}\r\n {}\n", &encoded[..40], &encoded[40..]);
+        assert_eq!(base64_decode(&wrapped), Ok(data));
+        assert!(base64_decode(&String::from("Zm9v!")).is_err());
+    }
+}
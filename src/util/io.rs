@@ -20,6 +20,7 @@ use std::fs::ReadDir;
 use std::os::unix::fs::PermissionsExt;
 use std::process;
 use std::io::Read;
+use std::env;
 
 // read a directory as per the normal rust way, but map any errors to strings
 pub fn jet_read_dir(path: &Path) -> Result<ReadDir, String> {
@@ -73,6 +74,17 @@ pub fn directory_as_string(path: &Path) -> String {
     return path.parent().unwrap().to_str().unwrap().to_string();
 }
 
+// paths in inventory variables may start with ~/ for the home directory of the user running jetp
+pub fn expand_home_path(path: &String) -> String {
+    if path.starts_with("~/") {
+        match env::var("HOME") {
+            Ok(home) => { return format!("{}/{}", home, &path[2..]); },
+            Err(_) => {}
+        }
+    }
+    return path.clone();
+}
+
 pub fn quit(s: &String) {
     // quit with a message - don't use this except in main.rs!
     println!("{}", s); 
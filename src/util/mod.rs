@@ -17,3 +17,4 @@
 pub mod io;
 pub mod yaml;
 pub mod terminal;
+pub mod encoding;
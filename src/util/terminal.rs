@@ -47,4 +47,23 @@ pub fn captioned_display(caption: &String, body: &String) {
         println!("    {}", line);
     }
     println!("");
-}
\ No newline at end of file
+}
+// prompts for a secret on standard input.  When attached to a terminal, echo is turned off while typing so the
+// value never appears on screen.
+
+pub fn read_secret(prompt: &String) -> Result<String, String> {
+    println!("{}", prompt);
+    let tty = std::process::Command::new("stty").arg("-echo").stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::null()).status()
+        .map(|x| x.success()).unwrap_or(false);
+    let mut value = String::new();
+    let result = std::io::stdin().read_line(&mut value);
+    if tty {
+        let _ = std::process::Command::new("stty").arg("echo").stdin(std::process::Stdio::inherit()).status();
+        println!("");
+    }
+    return match result {
+        Ok(_) => Ok(String::from(value.trim_end_matches(|c| c == '\n' || c == '\r'))),
+        Err(e) => Err(format!("failure reading input: {}", e))
+    };
+}